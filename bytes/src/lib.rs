@@ -2,8 +2,11 @@
 extern crate serde;
 
 mod morton;
+mod node_id;
 mod stream;
 
+pub use node_id::{Code, NodeId};
+
 use bitstream_io::*;
 use std::fmt::Debug;
 use std::io::{Read, Result as IOResult, Write};
@@ -15,13 +18,13 @@ pub enum LoadType {
     Offline,
 }
 
-pub struct CompressedEdgesBlockSet {
-    arrangement: Matrix,
+pub struct CompressedEdgesBlockSet<N = u32> {
+    arrangement: Matrix<N>,
     blocks: Vec<CompressedEdges>,
 }
 
-impl CompressedEdgesBlockSet {
-    pub fn from_files<P, I>(arrangement: Matrix, load: LoadType, paths: I) -> IOResult<Self>
+impl<N: NodeId> CompressedEdgesBlockSet<N> {
+    pub fn from_files<P, I>(arrangement: Matrix<N>, load: LoadType, paths: I) -> IOResult<Self>
     where
         P: AsRef<Path> + Debug,
         I: IntoIterator<Item = (P, Option<P>)>,
@@ -37,16 +40,16 @@ impl CompressedEdgesBlockSet {
         })
     }
 
-    pub fn total_nodes(&self) -> u32 {
+    pub fn total_nodes(&self) -> N {
         self.arrangement.side_elements
     }
 
     /// Iterates through the blocks responsible for a node
-    pub fn node_blocks(&self, x: u32) -> impl Iterator<Item = u32> {
+    pub fn node_blocks(&self, x: N) -> impl Iterator<Item = u32> {
         self.arrangement.node_blocks(x)
     }
 
-    pub fn for_each<F: FnMut(u32, u32, u32)>(&self, mut action: F) {
+    pub fn for_each<F: FnMut(N, N, u32)>(&self, mut action: F) {
         for block in self.blocks.iter() {
             block.for_each(&mut action);
         }
@@ -113,21 +116,23 @@ impl CompressedEdges {
         }
     }
 
-    pub fn for_each<F: FnMut(u32, u32, u32)>(&self, action: &mut F) {
+    /// Iterates through the edges, decoding the node ids as `N`, which
+    /// must be the type the edges were written with.
+    pub fn for_each<N: NodeId, F: FnMut(N, N, u32)>(&self, action: &mut F) {
         match self {
             Self::InMemory { raw, weights } => {
                 use std::io::Cursor;
                 let cursor = Cursor::new(&raw);
-                let mut reader = stream::DifferenceStreamReader::new(cursor);
+                let mut reader = stream::DifferenceStreamReader::<_, N::Code>::new(cursor);
 
                 if let Some(weights) = weights.as_ref() {
                     let mut weights = weights.iter();
                     loop {
                         let z = reader.read().expect("problem reading form the stream");
-                        if z == 0 {
+                        if z == N::Code::default() {
                             return;
                         } else {
-                            let (u, v) = morton::zorder_to_pair(z);
+                            let (u, v) = N::zorder_to_pair(z);
                             let w = *weights.next().expect("weights exhausted too soon!");
                             action(u, v, w);
                         }
@@ -135,10 +140,10 @@ impl CompressedEdges {
                 } else {
                     loop {
                         let z = reader.read().expect("problem reading form the stream");
-                        if z == 0 {
+                        if z == N::Code::default() {
                             return;
                         } else {
-                            let (u, v) = morton::zorder_to_pair(z);
+                            let (u, v) = N::zorder_to_pair(z);
                             action(u, v, 1);
                         }
                     }
@@ -151,7 +156,7 @@ impl CompressedEdges {
                 use std::fs::File;
                 use std::io::{BufReader, Read};
                 let file_reader = BufReader::new(File::open(raw_path).unwrap());
-                let mut reader = stream::DifferenceStreamReader::new(file_reader);
+                let mut reader = stream::DifferenceStreamReader::<_, N::Code>::new(file_reader);
 
                 if let Some(weights_path) = weights_path.as_ref() {
                     let mut weights = BitReader::<_, BE>::new(BufReader::new(
@@ -160,10 +165,10 @@ impl CompressedEdges {
 
                     loop {
                         let z = reader.read().expect("problem reading form the stream");
-                        if z == 0 {
+                        if z == N::Code::default() {
                             return;
                         } else {
-                            let (u, v) = morton::zorder_to_pair(z);
+                            let (u, v) = N::zorder_to_pair(z);
                             let w = weights.read(32).expect("weights exhausted too soon!");
                             action(u, v, w);
                         }
@@ -171,10 +176,10 @@ impl CompressedEdges {
                 } else {
                     loop {
                         let z = reader.read().expect("problem reading form the stream");
-                        if z == 0 {
+                        if z == N::Code::default() {
                             return;
                         } else {
-                            let (u, v) = morton::zorder_to_pair(z);
+                            let (u, v) = N::zorder_to_pair(z);
                             action(u, v, 1);
                         }
                     }
@@ -203,15 +208,17 @@ impl CompressedEdges {
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
-pub struct Matrix {
+pub struct Matrix<N = u32> {
     blocks_per_side: u32,
-    elems_per_block: u32,
-    side_elements: u32,
+    elems_per_block: N,
+    side_elements: N,
 }
 
-impl Matrix {
-    pub fn new(blocks_per_side: u32, side_elements: u32) -> Self {
-        let elems_per_block = (side_elements as f64 / blocks_per_side as f64).ceil() as u32;
+impl<N: NodeId> Matrix<N> {
+    pub fn new(blocks_per_side: u32, side_elements: N) -> Self {
+        let blocks_per_side_64 = blocks_per_side as u64;
+        let elems_per_block =
+            N::from_u64((side_elements.to_u64() + blocks_per_side_64 - 1) / blocks_per_side_64);
         Self {
             blocks_per_side,
             elems_per_block,
@@ -233,13 +240,13 @@ impl Matrix {
             let mut tokens = line.split("=");
             let key = tokens.next().expect("missing key");
             if key.starts_with("elems_per_block") {
-                elems_per_block.replace(
+                elems_per_block.replace(N::from_u64(
                     tokens
                         .next()
                         .expect("problem getting value")
-                        .parse::<u32>()
+                        .parse::<u64>()
                         .expect("problem parsing value"),
-                );
+                ));
             }
             if key.starts_with("blocks_per_side") {
                 blocks_per_side.replace(
@@ -251,13 +258,13 @@ impl Matrix {
                 );
             }
             if key.starts_with("side_elements") {
-                side_elements.replace(
+                side_elements.replace(N::from_u64(
                     tokens
                         .next()
                         .expect("problem getting value")
-                        .parse::<u32>()
+                        .parse::<u64>()
                         .expect("problem parsing value"),
-                );
+                ));
             }
         }
 
@@ -283,8 +290,8 @@ impl Matrix {
     }
 
     /// Gets the processors that might have edges incident to a node
-    pub fn node_blocks(&self, node: u32) -> impl Iterator<Item = u32> {
-        let block_idx = node / self.elems_per_block;
+    pub fn node_blocks(&self, node: N) -> impl Iterator<Item = u32> {
+        let block_idx = (node.to_u64() / self.elems_per_block.to_u64()) as u32;
         let n_blocks = self.blocks_per_side;
         let mut row_idx = 0;
         let mut col_idx = 0;
@@ -303,13 +310,15 @@ impl Matrix {
         })
     }
 
-    pub fn row_major_block(&self, (x, y): (u32, u32)) -> u32 {
+    pub fn row_major_block(&self, (x, y): (N, N)) -> u32 {
+        let (x, y) = (x.to_u64(), y.to_u64());
+        let elems_per_block = self.elems_per_block.to_u64();
         // The index within a block
-        let inner_x = x % self.elems_per_block;
-        let inner_y = y % self.elems_per_block;
+        let inner_x = x % elems_per_block;
+        let inner_y = y % elems_per_block;
         // The index of the (square) block
-        let block_x = x / self.elems_per_block;
-        let block_y = y / self.elems_per_block;
+        let block_x = (x / elems_per_block) as u32;
+        let block_y = (y / elems_per_block) as u32;
 
         if inner_x < inner_y {
             // Upper triangle
@@ -321,26 +330,26 @@ impl Matrix {
     }
 }
 
-pub struct CompressedPairsWriter {
+pub struct CompressedPairsWriter<N: NodeId = u32> {
     output_path: PathBuf,
-    encoded: Vec<u64>,
+    encoded: Vec<N::Code>,
     node_blocks: u32,
-    max_id: u32,
+    max_id: N,
 }
 
-impl CompressedPairsWriter {
+impl<N: NodeId> CompressedPairsWriter<N> {
     pub fn to_file<P: AsRef<Path>>(path: P, node_blocks: u32) -> Self {
         Self {
             output_path: path.as_ref().to_path_buf(),
             encoded: Vec::new(),
             node_blocks,
-            max_id: 0,
+            max_id: N::default(),
         }
     }
 
-    pub fn write(&mut self, pair: (u32, u32)) {
+    pub fn write(&mut self, pair: (N, N)) {
         self.max_id = std::cmp::max(self.max_id, std::cmp::max(pair.0, pair.1));
-        self.encoded.push(morton::pair_to_zorder(pair));
+        self.encoded.push(N::pair_to_zorder(pair));
     }
 
     fn flush(&mut self) -> IOResult<()> {
@@ -366,14 +375,14 @@ impl CompressedPairsWriter {
             let p = get_path(part_id);
             // println!("opening {:?}", p);
             let writer = BufWriter::new(File::create(p)?);
-            let writer = stream::DifferenceStreamWriter::new(writer);
+            let writer = stream::DifferenceStreamWriter::<_, N::Code>::new(writer);
             writers.push(writer);
         }
 
-        let matrix = Matrix::new(self.node_blocks, self.max_id + 1);
+        let matrix = Matrix::new(self.node_blocks, N::from_u64(self.max_id.to_u64() + 1));
 
         for &x in self.encoded.iter() {
-            let writer = &mut writers[matrix.row_major_block(morton::zorder_to_pair(x)) as usize];
+            let writer = &mut writers[matrix.row_major_block(N::zorder_to_pair(x)) as usize];
             if writer.is_new_elem(x) {
                 // Remove duplicate edges
                 writer.write(x)?;
@@ -389,33 +398,33 @@ impl CompressedPairsWriter {
     }
 }
 
-impl Drop for CompressedPairsWriter {
+impl<N: NodeId> Drop for CompressedPairsWriter<N> {
     fn drop(&mut self) {
         self.flush()
             .expect("problems flushing the compressed pairs writer");
     }
 }
 
-pub struct CompressedTripletsWriter {
+pub struct CompressedTripletsWriter<N: NodeId = u32> {
     output_path: PathBuf,
-    encoded: Vec<(u64, u32)>,
+    encoded: Vec<(N::Code, u32)>,
     node_blocks: u32,
-    max_id: u32,
+    max_id: N,
 }
 
-impl CompressedTripletsWriter {
+impl<N: NodeId> CompressedTripletsWriter<N> {
     pub fn to_file<P: AsRef<Path>>(path: P, node_blocks: u32) -> Self {
         Self {
             output_path: path.as_ref().to_path_buf(),
             encoded: Vec::new(),
             node_blocks,
-            max_id: 0,
+            max_id: N::default(),
         }
     }
 
-    pub fn write(&mut self, (u, v, w): (u32, u32, u32)) {
+    pub fn write(&mut self, (u, v, w): (N, N, u32)) {
         self.max_id = std::cmp::max(self.max_id, std::cmp::max(u, v));
-        self.encoded.push((morton::pair_to_zorder((u, v)), w));
+        self.encoded.push((N::pair_to_zorder((u, v)), w));
     }
 
     fn flush(&mut self) -> IOResult<()> {
@@ -441,7 +450,7 @@ impl CompressedTripletsWriter {
             path
         };
 
-        let matrix = Matrix::new(self.node_blocks, self.max_id + 1);
+        let matrix = Matrix::new(self.node_blocks, N::from_u64(self.max_id.to_u64() + 1));
 
         let mut writers = Vec::new();
         for part_id in 0..(self.node_blocks * self.node_blocks) {
@@ -450,12 +459,12 @@ impl CompressedTripletsWriter {
             let writer = BufWriter::new(File::create(p)?);
             let weights_writer =
                 BitWriter::<_, BE>::new(BufWriter::new(File::create(get_path_weights(part_id))?));
-            let writer = stream::DifferenceStreamWriter::new(writer);
+            let writer = stream::DifferenceStreamWriter::<_, N::Code>::new(writer);
             writers.push((writer, weights_writer));
         }
         for &(x, w) in self.encoded.iter() {
             let (writer, weights_writer) =
-                &mut writers[matrix.row_major_block(morton::zorder_to_pair(x)) as usize];
+                &mut writers[matrix.row_major_block(N::zorder_to_pair(x)) as usize];
             if writer.is_new_elem(x) {
                 // Remove duplicate edges
                 writer.write(x)?;
@@ -473,7 +482,7 @@ impl CompressedTripletsWriter {
     }
 }
 
-impl Drop for CompressedTripletsWriter {
+impl<N: NodeId> Drop for CompressedTripletsWriter<N> {
     fn drop(&mut self) {
         self.flush()
             .expect("problems flushing the compressed triplets writer");
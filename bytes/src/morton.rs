@@ -1,50 +1,57 @@
-// interleave the bits
-#[inline]
-pub fn pair_to_zorder((mut x, mut y): (u32, u32)) -> u64 {
-    let mut z = 0;
-    let msb_mask = 1_u32 << 31;
-    for _ in 0..32 {
-        if x & msb_mask == 0 {
-            z = z << 1;
-        } else {
-            z = (z << 1) | 1;
+macro_rules! morton {
+    ($to_zorder:ident, $to_pair:ident, $id:ty, $code:ty, $bits:expr) => {
+        // interleave the bits
+        #[inline]
+        pub fn $to_zorder((mut x, mut y): ($id, $id)) -> $code {
+            let mut z = 0;
+            let msb_mask = 1 << ($bits - 1);
+            for _ in 0..$bits {
+                if x & msb_mask == 0 {
+                    z = z << 1;
+                } else {
+                    z = (z << 1) | 1;
+                }
+                if y & msb_mask == 0 {
+                    z = z << 1;
+                } else {
+                    z = (z << 1) | 1;
+                }
+                x = x << 1;
+                y = y << 1;
+            }
+            z
         }
-        if y & msb_mask == 0 {
-            z = z << 1;
-        } else {
-            z = (z << 1) | 1;
-        }
-        x = x << 1;
-        y = y << 1;
-    }
-    z
-}
 
-#[inline]
-pub fn zorder_to_pair(mut z: u64) -> (u32, u32) {
-    let mut x = 0;
-    let mut y = 0;
+        #[inline]
+        pub fn $to_pair(mut z: $code) -> ($id, $id) {
+            let mut x = 0;
+            let mut y = 0;
 
-    let x_mask = 1_u64 << 63;
-    let y_mask = 1_u64 << 62;
+            let x_mask: $code = 1 << (2 * $bits - 1);
+            let y_mask: $code = 1 << (2 * $bits - 2);
 
-    for _i in 0..32 {
-        if z & x_mask == 0 {
-            x = x << 1;
-        } else {
-            x = (x << 1) | 1;
-        }
-        if z & y_mask == 0 {
-            y = y << 1;
-        } else {
-            y = (y << 1) | 1;
-        }
-        z = z << 2;
-    }
+            for _i in 0..$bits {
+                if z & x_mask == 0 {
+                    x = x << 1;
+                } else {
+                    x = (x << 1) | 1;
+                }
+                if z & y_mask == 0 {
+                    y = y << 1;
+                } else {
+                    y = (y << 1) | 1;
+                }
+                z = z << 2;
+            }
 
-    (x, y)
+            (x, y)
+        }
+    };
 }
 
+morton!(pair_to_zorder, zorder_to_pair, u32, u64, 32);
+morton!(pair_to_zorder_128, zorder_to_pair_128, u64, u128, 64);
+
 #[test]
 fn test_zorder() {
     for x in 0..100 {
@@ -53,3 +60,19 @@ fn test_zorder() {
         }
     }
 }
+
+#[test]
+fn test_zorder_128() {
+    let big = 1u64 << 40;
+    for x in 0..100 {
+        for y in 0..100 {
+            assert_eq!((x, y), zorder_to_pair_128(pair_to_zorder_128((x, y))));
+            assert_eq!(
+                (big + x, y),
+                zorder_to_pair_128(pair_to_zorder_128((big + x, y)))
+            );
+        }
+    }
+    // Consistent with the ordering of 32 bits codes
+    assert_eq!(pair_to_zorder((3, 5)) as u128, pair_to_zorder_128((3, 5)));
+}
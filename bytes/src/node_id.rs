@@ -0,0 +1,136 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+/// The identifier of a node. Pairs of identifiers are stored as Morton codes
+/// twice as wide, so `u32` identifiers are the compact choice for all the
+/// graphs with less than 2^32 nodes, and `u64` ones are there for the others.
+pub trait NodeId:
+    Copy
+    + Ord
+    + Hash
+    + Default
+    + Debug
+    + Display
+    + FromStr
+    + Serialize
+    + DeserializeOwned
+    + Send
+    + Sync
+    + 'static
+{
+    type Code: Code;
+
+    /// Panics if the value does not fit
+    fn from_u64(x: u64) -> Self;
+
+    fn to_u64(self) -> u64;
+
+    fn pair_to_zorder(pair: (Self, Self)) -> Self::Code;
+
+    fn zorder_to_pair(z: Self::Code) -> (Self, Self);
+}
+
+/// An unsigned integer that can be written to a difference stream
+pub trait Code: Copy + Ord + Default + Debug + Add<Output = Self> + Sub<Output = Self> {
+    const BITS: u32;
+
+    /// The number of bits needed to represent this value
+    fn significant_bits(self) -> u32;
+
+    /// The high and the low 64 bits of this value
+    fn split(self) -> (u64, u64);
+
+    fn join(high: u64, low: u64) -> Self;
+}
+
+impl NodeId for u32 {
+    type Code = u64;
+
+    fn from_u64(x: u64) -> Self {
+        assert!(
+            x <= std::u32::MAX as u64,
+            "node id {} does not fit in 32 bits",
+            x
+        );
+        x as u32
+    }
+
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+
+    #[inline]
+    fn pair_to_zorder(pair: (u32, u32)) -> u64 {
+        crate::morton::pair_to_zorder(pair)
+    }
+
+    #[inline]
+    fn zorder_to_pair(z: u64) -> (u32, u32) {
+        crate::morton::zorder_to_pair(z)
+    }
+}
+
+impl NodeId for u64 {
+    type Code = u128;
+
+    fn from_u64(x: u64) -> Self {
+        x
+    }
+
+    fn to_u64(self) -> u64 {
+        self
+    }
+
+    #[inline]
+    fn pair_to_zorder(pair: (u64, u64)) -> u128 {
+        crate::morton::pair_to_zorder_128(pair)
+    }
+
+    #[inline]
+    fn zorder_to_pair(z: u128) -> (u64, u64) {
+        crate::morton::zorder_to_pair_128(z)
+    }
+}
+
+impl Code for u64 {
+    const BITS: u32 = 64;
+
+    #[inline]
+    fn significant_bits(self) -> u32 {
+        64 - self.leading_zeros()
+    }
+
+    #[inline]
+    fn split(self) -> (u64, u64) {
+        (0, self)
+    }
+
+    #[inline]
+    fn join(high: u64, low: u64) -> Self {
+        assert!(high == 0);
+        low
+    }
+}
+
+impl Code for u128 {
+    const BITS: u32 = 128;
+
+    #[inline]
+    fn significant_bits(self) -> u32 {
+        128 - self.leading_zeros()
+    }
+
+    #[inline]
+    fn split(self) -> (u64, u64) {
+        ((self >> 64) as u64, self as u64)
+    }
+
+    #[inline]
+    fn join(high: u64, low: u64) -> Self {
+        ((high as u128) << 64) | low as u128
+    }
+}
@@ -1,28 +1,30 @@
+use crate::node_id::Code;
 use bitstream_io::{BitReader, BitWriter};
 use std::io::{Read, Result as IOResult, Write};
+use std::marker::PhantomData;
 
-pub struct DifferenceStreamWriter<W: Write> {
-    inner: GammaStreamWriter<W>,
-    last: u64,
+pub struct DifferenceStreamWriter<W: Write, C: Code = u64> {
+    inner: GammaStreamWriter<W, C>,
+    last: C,
     // histogram: std::collections::BTreeMap<u64, u64>,
 }
 
-impl<W: Write> DifferenceStreamWriter<W> {
+impl<W: Write, C: Code> DifferenceStreamWriter<W, C> {
     pub fn new(inner: W) -> Self {
         Self {
             inner: GammaStreamWriter::new(inner),
-            last: 0,
+            last: C::default(),
             // histogram: std::collections::BTreeMap::new(),
         }
     }
 
     #[inline]
-    pub fn is_new_elem(&self, elem: u64) -> bool {
+    pub fn is_new_elem(&self, elem: C) -> bool {
         self.last != elem
     }
 
     #[inline]
-    pub fn write(&mut self, elem: u64) -> IOResult<()> {
+    pub fn write(&mut self, elem: C) -> IOResult<()> {
         assert!(self.last < elem);
         let diff = elem - self.last;
         // self.histogram
@@ -39,24 +41,24 @@ impl<W: Write> DifferenceStreamWriter<W> {
     }
 }
 
-pub struct DifferenceStreamReader<R: Read> {
-    inner: GammaStreamReader<R>,
-    last: u64,
+pub struct DifferenceStreamReader<R: Read, C: Code = u64> {
+    inner: GammaStreamReader<R, C>,
+    last: C,
 }
 
-impl<R: Read> DifferenceStreamReader<R> {
+impl<R: Read, C: Code> DifferenceStreamReader<R, C> {
     pub fn new(inner: R) -> Self {
         Self {
             inner: GammaStreamReader::new(inner),
-            last: 0,
+            last: C::default(),
         }
     }
 
     #[inline]
-    pub fn read(&mut self) -> IOResult<u64> {
+    pub fn read(&mut self) -> IOResult<C> {
         let diff = self.inner.read()?;
-        if diff == 0 {
-            return Ok(0);
+        if diff == C::default() {
+            return Ok(diff);
         }
         let elem = self.last + diff;
         self.last = elem;
@@ -64,29 +66,40 @@ impl<R: Read> DifferenceStreamReader<R> {
     }
 }
 
-pub struct GammaStreamWriter<W: Write> {
+/// Writes Elias gamma codes of values of type `C`. Values wider than 64 bits
+/// are written in two parts, so that the codes of small values are the same
+/// whatever the type.
+pub struct GammaStreamWriter<W: Write, C: Code = u64> {
     inner: BitWriter<W, bitstream_io::BE>,
+    _code: PhantomData<C>,
 }
 
-impl<W: Write> GammaStreamWriter<W> {
+impl<W: Write, C: Code> GammaStreamWriter<W, C> {
     pub fn new(inner: W) -> Self {
         Self {
             inner: BitWriter::new(inner),
+            _code: PhantomData,
         }
     }
 
     #[inline]
-    pub fn write(&mut self, elem: u64) -> Result<(), std::io::Error> {
-        let N = 64 - elem.leading_zeros(); // the number of bits to represent `elem`
+    pub fn write(&mut self, elem: C) -> Result<(), std::io::Error> {
+        let N = elem.significant_bits(); // the number of bits to represent `elem`
         for _ in 0..(N - 1) {
             self.inner.write_bit(false)?;
         }
-        self.inner.write(N, elem)
+        let (high, low) = elem.split();
+        if N > 64 {
+            self.inner.write(N - 64, high)?;
+            self.inner.write(64, low)
+        } else {
+            self.inner.write(N, low)
+        }
     }
 
     pub fn close(mut self) -> Result<(), std::io::Error> {
-        // Write 65 zeros to signal the end of the stream
-        for _ in 0..=65 {
+        // Write C::BITS + 2 zeros to signal the end of the stream
+        for _ in 0..=(C::BITS + 1) {
             self.inner.write_bit(false)?;
         }
         self.inner.byte_align()?;
@@ -94,30 +107,38 @@ impl<W: Write> GammaStreamWriter<W> {
     }
 }
 
-pub struct GammaStreamReader<R: Read> {
+pub struct GammaStreamReader<R: Read, C: Code = u64> {
     inner: BitReader<R, bitstream_io::BE>,
+    _code: PhantomData<C>,
 }
 
-impl<R: Read> GammaStreamReader<R> {
+impl<R: Read, C: Code> GammaStreamReader<R, C> {
     pub fn new(inner: R) -> Self {
         Self {
             inner: BitReader::new(inner),
+            _code: PhantomData,
         }
     }
 
     #[inline]
-    pub fn read(&mut self) -> Result<u64, std::io::Error> {
+    pub fn read(&mut self) -> Result<C, std::io::Error> {
         let mut N = 0;
         while !self.inner.read_bit()? {
             N += 1;
-            if N > 64 {
+            if N > C::BITS {
                 // 0 is a value out of the domain of possible values,
                 // hence we use it to signal the end of the stream.
-                return Ok(0);
+                return Ok(C::default());
             }
         }
-        let elem: u64 = self.inner.read(N)?;
-        Ok(elem | (1 << N))
+        if N >= 64 {
+            let high: u64 = self.inner.read(N - 64)?;
+            let low: u64 = self.inner.read(64)?;
+            Ok(C::join(high | (1 << (N - 64)), low))
+        } else {
+            let low: u64 = self.inner.read(N)?;
+            Ok(C::join(0, low | (1 << N)))
+        }
     }
 }
 
@@ -127,7 +148,7 @@ mod test {
 
     #[test]
     fn test_encode_decode() {
-        for x in 1..100 {
+        for x in 1..100u64 {
             let mut buf = Vec::new();
             // let mut writer = GammaStreamWriter::new(File::create(&file).unwrap());
             let mut writer = GammaStreamWriter::new(&mut buf);
@@ -135,13 +156,38 @@ mod test {
             writer.close().unwrap();
             let cursor = std::io::Cursor::new(buf);
             // let mut reader = GammaStreamReader::new(File::open(&file).unwrap());
-            let mut reader = GammaStreamReader::new(cursor);
+            let mut reader = GammaStreamReader::<_, u64>::new(cursor);
             let res = reader.read();
             assert!(res.is_ok(), "error was: {:?}", res.unwrap_err());
             assert_eq!(res.unwrap(), x);
         }
     }
 
+    #[test]
+    fn test_wide_encode_decode() {
+        let values: Vec<u128> = vec![1, 2, 1 << 63, 1 << 64, (1 << 64) + 5, std::u128::MAX];
+
+        let mut buf = Vec::new();
+        let mut writer = GammaStreamWriter::new(&mut buf);
+        for x in values.iter() {
+            assert!(writer.write(*x).is_ok());
+        }
+        writer.close().unwrap();
+
+        let cursor = std::io::Cursor::new(buf);
+        let mut reader = GammaStreamReader::<_, u128>::new(cursor);
+        let mut actual = Vec::new();
+        loop {
+            let x = reader.read().unwrap();
+            if x == 0 {
+                // end of the stream
+                break;
+            }
+            actual.push(x);
+        }
+        assert_eq!(actual, values);
+    }
+
     #[test]
     fn test_many_encode_decode() {
         use rand::distributions::Distribution;
@@ -159,7 +205,7 @@ mod test {
         writer.close().unwrap();
 
         let cursor = std::io::Cursor::new(buf);
-        let mut reader = GammaStreamReader::new(cursor);
+        let mut reader = GammaStreamReader::<_, u64>::new(cursor);
         for &expected in values.iter() {
             let res = reader.read();
             assert!(res.is_ok(), "error is {:?}", res);
@@ -184,7 +230,7 @@ mod test {
         writer.close().unwrap();
 
         let cursor = std::io::Cursor::new(buf);
-        let mut reader = GammaStreamReader::new(cursor);
+        let mut reader = GammaStreamReader::<_, u64>::new(cursor);
         let mut actual = Vec::new();
         loop {
             let x = reader.read().unwrap();
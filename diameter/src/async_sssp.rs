@@ -0,0 +1,126 @@
+use crate::bounds::DiameterBounds;
+use crate::distance::Distance;
+use crate::distributed_adjacencies::*;
+use crate::node::Node;
+use crate::operators::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::*;
+use timely::dataflow::Scope;
+use timely::order::Product;
+
+/// Single source shortest paths by chaotic relaxation.
+///
+/// Unlike `delta_stepping`, there are no buckets and no rounds: each
+/// improved distance is propagated as soon as it is received, and the
+/// iteration counter is only there to let timely close the loop. The
+/// computation is over when progress tracking tells that no proposal
+/// is in flight anymore.
+pub fn async_sssp<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    n: Node,
+    seed: u64,
+) -> (Option<DiameterBounds<D>>, std::time::Duration) {
+    let root = random_root(&adjacencies, worker, n, seed);
+    info!("Root is {}", root);
+    let (eccentricity, _distances, elapsed) = async_sssp_from::<D, _>(&adjacencies, worker, root);
+
+    (eccentricity.map(DiameterBounds::from_eccentricity), elapsed)
+}
+
+/// Runs the chaotic relaxation from the given root, returning its
+/// eccentricity on all the workers, along with the distances of the nodes
+/// of this worker. Nodes out of the component of the root have none.
+pub fn async_sssp_from<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    root: Node,
+) -> (Option<D>, LocalStates<D>, std::time::Duration) {
+    let distances = Rc::new(RefCell::new(adjacencies.local_states::<D>()));
+    let (eccentricity_box, probe) = worker.dataflow::<(), _, _>(|scope| {
+        let collector_distances = Rc::clone(&distances);
+        let distances = Rc::clone(&distances);
+
+        let roots = if scope.index() == 0 {
+            vec![(root, D::zero())]
+        } else {
+            vec![]
+        }
+        .to_stream(scope);
+
+        let done = scope.iterative::<u32, _, _>(|subscope| {
+            let (handle, cycle) = subscope.feedback(Product::new(Default::default(), 1));
+
+            let proposals = adjacencies.relax(&roots.enter(subscope).concat(&cycle), distances);
+            proposals.connect_loop(handle);
+
+            // Nothing leaves the loop: we are only interested in its frontier
+            proposals.filter(|_| false).leave()
+        });
+
+        roots
+            .concat(&done)
+            .unary_frontier(Pipeline, "collect distances", move |cap, _| {
+                let mut cap = Some(cap);
+                move |input, output| {
+                    input.for_each(|_t, _data| {});
+                    if input.frontier().frontier().is_empty() {
+                        if let Some(cap) = cap.take() {
+                            let distances = collector_distances.borrow();
+                            info!("Reached {} nodes", distances.iter().count());
+                            if let Some(max) = distances.iter().map(|(_id, d)| *d).max() {
+                                output.session(&cap).give(max);
+                            }
+                        }
+                    }
+                }
+            })
+            .exchange(|_| 0)
+            .accumulate(D::zero(), |max, data| {
+                *max = std::cmp::max(*data.iter().max().expect("empty collection"), *max)
+            })
+            .broadcast()
+            .collect_single()
+    });
+
+    let elapsed = run_to_completion(worker, probe);
+
+    let eccentricity = eccentricity_box.borrow_mut().take();
+    let distances = distances.replace(adjacencies.local_states::<D>());
+
+    (eccentricity, distances, elapsed)
+}
+
+#[test]
+fn test_async_sssp_matches_dijkstra() {
+    use crate::sequential::{dijkstra, init_neighbourhoods};
+
+    // A weighted graph whose lightest paths take detours, and the isolated node 7
+    let edges: Vec<((Node, Node), u32)> = vec![
+        ((0, 1), 7),
+        ((0, 2), 1),
+        ((2, 1), 2),
+        ((1, 3), 1),
+        ((2, 4), 10),
+        ((3, 4), 3),
+        ((4, 5), 1),
+        ((3, 6), 8),
+    ];
+    let n = 8;
+    let neighbourhoods = init_neighbourhoods(edges.clone(), n);
+
+    timely::execute_directly(move |worker| {
+        let adjacencies = DistributedAdjacencies::from_edge_list(0, 1, n, &edges);
+        for root in 0..n {
+            let expected = dijkstra(&neighbourhoods, root).0;
+            let (eccentricity, distances, _) =
+                async_sssp_from::<u32, _>(&adjacencies, worker, root);
+            assert_eq!(eccentricity, expected.iter().filter_map(|&d| d).max());
+            for id in 0..n {
+                assert_eq!(distances.get(id).cloned(), expected[id as usize]);
+            }
+        }
+    });
+}
@@ -1,14 +1,21 @@
+use crate::bounds::DiameterBounds;
 use crate::distributed_adjacencies::*;
 use crate::distributed_graph::*;
+use crate::eccentricities::*;
+use crate::node::Node;
 use crate::operators::*;
-use rand::distributions::Uniform;
-use rand::prelude::*;
-use rand_xoshiro::Xoshiro256StarStar;
+use crate::paths::*;
+use crate::vertex_program::*;
+use std::cell::RefCell;
+use std::rc::Rc;
 use timely::dataflow::Scope;
+use timely::progress::Timestamp;
 
 #[derive(Debug, Clone, Abomonation)]
 struct State {
     distance: Option<u32>,
+    /// The neighbour that reached the node first, none for the root
+    parent: Option<Node>,
     active: bool,
 }
 
@@ -16,6 +23,7 @@ impl Default for State {
     fn default() -> Self {
         Self {
             distance: None,
+            parent: None,
             active: true,
         }
     }
@@ -27,26 +35,144 @@ impl State {
     }
 }
 
+#[derive(Clone)]
+struct BfsProgram;
+
+impl<T: Timestamp> VertexProgram<T> for BfsProgram {
+    type State = State;
+    /// The distance through the sender, and the sender
+    type Message = (u32, Node);
+
+    // Filter nodes that have something to say
+    fn should_send(&self, _time: &T, state: &State) -> bool {
+        state.should_send()
+    }
+
+    fn message(&self, _time: &T, id: Node, state: &State, _weight: u32) -> Option<(u32, Node)> {
+        Some((1 + state.distance.expect("missing distance"), id))
+    }
+
+    fn combine(m1: &(u32, Node), m2: &(u32, Node)) -> (u32, Node) {
+        std::cmp::min(*m1, *m2)
+    }
+
+    fn apply(&self, _time: &T, state: &State, message: Option<&(u32, Node)>) -> State {
+        match message {
+            // Update nodes that got messages
+            Some(&(distance, parent)) => {
+                if state.active {
+                    let was_uncovered = state.distance.is_none();
+                    if was_uncovered {
+                        State {
+                            active: true,
+                            distance: Some(distance),
+                            parent: Some(parent),
+                        }
+                    } else {
+                        State {
+                            active: false,
+                            ..state.clone()
+                        }
+                    }
+                } else {
+                    state.clone()
+                }
+            }
+            // Deactivate already reached nodes that got no messages
+            None => {
+                if state.active && state.distance.is_some() {
+                    State {
+                        active: false,
+                        ..state.clone()
+                    }
+                } else {
+                    state.clone()
+                }
+            }
+        }
+    }
+
+    fn is_active(&self, _time: &T, state: &State) -> bool {
+        state.active
+    }
+}
+
 pub fn bfs<A: timely::communication::Allocate>(
     adjacencies: DistributedAdjacencies,
     worker: &mut timely::worker::Worker<A>,
-    n: u32,
+    n: Node,
+    max_weight: u32,
     seed: u64,
-) -> (Option<u32>, std::time::Duration) {
+    eccentricities: &Eccentricities,
+    paths: &Paths,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
+    let root = random_root(&adjacencies, worker, n, seed);
+    info!("Root is {}", root);
+    let (farthest, tree, elapsed) = bfs_from(
+        &adjacencies,
+        worker,
+        root,
+        eccentricities.is_enabled() || paths.is_enabled(),
+    );
+
+    if let Some((hops, _far)) = farthest.filter(|_| eccentricities.is_enabled()) {
+        // As for the diameter, hops are lower bounds and the upper
+        // bounds must account for the heaviest edge
+        let mut node_bounds = EccentricityBounds::new();
+        for (&id, &(distance, _parent)) in tree.iter() {
+            let lower = std::cmp::max(distance, hops - distance) as u64;
+            let upper = (distance + hops) as u64 * max_weight as u64;
+            node_bounds.refine(id, lower, upper);
+        }
+        eccentricities.save(&node_bounds);
+    }
+    if let Some((_hops, far)) = farthest.filter(|_| paths.is_enabled()) {
+        // The distances along the path are in hops
+        let path = trace_path(worker, tree, far);
+        if worker.index() == 0 {
+            paths.save(&path);
+        }
+    }
+
+    // The eccentricity is in hops: it is a lower bound to the weighted
+    // diameter too, but the upper bound must account for the heaviest edge
+    let bounds = farthest.map(|(hops, far)| {
+        let bounds = DiameterBounds::from_sweep(hops as u64, root, far);
+        DiameterBounds {
+            upper: bounds.upper.map(|upper| upper * max_weight as u64),
+            ..bounds
+        }
+    });
+
+    (bounds, elapsed)
+}
+
+/// Runs a BFS from the given root, returning its eccentricity in hops
+/// along with the farthest node from it, on all the workers. If asked to,
+/// also returns the tree of the BFS, with the distances in hops, restricted
+/// to the nodes of this worker.
+pub fn bfs_from<A: timely::communication::Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    root: Node,
+    keep_tree: bool,
+) -> (
+    Option<(u32, Node)>,
+    ShortestPathsTree<u32>,
+    std::time::Duration,
+) {
     use timely::dataflow::operators::*;
-    use timely::order::Product;
 
-    let (diameter_box, probe) = worker.dataflow::<(), _, _>(|scope| {
+    let local_tree = Rc::new(RefCell::new(ShortestPathsTree::new()));
+    let local_tree_ref = Rc::clone(&local_tree);
+    let (farthest_box, probe) = worker.dataflow::<(), _, _>(|scope| {
         let nodes = if scope.index() == 0 {
-            let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
-            let dist = Uniform::new(0u32, n + 1);
-            let root: u32 = dist.sample(&mut rng);
-            info!("Root is {}", root);
             vec![(
                 root,
                 State {
                     active: true,
                     distance: Some(0),
+                    parent: None,
                 },
             )]
         } else {
@@ -55,73 +181,32 @@ pub fn bfs<A: timely::communication::Allocate>(
         .to_stream(scope)
         .exchange(|p| p.0 as u64);
 
-        let distances = nodes.scope().iterative::<u32, _, _>(move |inner_scope| {
-            let (handle, cycle) = inner_scope.feedback(Product::new(Default::default(), 1));
-            let nodes = nodes.enter(inner_scope);
-            let (inactive_nodes, active_nodes) = adjacencies
-                .send(
-                    &nodes.concat(&cycle),
-                    true,
-                    // Filter nodes that have something to say
-                    |_, state| state.should_send(),
-                    // Create messages
-                    |_time, state, _weight| Some(1 + state.distance.expect("missing distance")),
-                    // Aggregate messages
-                    |d1, d2| std::cmp::min(*d1, *d2),
-                    // Update nodes that got messages
-                    |state, message| {
-                        if state.active {
-                            let was_uncovered = state.distance.is_none();
-                            if was_uncovered {
-                                State {
-                                    active: true,
-                                    distance: Some(*message),
-                                }
-                            } else {
-                                State {
-                                    active: false,
-                                    ..state.clone()
-                                }
-                            }
-                        } else {
-                            state.clone()
-                        }
-                    },
-                    // Deactivate already reached nodes that got no messages
-                    |state| {
-                        if state.active && state.distance.is_some() {
-                            State {
-                                active: false,
-                                ..state.clone()
-                            }
-                        } else {
-                            state.clone()
-                        }
-                    },
-                )
-                .branch_all("active", |_t, pair| pair.1.active, 0);
-
-            active_nodes.connect_loop(handle);
-
-            inactive_nodes.leave()
-        });
+        let distances = run_until_quiescent(adjacencies, &nodes, BfsProgram);
 
+        // Nodes out of the component of the root are never reached
         distances
-            .map(|(_, state)| state.distance.expect("missing distance"))
-            // .inspect(|c| info!("{:?}", c))
-            .accumulate(0, |max, data| {
+            .flat_map(|(id, state)| state.distance.map(|distance| (id, distance, state.parent)))
+            .inspect(move |&(id, distance, parent)| {
+                if keep_tree {
+                    local_tree_ref.borrow_mut().insert(id, (distance, parent));
+                }
+            })
+            .map(|(id, distance, _parent)| (distance, id))
+            .accumulate((0, root), |max, data| {
                 *max = std::cmp::max(*data.iter().max().expect("empty collection"), *max)
             })
             .inspect(|partial| info!("Partial maximum {:?}", partial))
             .exchange(|_| 0)
-            .accumulate(0, |max, data| {
+            .accumulate((0, root), |max, data| {
                 *max = std::cmp::max(*data.iter().max().expect("empty collection"), *max)
             })
+            .broadcast()
             .collect_single()
     });
 
     let elapsed = run_to_completion(worker, probe);
-    let diameter = diameter_box.borrow_mut().take();
+    let farthest = farthest_box.borrow_mut().take();
+    let tree = local_tree.replace(ShortestPathsTree::new());
 
-    (diameter, elapsed)
+    (farthest, tree, elapsed)
 }
@@ -0,0 +1,124 @@
+use crate::distance::Distance;
+use crate::node::Node;
+use serde::{Deserialize, Serialize};
+
+/// Bounds to the diameter of a graph, as computed by an algorithm.
+/// The witness pair, if known, is a pair of nodes at distance at least `lower`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Abomonation, Serialize, Deserialize)]
+pub struct DiameterBounds<D> {
+    pub lower: D,
+    /// Missing for the algorithms that cannot certify an upper bound
+    pub upper: Option<D>,
+    pub witness_pair: Option<(Node, Node)>,
+}
+
+impl<D: Distance> DiameterBounds<D> {
+    pub fn exact(diameter: D, witness_pair: (Node, Node)) -> Self {
+        Self {
+            lower: diameter,
+            upper: Some(diameter),
+            witness_pair: Some(witness_pair),
+        }
+    }
+
+    /// In an undirected graph the diameter is at least the eccentricity of
+    /// any node, and at most twice that, or the largest distance if smaller.
+    pub fn from_eccentricity(eccentricity: D) -> Self {
+        Self {
+            lower: eccentricity,
+            upper: Some(eccentricity.saturating_times(2)),
+            witness_pair: None,
+        }
+    }
+
+    /// The bounds given by the eccentricity of `source`, with the farthest node from it
+    pub fn from_sweep(eccentricity: D, source: Node, farthest: Node) -> Self {
+        Self {
+            witness_pair: Some((source, farthest)),
+            ..Self::from_eccentricity(eccentricity)
+        }
+    }
+
+    pub fn lower_only(lower: D) -> Self {
+        Self {
+            lower,
+            upper: None,
+            witness_pair: None,
+        }
+    }
+
+    /// The tightest bounds implied by both
+    pub fn intersect(self, other: Self) -> Self {
+        let (lower, witness_pair) = if other.lower > self.lower {
+            (other.lower, other.witness_pair)
+        } else {
+            (self.lower, self.witness_pair)
+        };
+        let upper = match (self.upper, other.upper) {
+            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+        Self {
+            lower,
+            upper,
+            witness_pair,
+        }
+    }
+
+    /// The ratio between the upper and the lower bound
+    pub fn ratio(&self) -> Option<f64> {
+        self.upper
+            .map(|upper| upper.to_u64() as f64 / self.lower.to_u64() as f64)
+    }
+
+    pub fn to_u64(self) -> DiameterBounds<u64> {
+        DiameterBounds {
+            lower: self.lower.to_u64(),
+            upper: self.upper.map(|upper| upper.to_u64()),
+            witness_pair: self.witness_pair,
+        }
+    }
+}
+
+#[test]
+fn test_from_eccentricity() {
+    let bounds = DiameterBounds::from_eccentricity(21u32);
+    assert_eq!(bounds.lower, 21);
+    assert_eq!(bounds.upper, Some(42));
+    assert_eq!(bounds.ratio(), Some(2.0));
+    assert_eq!(bounds.to_u64().upper, Some(42u64));
+}
+
+#[test]
+fn test_from_eccentricity_at_the_boundary() {
+    use crate::datasets::Metadata;
+    use crate::distance::fits_u32;
+
+    // The largest eccentricity in a graph whose distances fit in 32 bits
+    let meta = Metadata {
+        num_nodes: 1 << 16,
+        num_edges: 0,
+        min_weight: 1,
+        max_weight: (1 << 15) - 1,
+    };
+    assert!(fits_u32(&meta));
+    let eccentricity = (meta.num_nodes - 1) * meta.max_weight;
+    let bounds = DiameterBounds::from_eccentricity(eccentricity);
+    assert_eq!(bounds.upper, Some(2 * eccentricity));
+    // Larger ones give the largest distance, rather than wrapping around
+    let bounds = DiameterBounds::from_eccentricity(std::u32::MAX / 2 + 1);
+    assert_eq!(bounds.upper, Some(std::u32::MAX));
+}
+
+#[test]
+fn test_intersect() {
+    let first = DiameterBounds::from_sweep(10u32, 0, 1);
+    let second = DiameterBounds::from_sweep(14u32, 1, 2);
+    let bounds = first.intersect(second);
+    assert_eq!(bounds.lower, 14);
+    assert_eq!(bounds.upper, Some(20));
+    assert_eq!(bounds.witness_pair, Some((1, 2)));
+    let bounds = DiameterBounds::lower_only(16u32).intersect(bounds);
+    assert_eq!(bounds.lower, 16);
+    assert_eq!(bounds.upper, Some(20));
+}
@@ -0,0 +1,104 @@
+use crate::node::Node;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the centralities of the nodes are saved, in the data directory under
+/// the sha of the run. Each worker writes the centralities of its own nodes
+/// to a text file named after its index, with a line per node made of the
+/// node, its harmonic centrality and its closeness centrality, separated by
+/// tabs. The first worker also writes the `top` most central nodes by each
+/// measure, from the most central one.
+#[derive(Clone)]
+pub struct Centralities {
+    dir: PathBuf,
+    worker: usize,
+    top: Option<usize>,
+}
+
+/// The centralities of a node, from the sizes of its balls of growing radius
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Centrality {
+    /// The sum of the inverse distances to the other nodes
+    pub harmonic: f64,
+    /// The inverse of the sum of the distances to the nodes it reaches
+    pub closeness: f64,
+}
+
+impl Centralities {
+    pub fn new(ddir: &Path, sha: &str, worker: usize, top: Option<usize>) -> Self {
+        let mut dir = ddir.to_path_buf();
+        dir.push("centralities");
+        dir.push(sha);
+        Self { dir, worker, top }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.top.is_some()
+    }
+
+    /// How many of the most central nodes are saved
+    pub fn top(&self) -> Option<usize> {
+        self.top
+    }
+
+    pub fn save(&self, centralities: &mut [(Node, Centrality)]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let timer = std::time::Instant::now();
+        centralities.sort_unstable_by_key(|(node, _centrality)| *node);
+        let path = self.dir.join(format!("{}.tsv", self.worker));
+        let mut writer = self.create(&path);
+        for (node, centrality) in centralities.iter() {
+            writeln!(
+                writer,
+                "{}\t{}\t{}",
+                node, centrality.harmonic, centrality.closeness
+            )
+            .expect("problem writing centralities");
+        }
+        writer.flush().expect("problem writing centralities");
+        info!(
+            "Centralities of {} nodes saved to {:?} in {:?}",
+            centralities.len(),
+            path,
+            timer.elapsed()
+        );
+    }
+
+    /// Saves the most central nodes by the measure with the given name
+    pub fn save_top(&self, measure: &str, top: &[(f64, Node)]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let path = self.dir.join(format!("top-{}.tsv", measure));
+        let mut writer = self.create(&path);
+        for (centrality, node) in top.iter() {
+            writeln!(writer, "{}\t{}", node, centrality).expect("problem writing centralities");
+        }
+        writer.flush().expect("problem writing centralities");
+        info!("Top {} nodes by {} saved to {:?}", top.len(), measure, path);
+    }
+
+    fn create(&self, path: &Path) -> BufWriter<File> {
+        std::fs::create_dir_all(&self.dir).expect("problem creating centralities directory");
+        BufWriter::new(File::create(path).expect("problem creating centralities file"))
+    }
+}
+
+/// Sorts from the most central node, breaking ties by node
+pub fn sort_by_centrality(nodes: &mut [(f64, Node)]) {
+    nodes.sort_unstable_by(|(c1, u), (c2, v)| {
+        c2.partial_cmp(c1)
+            .expect("centralities are never NaN")
+            .then(u.cmp(v))
+    });
+}
+
+#[test]
+fn test_sort_by_centrality() {
+    let mut nodes = vec![(1.0, 3), (2.5, 1), (1.0, 2), (0.0, 0)];
+    sort_by_centrality(&mut nodes);
+    assert_eq!(nodes, vec![(2.5, 1), (1.0, 2), (1.0, 3), (0.0, 0)]);
+}
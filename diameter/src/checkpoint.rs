@@ -0,0 +1,433 @@
+use crate::distributed_adjacencies::LocalStates;
+use crate::node::Node;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use timely::communication::Allocate;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::*;
+use timely::dataflow::Scope;
+use timely::dataflow::Stream;
+use timely::order::Product;
+use timely::progress::Timestamp;
+use timely::worker::Worker;
+use timely::Data;
+
+/// The checkpoints of a run, saved in the data directory under the sha
+/// of the run. Each worker saves its own node states, at the end of every
+/// `every` rounds, in a file named after the round and the worker index.
+/// The states that left the loop since the previous checkpoint go in a
+/// file of their own, so that each of them is written once.
+#[derive(Clone)]
+pub struct Checkpoints {
+    dir: PathBuf,
+    worker: usize,
+    every: Option<u32>,
+    resuming: bool,
+}
+
+impl Checkpoints {
+    pub fn new(ddir: &Path, sha: &str, worker: usize, every: Option<u32>, resuming: bool) -> Self {
+        let mut dir = ddir.to_path_buf();
+        dir.push("checkpoints");
+        dir.push(sha);
+        Self {
+            dir,
+            worker,
+            every,
+            resuming,
+        }
+    }
+
+    /// The checkpoints of a phase of the algorithm, kept apart from the
+    /// ones of the other phases
+    pub fn phase(&self, name: &str) -> Self {
+        Self {
+            dir: self.dir.join(name),
+            ..self.clone()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.every.is_some()
+    }
+
+    pub fn is_resuming(&self) -> bool {
+        self.resuming
+    }
+
+    pub fn should_save(&self, round: u32) -> bool {
+        match self.every {
+            Some(every) => round > 0 && round % every == 0,
+            None => false,
+        }
+    }
+
+    fn path(&self, round: u32) -> PathBuf {
+        self.dir.join(format!("{}-{}.bin", round, self.worker))
+    }
+
+    fn retired_path(&self, round: u32) -> PathBuf {
+        self.dir
+            .join(format!("retired-{}-{}.bin", round, self.worker))
+    }
+
+    /// The rounds for which this worker has a checkpoint
+    fn rounds(&self) -> Vec<u32> {
+        let suffix = format!("-{}.bin", self.worker);
+        match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| {
+                    let name = entry.ok()?.file_name().into_string().ok()?;
+                    if name.ends_with(&suffix) {
+                        name[..name.len() - suffix.len()].parse::<u32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// If resuming, agrees with the other workers on the latest round for
+    /// which all of them have a checkpoint. Must be called by all workers.
+    pub fn resume_point<A: Allocate>(&self, worker: &mut Worker<A>) -> Option<u32> {
+        if !self.resuming {
+            return None;
+        }
+        let peers = worker.peers();
+        let result = Rc::new(RefCell::new(None));
+        let result_ref = Rc::clone(&result);
+        let rounds = self.rounds();
+        let probe = worker.dataflow::<(), _, _>(move |scope| {
+            vec![rounds]
+                .to_stream(scope)
+                .broadcast()
+                .accumulate(HashMap::new(), |counts: &mut HashMap<u32, usize>, data| {
+                    for rounds in data.iter() {
+                        for round in rounds.iter() {
+                            *counts.entry(*round).or_insert(0) += 1;
+                        }
+                    }
+                })
+                .inspect(move |counts| {
+                    *result_ref.borrow_mut() = counts
+                        .iter()
+                        .filter(|(_round, count)| **count == peers)
+                        .map(|(round, _count)| *round)
+                        .max();
+                })
+                .probe()
+        });
+        worker.step_while(|| !probe.done());
+        let round = result.borrow_mut().take();
+        match round {
+            Some(round) => info!("Resuming from round {} ({:?})", round, self.dir),
+            None => info!("No checkpoint to resume from in {:?}", self.dir),
+        }
+        round
+    }
+
+    /// Saves the states still circulating in the loop, along with the ones
+    /// that left it since the previous checkpoint.
+    pub fn save<'a, S, I, J>(&self, round: u32, circulating: I, retired: J)
+    where
+        S: Serialize + 'a,
+        I: IntoIterator<Item = (Node, &'a S)>,
+        J: IntoIterator<Item = (Node, &'a S)>,
+    {
+        let timer = std::time::Instant::now();
+        let circulating: Vec<(Node, &S)> = circulating.into_iter().collect();
+        let retired: Vec<(Node, &S)> = retired.into_iter().collect();
+        std::fs::create_dir_all(&self.dir).expect("problem creating checkpoint directory");
+        // The circulating states go last, since their file is the one
+        // telling that the checkpoint of the round is there
+        write_atomically(&self.retired_path(round), &retired);
+        write_atomically(&self.path(round), &circulating);
+        info!(
+            "Checkpoint of round {} with {} states saved in {:?}",
+            round,
+            circulating.len() + retired.len(),
+            timer.elapsed()
+        );
+    }
+
+    /// Loads the states circulating in the loop at the given round, and
+    /// the ones that left it in this round or earlier.
+    pub fn load<S: DeserializeOwned>(&self, round: u32) -> (Vec<(Node, S)>, Vec<(Node, S)>) {
+        let circulating = read(&self.path(round));
+        let mut retired = Vec::new();
+        for retired_round in self.retired_rounds() {
+            if retired_round <= round {
+                retired.extend(read::<Vec<(Node, S)>>(&self.retired_path(retired_round)));
+            }
+        }
+        (circulating, retired)
+    }
+
+    /// The rounds for which this worker saved the states that left the loop
+    fn retired_rounds(&self) -> Vec<u32> {
+        let suffix = format!("-{}.bin", self.worker);
+        match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| {
+                    let name = entry.ok()?.file_name().into_string().ok()?;
+                    if name.starts_with("retired-") && name.ends_with(&suffix) {
+                        name["retired-".len()..name.len() - suffix.len()]
+                            .parse::<u32>()
+                            .ok()
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Removes the checkpoints of this worker, of all phases
+    pub fn clear(&self) {
+        fn clear_dir(dir: &Path, suffix: &str) {
+            if let Ok(entries) = std::fs::read_dir(dir) {
+                for entry in entries.filter_map(|entry| entry.ok()) {
+                    let path = entry.path();
+                    if path.is_dir() {
+                        clear_dir(&path, suffix);
+                    } else if path.to_string_lossy().ends_with(suffix) {
+                        std::fs::remove_file(&path).expect("problem removing checkpoint");
+                    }
+                }
+            }
+            // Fails if other workers still have their checkpoints in there
+            std::fs::remove_dir(dir).ok();
+        }
+        if self.is_enabled() {
+            clear_dir(&self.dir, &format!("-{}.bin", self.worker));
+        }
+    }
+}
+
+/// Writes to a temporary file first, so that a kill in the middle
+/// of the write does not leave a truncated checkpoint around
+fn write_atomically<V: Serialize>(path: &Path, value: &V) {
+    let tmp_path = path.with_extension("tmp");
+    let writer = BufWriter::new(File::create(&tmp_path).expect("problem creating checkpoint"));
+    bincode::serialize_into(writer, value).expect("problem writing checkpoint");
+    std::fs::rename(&tmp_path, path).expect("problem renaming checkpoint");
+}
+
+fn read<V: DeserializeOwned>(path: &Path) -> V {
+    let reader = BufReader::new(File::open(path).expect("missing checkpoint"));
+    bincode::deserialize_from(reader).expect("problem reading checkpoint")
+}
+
+/// Holds back the data of the checkpoint rounds until this round is complete,
+/// calls `save` on it, and then releases it. The round is complete also with
+/// respect to the `others` stream, even if no data flows through `stream`.
+///
+/// Every worker is notified of every checkpoint round in which some worker
+/// has data, so that all of them save their checkpoint.
+pub fn hold_at_checkpoints<G, T, D, E, F>(
+    stream: &Stream<G, D>,
+    others: &Stream<G, E>,
+    checkpoints: &Checkpoints,
+    mut save: F,
+) -> Stream<G, D>
+where
+    G: Scope<Timestamp = Product<T, u32>>,
+    T: Timestamp,
+    D: Data,
+    E: Data,
+    F: FnMut(&Product<T, u32>, &[D]) + 'static,
+{
+    let token_checkpoints = checkpoints.clone();
+    let checkpoints = checkpoints.clone();
+    let mut stash = HashMap::new();
+
+    let tokens = stream
+        .binary(
+            others,
+            Pipeline,
+            Pipeline,
+            "checkpoint tokens",
+            move |_, _| {
+                move |input, others_input, output| {
+                    input.for_each(|t, _data| {
+                        if token_checkpoints.should_save(t.time().inner) {
+                            output.session(&t).give(());
+                        }
+                    });
+                    others_input.for_each(|t, _data| {
+                        if token_checkpoints.should_save(t.time().inner) {
+                            output.session(&t).give(());
+                        }
+                    });
+                }
+            },
+        )
+        .broadcast();
+
+    stream.binary_notify(
+        &tokens,
+        Pipeline,
+        Pipeline,
+        "checkpoint",
+        None,
+        move |input, tokens_input, output, notificator| {
+            input.for_each(|t, data| {
+                let data = data.replace(Vec::new());
+                if checkpoints.should_save(t.time().inner) {
+                    stash
+                        .entry(t.time().clone())
+                        .or_insert_with(Vec::new)
+                        .extend(data.into_iter());
+                    notificator.notify_at(t.retain());
+                } else {
+                    output.session(&t).give_iterator(data.into_iter());
+                }
+            });
+            tokens_input.for_each(|t, _data| {
+                notificator.notify_at(t.retain());
+            });
+            notificator.for_each(|t, _, _| {
+                let data = stash.remove(t.time()).unwrap_or_else(Vec::new);
+                save(t.time(), &data);
+                output.session(&t).give_iterator(data.into_iter());
+            });
+        },
+    )
+}
+
+pub trait Checkpoint<G: Scope> {
+    /// Saves, at the checkpoint rounds, the states of the nodes going around
+    /// a loop, together with the ones of the `retired` nodes that left the
+    /// loop since the previous checkpoint. The states are looked up in the
+    /// given array. The loop must run within a single time of the enclosing
+    /// scope.
+    fn checkpoint<S: Serialize + 'static>(
+        &self,
+        retired: &Stream<G, Node>,
+        states: &Rc<RefCell<LocalStates<S>>>,
+        checkpoints: &Checkpoints,
+    ) -> Stream<G, Node>;
+}
+
+impl<G, T> Checkpoint<G> for Stream<G, Node>
+where
+    G: Scope<Timestamp = Product<T, u32>>,
+    T: Timestamp,
+{
+    fn checkpoint<S: Serialize + 'static>(
+        &self,
+        retired: &Stream<G, Node>,
+        states: &Rc<RefCell<LocalStates<S>>>,
+        checkpoints: &Checkpoints,
+    ) -> Stream<G, Node> {
+        if !checkpoints.is_enabled() {
+            return self.clone();
+        }
+
+        // The nodes retired since the last checkpoint, with their round,
+        // dropped once saved
+        let retired_log = Rc::new(RefCell::new(Vec::new()));
+        let retired_log_writer = Rc::clone(&retired_log);
+        let retired = retired.inspect_batch(move |t, data| {
+            retired_log_writer
+                .borrow_mut()
+                .extend(data.iter().map(|id| (t.inner, *id)));
+        });
+
+        let saver = checkpoints.clone();
+        let states = Rc::clone(states);
+        hold_at_checkpoints(self, &retired, checkpoints, move |t, data| {
+            let states = states.borrow();
+            let state = |id: Node| (id, states.get(id).expect("missing state"));
+            let mut retired_log = retired_log.borrow_mut();
+            let (persisted, later): (Vec<_>, Vec<_>) = retired_log
+                .drain(..)
+                .partition(|(round, _id)| *round <= t.inner);
+            *retired_log = later;
+            saver.save(
+                t.inner,
+                data.iter().map(|id| state(*id)),
+                persisted.iter().map(|(_round, id)| state(*id)),
+            );
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("diameter-{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn test_checkpoints_save_load_clear() {
+    let ddir = test_dir("checkpoints");
+    let checkpoints = Checkpoints::new(&ddir, "sha", 0, Some(2), false);
+    assert!(!checkpoints.should_save(0));
+    assert!(!checkpoints.should_save(1));
+    assert!(checkpoints.should_save(2));
+    assert!(checkpoints.should_save(4));
+    assert!(!Checkpoints::new(&ddir, "sha", 0, None, false).should_save(2));
+
+    checkpoints.save(2, vec![(1, &10u32), (3, &30)], vec![(5, &50)]);
+    checkpoints.save(4, vec![(3, &31u32)], vec![(1, &11)]);
+    let (circulating, retired) = checkpoints.load::<u32>(2);
+    assert_eq!(circulating, vec![(1, 10), (3, 30)]);
+    assert_eq!(retired, vec![(5, 50)]);
+    // The states retired before a round are loaded with it
+    let (circulating, mut retired) = checkpoints.load::<u32>(4);
+    retired.sort();
+    assert_eq!(circulating, vec![(3, 31)]);
+    assert_eq!(retired, vec![(1, 11), (5, 50)]);
+
+    let phase = checkpoints.phase("sweep-0");
+    phase.save(2, vec![(1, &1u32)], Vec::new());
+    let other = Checkpoints::new(&ddir, "sha", 1, Some(2), false);
+    other.save(2, vec![(2, &20u32)], Vec::new());
+    let mut rounds = checkpoints.rounds();
+    rounds.sort();
+    assert_eq!(rounds, vec![2, 4]);
+    assert_eq!(phase.rounds(), vec![2]);
+
+    // Clearing removes the checkpoints of all the phases of this worker only
+    checkpoints.clear();
+    assert!(checkpoints.rounds().is_empty());
+    assert!(checkpoints.retired_rounds().is_empty());
+    assert!(phase.rounds().is_empty());
+    assert_eq!(other.rounds(), vec![2]);
+    other.clear();
+    assert!(!checkpoints.dir.exists());
+    std::fs::remove_dir_all(&ddir).ok();
+}
+
+#[test]
+fn test_resume_point_agreed_by_all_workers() {
+    let ddir = test_dir("resume");
+    let dir = ddir.clone();
+    timely::execute(
+        timely::communication::Configuration::Process(2),
+        move |worker| {
+            let index = worker.index();
+            let saver = Checkpoints::new(&dir, "sha", index, Some(2), false);
+            // The run was killed after the first worker saved round 4, but
+            // before the second one did
+            let rounds: &[u32] = if index == 0 { &[2, 4] } else { &[2] };
+            for round in rounds.iter() {
+                saver.save(*round, vec![(index as Node, round)], Vec::new());
+            }
+            assert_eq!(saver.resume_point(worker), None);
+            let checkpoints = Checkpoints::new(&dir, "sha", index, Some(2), true);
+            assert_eq!(checkpoints.resume_point(worker), Some(2));
+        },
+    )
+    .expect("problem running the workers");
+    std::fs::remove_dir_all(&ddir).ok();
+}
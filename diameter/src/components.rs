@@ -0,0 +1,305 @@
+use crate::bounds::DiameterBounds;
+use crate::checkpoint::Checkpoints;
+use crate::distance::Distance;
+use crate::distributed_adjacencies::DistributedAdjacencies;
+use crate::node::Node;
+use crate::operators::*;
+use crate::sweep::sweep_from;
+use crate::vertex_program::*;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+use timely::communication::Allocate;
+use timely::dataflow::operators::*;
+use timely::progress::Timestamp;
+
+/// A connected component, named after its smallest node
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub root: Node,
+    pub size: u64,
+    /// Bounds to the diameter of the component on its own, missing if the
+    /// graph is connected, since then they are the ones of the algorithm
+    pub bounds: Option<DiameterBounds<u64>>,
+}
+
+/// The connected components of the graph, each node without edges being
+/// one on its own, with the largest ones bounded on their own.
+#[derive(Debug, Clone)]
+pub struct Components {
+    pub count: u64,
+    pub num_nodes: u64,
+    /// The size of the component of the root of the algorithm
+    pub root_size: u64,
+    /// The largest components, largest first
+    pub largest: Vec<Component>,
+    /// Upper bound to the diameters of the components not in `largest`,
+    /// which have no more nodes than the smallest of them
+    rest_upper: u64,
+}
+
+impl Components {
+    pub fn is_connected(&self) -> bool {
+        self.count <= 1
+    }
+
+    /// The nodes out of the component of the root, which the algorithms
+    /// starting from it never reach
+    pub fn unreachable(&self) -> u64 {
+        self.num_nodes - self.root_size
+    }
+
+    /// Widens the bounds given by an algorithm, which might have looked at
+    /// the component of its root only, so that they hold for the whole
+    /// graph, whose diameter is the largest among its components.
+    pub fn cover(&self, bounds: DiameterBounds<u64>) -> DiameterBounds<u64> {
+        if self.is_connected() {
+            return bounds;
+        }
+        let mut covered = bounds;
+        for component_bounds in self.largest.iter().filter_map(|c| c.bounds) {
+            if component_bounds.lower > covered.lower {
+                covered.lower = component_bounds.lower;
+                covered.witness_pair = component_bounds.witness_pair;
+            }
+            covered.upper = match (covered.upper, component_bounds.upper) {
+                (Some(a), Some(b)) => Some(std::cmp::max(a, b)),
+                _ => None,
+            };
+        }
+        covered.upper = covered
+            .upper
+            .map(|upper| std::cmp::max(upper, self.rest_upper));
+        covered
+    }
+}
+
+#[derive(Debug, Clone, Default, Abomonation, Serialize, Deserialize)]
+struct State {
+    label: Node,
+    active: bool,
+}
+
+/// Each node takes the smallest label among its own and the ones of its
+/// neighbours, starting from its identifier
+#[derive(Clone)]
+struct LabelPropagation;
+
+impl<T: Timestamp> VertexProgram<T> for LabelPropagation {
+    type State = State;
+    type Message = Node;
+
+    /// All the nodes with edges are in the input
+    fn with_default(&self) -> bool {
+        false
+    }
+
+    fn should_send(&self, _time: &T, state: &State) -> bool {
+        state.active
+    }
+
+    fn message(&self, _time: &T, _id: Node, state: &State, _weight: u32) -> Option<Node> {
+        Some(state.label)
+    }
+
+    fn combine(l1: &Node, l2: &Node) -> Node {
+        std::cmp::min(*l1, *l2)
+    }
+
+    fn apply(&self, _time: &T, state: &State, message: Option<&Node>) -> State {
+        match message {
+            Some(&label) if label < state.label => State {
+                label,
+                active: true,
+            },
+            _ => State {
+                label: state.label,
+                active: false,
+            },
+        }
+    }
+
+    fn is_active(&self, _time: &T, state: &State) -> bool {
+        state.active
+    }
+}
+
+/// Finds the connected components of the graph by label propagation, and
+/// bounds the diameters of the `k` largest ones with a double sweep from
+/// their smallest node, if there is more than one, using the given delta
+/// on weighted graphs. The components are known to all the workers, along
+/// with the size of the one of the given root.
+pub fn components<D: Distance, A: Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    k: usize,
+    n: Node,
+    root: Node,
+    (min_weight, max_weight): (u32, u32),
+    delta: u32,
+    checkpoints: &Checkpoints,
+) -> (Components, Duration) {
+    // The sizes of the components whose label is owned by this worker
+    let local_sizes = Rc::new(RefCell::new(HashMap::new()));
+    let local_sizes_ref = Rc::clone(&local_sizes);
+    let root_label = Rc::new(RefCell::new(None));
+    let root_label_ref = Rc::clone(&root_label);
+    let probe = worker.dataflow::<(), _, _>(|scope| {
+        let nodes = adjacencies.nodes::<_, State>(scope).map(|(id, _state)| {
+            (
+                id,
+                State {
+                    label: id,
+                    active: true,
+                },
+            )
+        });
+        run_until_quiescent(adjacencies, &nodes, LabelPropagation)
+            .map(move |(id, state)| {
+                if id == root {
+                    root_label_ref.borrow_mut().replace(state.label);
+                }
+                state.label
+            })
+            .exchange(|&label| label as u64)
+            .inspect(move |&label| {
+                *local_sizes_ref.borrow_mut().entry(label).or_insert(0u64) += 1;
+            })
+            .probe()
+    });
+    let mut elapsed = run_to_completion(worker, probe);
+    // The label of the root is known to its worker only
+    let root_label = global_min(worker, root_label.replace(None));
+
+    // Each worker shares the count of its components and the largest
+    // ones, with one more to bound the diameters of the rest
+    let local_sizes = local_sizes.replace(HashMap::new());
+    let mut local_largest: Vec<(u64, Node)> = local_sizes
+        .iter()
+        .map(|(&label, &size)| (size, label))
+        .collect();
+    local_largest.sort_unstable_by(|a, b| b.cmp(a));
+    local_largest.truncate(k + 1);
+    let local_root_size = root_label
+        .and_then(|label| local_sizes.get(&label).copied())
+        .unwrap_or(0);
+    let local_summary = (
+        local_sizes.len() as u64,
+        local_root_size,
+        local_sizes.values().sum::<u64>(),
+        local_largest,
+    );
+    let summary = Rc::new(RefCell::new((0u64, 0u64, 0u64, Vec::new())));
+    let summary_ref = Rc::clone(&summary);
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        vec![local_summary]
+            .to_stream(scope)
+            .broadcast()
+            .inspect(move |(count, root_size, num_nodes, largest)| {
+                let mut summary = summary_ref.borrow_mut();
+                summary.0 += count;
+                summary.1 += root_size;
+                summary.2 += num_nodes;
+                summary.3.extend(largest.iter().copied());
+            })
+            .probe()
+    });
+    elapsed += run_to_completion(worker, probe);
+    let (count, root_size, num_nodes, mut largest) = summary.replace((0, 0, 0, Vec::new()));
+    // A root without edges is a component on its own
+    let root_size = std::cmp::max(root_size, 1);
+    // The nodes without edges are not in the adjacencies, and are left out
+    // of the propagation, but each is a component of a single node
+    let isolated = n as u64 - num_nodes;
+    if isolated > 0 {
+        info!("{} nodes have no edges", isolated);
+    }
+    let (count, num_nodes) = (count + isolated, n);
+    largest.sort_unstable_by(|a, b| b.cmp(a));
+    let rest_size = largest.get(k).map(|&(size, _label)| size).unwrap_or(0);
+    largest.truncate(k);
+
+    let mut components = Vec::with_capacity(largest.len());
+    for (rank, &(size, root)) in largest.iter().enumerate() {
+        // A component has a path through all its nodes at most
+        let size_upper = (size - 1) * max_weight as u64;
+        let bounds = if count > 1 {
+            let (bounds, _node_bounds, sweep_elapsed) = sweep_from::<D, _>(
+                adjacencies,
+                worker,
+                2,
+                root,
+                (min_weight, max_weight),
+                delta,
+                &checkpoints.phase(&format!("component-{}", rank)),
+                false,
+            );
+            elapsed += sweep_elapsed;
+            bounds.map(|bounds| {
+                let bounds = bounds.to_u64();
+                DiameterBounds {
+                    upper: bounds.upper.map(|upper| std::cmp::min(upper, size_upper)),
+                    ..bounds
+                }
+            })
+        } else {
+            None
+        };
+        components.push(Component { root, size, bounds });
+    }
+
+    let components = Components {
+        count,
+        num_nodes,
+        root_size,
+        largest: components,
+        rest_upper: rest_size.saturating_sub(1) * max_weight as u64,
+    };
+    (components, elapsed)
+}
+
+#[test]
+fn test_cover() {
+    let component = |root, size, lower, upper| Component {
+        root,
+        size,
+        bounds: Some(DiameterBounds {
+            lower,
+            upper: Some(upper),
+            witness_pair: Some((root, root + 1)),
+        }),
+    };
+    let components = Components {
+        count: 3,
+        num_nodes: 20,
+        root_size: 12,
+        largest: vec![component(0, 12, 5, 8), component(12, 6, 4, 5)],
+        rest_upper: 1,
+    };
+    assert_eq!(components.unreachable(), 8);
+
+    // The bounds of the algorithm on a small component are widened
+    let bounds = DiameterBounds {
+        lower: 4,
+        upper: Some(5),
+        witness_pair: Some((12, 17)),
+    };
+    let covered = components.cover(bounds);
+    assert_eq!(covered.lower, 5);
+    assert_eq!(covered.upper, Some(8));
+    assert_eq!(covered.witness_pair, Some((0, 1)));
+
+    // A lower bound better than the sweeps is kept, while the upper bound
+    // must hold for all the components
+    let bounds = DiameterBounds {
+        lower: 6,
+        upper: Some(7),
+        witness_pair: Some((0, 11)),
+    };
+    let covered = components.cover(bounds);
+    assert_eq!(covered.lower, 6);
+    assert_eq!(covered.upper, Some(8));
+    assert_eq!(covered.witness_pair, Some((0, 11)));
+}
@@ -1,4 +1,5 @@
 use crate::distributed_graph::*;
+use crate::node::{Node, WIDTH_SUFFIX};
 use bytes::*;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
@@ -17,7 +18,7 @@ mod bvconvert;
 
 #[derive(Abomonation, Clone, PartialEq, PartialOrd, Debug)]
 pub struct WeightedEdge {
-    pub dst: u32,
+    pub dst: Node,
     pub weight: u32,
 }
 
@@ -31,7 +32,7 @@ impl Ord for WeightedEdge {
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Metadata {
-    pub num_nodes: u32,
+    pub num_nodes: Node,
     pub num_edges: u64,
     pub min_weight: u32,
     pub max_weight: u32,
@@ -131,7 +132,17 @@ pub enum DatasetKind {
 }
 
 impl Dataset {
-    pub fn as_vec(&self) -> Vec<((u32, u32), u32)> {
+    /// Whether the graph is connected by construction, as the largest
+    /// connected component of another, possibly reweighted
+    pub fn is_connected(&self) -> bool {
+        match &self.kind {
+            DatasetKind::LCC(_) => true,
+            DatasetKind::RWeight(_, inner) => inner.is_connected(),
+            _ => false,
+        }
+    }
+
+    pub fn as_vec(&self) -> Vec<((Node, Node), u32)> {
         let mut edges = Vec::new();
         self.for_each(|u, v, w| {
             edges.push(((u, v), w));
@@ -164,7 +175,7 @@ impl Dataset {
     fn metadata_map(&self) -> HashMap<String, Metadata> {
         let mut map = HashMap::new();
         let mut metadata_file = self.data_dir.clone();
-        metadata_file.push("metadata.bin");
+        metadata_file.push(format!("metadata{}.bin", WIDTH_SUFFIX));
         if metadata_file.exists() {
             let reader = File::open(metadata_file).expect("error opening metadata file");
             let values: Vec<(String, Metadata)> =
@@ -176,7 +187,7 @@ impl Dataset {
 
     fn update_metadata(&self, new_map: HashMap<String, Metadata>) {
         let mut metadata_file = self.data_dir.clone();
-        metadata_file.push("metadata.bin");
+        metadata_file.push(format!("metadata{}.bin", WIDTH_SUFFIX));
         let writer = File::create(metadata_file).expect("error creating metadata file");
         let values: Vec<(String, Metadata)> = new_map.into_iter().collect();
         bincode::serialize_into(writer, &values).expect("problem serializing metadata");
@@ -303,7 +314,14 @@ impl Dataset {
                 let compressed_path = self.edges_directory();
                 if !compressed_path.is_dir() {
                     let timer = std::time::Instant::now();
-                    bvconvert::convert(&tool_graph_path, &compressed_path);
+                    // The converter writes 32 bits identifiers, re-encoded if ours are wider
+                    let narrow_path = dir.join("edges");
+                    if !narrow_path.is_dir() {
+                        bvconvert::convert(&tool_graph_path, &narrow_path);
+                    }
+                    if narrow_path != compressed_path {
+                        widen_edges(&narrow_path, compressed_path);
+                    }
                     info!("Compression took {:?}", timer.elapsed());
                 }
             }
@@ -318,7 +336,7 @@ impl Dataset {
                 let inner_meta = inner.metadata();
                 let n = inner_meta.num_nodes;
 
-                let max_weight = n;
+                let max_weight = std::cmp::min(n as u64, std::u32::MAX as u64) as u32;
 
                 let mut rng = rand_xoshiro::Xoshiro512StarStar::seed_from_u64(*seed);
                 let distribution = rand::distributions::Uniform::new_inclusive(1, max_weight);
@@ -339,7 +357,7 @@ impl Dataset {
             }
 
             DatasetKind::Layered(layers, inner) => {
-                let layers = *layers as u32;
+                let layers = *layers as Node;
                 assert!(layers >= 1);
                 inner.prepare();
                 let edges_dir = self.edges_directory();
@@ -455,14 +473,15 @@ impl Dataset {
                     // std::cmp::min(1_000_000, std::cmp::max((edges / blocks) as u64, 1)),
                     32,
                 );
-                for i in 0..*side {
-                    for j in 0..*side {
+                let side = *side as Node;
+                for i in 0..side {
+                    for j in 0..side {
                         let node = i * side + j;
-                        if i + 1 < *side {
+                        if i + 1 < side {
                             let bottom = (i + 1) * side + j;
                             compressor.write((node, bottom));
                         }
-                        if j + 1 < *side {
+                        if j + 1 < side {
                             let right = i * side + j + 1;
                             compressor.write((node, right));
                         }
@@ -481,15 +500,16 @@ impl Dataset {
                     edges_dir,
                     32, // std::cmp::min(1_000_000, std::cmp::max((edges / blocks) as u64, 1)),
                 );
-                for i in 0..*side {
-                    for j in 0..*side {
+                let side = *side as Node;
+                for i in 0..side {
+                    for j in 0..side {
                         let node = i * side + j;
-                        if i + 1 < *side {
+                        if i + 1 < side {
                             let w = if rng.gen_bool(*p) { *w1 } else { *w2 };
                             let bottom = (i + 1) * side + j;
                             compressor.write((node, bottom, w));
                         }
-                        if j + 1 < *side {
+                        if j + 1 < side {
                             let w = if rng.gen_bool(*p) { *w1 } else { *w2 };
                             let right = i * side + j + 1;
                             compressor.write((node, right, w));
@@ -509,15 +529,16 @@ impl Dataset {
                     edges_dir,
                     32, // std::cmp::min(1_000_000, std::cmp::max((edges / blocks) as u64, 1)),
                 );
-                for i in 0..*side {
-                    for j in 0..*side {
+                let side = *side as Node;
+                for i in 0..side {
+                    for j in 0..side {
                         let node = i * side + j;
-                        if i + 1 < *side {
+                        if i + 1 < side {
                             let w = uniform.sample(&mut rng);
                             let bottom = (i + 1) * side + j;
                             compressor.write((node, bottom, w));
                         }
-                        if j + 1 < *side {
+                        if j + 1 < side {
                             let w = uniform.sample(&mut rng);
                             let right = i * side + j + 1;
                             compressor.write((node, right, w));
@@ -530,7 +551,7 @@ impl Dataset {
 
     pub fn for_each<F>(&self, mut action: F)
     where
-        F: FnMut(u32, u32, u32),
+        F: FnMut(Node, Node, u32),
     {
         let files = self
             .binary_edge_files()
@@ -548,7 +569,7 @@ impl Dataset {
 
     pub fn edges_directory(&self) -> PathBuf {
         let mut path = self.dataset_directory();
-        path.push("edges");
+        path.push(format!("edges{}", WIDTH_SUFFIX));
         path
     }
 
@@ -759,12 +780,12 @@ fn maybe_download_and_remap_dimacs_file(url: &str, dir: PathBuf) -> PathBuf {
 
 #[derive(Default)]
 struct Remapper {
-    cnt: u32,
-    node_map: HashMap<u32, u32>,
+    cnt: Node,
+    node_map: HashMap<Node, Node>,
 }
 
 impl Remapper {
-    fn remap(&mut self, node: u32) -> u32 {
+    fn remap(&mut self, node: Node) -> Node {
         let mut cnt = self.cnt;
         let remapped = *self.node_map.entry(node).or_insert_with(|| {
             let remapped = cnt;
@@ -824,9 +845,30 @@ fn text_edge_file_remap(input_path: &PathBuf, output_path: &PathBuf) {
     });
 }
 
+/// Re-encodes the unweighted edges stored in `narrow_dir` with 32 bits
+/// identifiers into `edges_dir`, with identifiers of our width.
+fn widen_edges(narrow_dir: &PathBuf, edges_dir: PathBuf) {
+    info!("Re-encoding {:?} into {:?}", narrow_dir, edges_dir);
+    std::fs::create_dir_all(&edges_dir).expect("problem creating directory");
+    let mut compressor = CompressedPairsWriter::<Node>::to_file(edges_dir, 32);
+    for entry in std::fs::read_dir(narrow_dir).expect("problem reading files") {
+        let path = entry.expect("problem getting entry").path();
+        let is_part = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.starts_with("part-"))
+            .unwrap_or(false);
+        if is_part {
+            CompressedEdges::from_file(LoadType::Offline, path, None)
+                .expect("problem creating compressed edges from file")
+                .for_each(&mut |u: u32, v: u32, _w| compressor.write((u as Node, v as Node)));
+        }
+    }
+}
+
 fn read_text_edge_file_unweighted<F>(path: &PathBuf, mut action: F)
 where
-    F: FnMut((u32, u32)),
+    F: FnMut((Node, Node)),
 {
     use std::io::{BufRead, BufReader};
     let reader = BufReader::new(GzDecoder::new(
@@ -839,12 +881,12 @@ where
             let src = tokens
                 .next()
                 .expect("no source in line")
-                .parse::<u32>()
+                .parse::<Node>()
                 .expect("could not parse source");
             let dst = tokens
                 .next()
                 .expect("no destination in line")
-                .parse::<u32>()
+                .parse::<Node>()
                 .expect("could not parse destination");
             action((src, dst));
         }
@@ -853,7 +895,7 @@ where
 
 fn read_text_edge_file_weighted<F>(path: &PathBuf, mut action: F)
 where
-    F: FnMut((u32, u32, u32)),
+    F: FnMut((Node, Node, u32)),
 {
     use std::io::{BufRead, BufReader};
     let reader = BufReader::new(GzDecoder::new(
@@ -866,12 +908,12 @@ where
             let src = tokens
                 .next()
                 .expect("no source in line")
-                .parse::<u32>()
+                .parse::<Node>()
                 .expect("could not parse source");
             let dst = tokens
                 .next()
                 .expect("no destination in line")
-                .parse::<u32>()
+                .parse::<Node>()
                 .expect("could not parse destination");
             let weight = tokens
                 .next()
@@ -885,7 +927,7 @@ where
 
 fn read_dimacs_file<F>(path: &PathBuf, mut action: F)
 where
-    F: FnMut((u32, u32, u32)),
+    F: FnMut((Node, Node, u32)),
 {
     use std::io::{BufRead, BufReader};
     let reader = BufReader::new(GzDecoder::new(
@@ -898,12 +940,12 @@ where
             let src = tokens
                 .next()
                 .expect("no source in line")
-                .parse::<u32>()
+                .parse::<Node>()
                 .expect("could not parse source");
             let dst = tokens
                 .next()
                 .expect("no destination in line")
-                .parse::<u32>()
+                .parse::<Node>()
                 .expect("could not parse destination");
             let weight = tokens
                 .next()
@@ -916,9 +958,9 @@ where
 }
 
 struct UnionFindNode {
-    parent: u32,
+    parent: Node,
     rank: u32,
-    size: u32,
+    size: Node,
 }
 
 struct UnionFind {
@@ -930,15 +972,15 @@ impl UnionFind {
         let mut components = Vec::with_capacity(n);
         for i in 0..n {
             components.push(UnionFindNode {
-                parent: i as u32,
+                parent: i as Node,
                 rank: 0,
-                size: 1 as u32,
+                size: 1,
             });
         }
         Self { components }
     }
 
-    fn find(&self, x: u32) -> u32 {
+    fn find(&self, x: Node) -> Node {
         let mut root = self.components[x as usize].parent;
         while self.components[root as usize].parent != root {
             root = self.components[root as usize].parent;
@@ -946,7 +988,7 @@ impl UnionFind {
         root
     }
 
-    fn union(&mut self, x: u32, y: u32) {
+    fn union(&mut self, x: Node, y: Node) {
         let mut root_x = self.find(x);
         let mut root_y = self.find(y);
 
@@ -973,18 +1015,18 @@ impl UnionFind {
             .filter(|(id, ufn)| *id == ufn.parent as usize)
             .max_by_key(|(_, ufn)| ufn.size)
             .unwrap()
-            .0 as u32;
+            .0 as Node;
         LargestConnectedComponent { idx, uf: self }
     }
 }
 
 struct LargestConnectedComponent {
-    idx: u32,
+    idx: Node,
     uf: UnionFind,
 }
 
 impl LargestConnectedComponent {
-    fn is_in_lcc(&self, x: u32) -> bool {
+    fn is_in_lcc(&self, x: Node) -> bool {
         self.idx == self.uf.find(x)
     }
 }
@@ -1,33 +1,48 @@
+use crate::bounds::DiameterBounds;
+use crate::checkpoint::*;
+use crate::datasets::Metadata;
+use crate::distance::Distance;
 use crate::distributed_adjacencies::*;
 use crate::distributed_graph::*;
+use crate::eccentricities::*;
+use crate::node::Node;
 use crate::operators::*;
-use rand::distributions::Uniform;
-use rand::prelude::*;
-use rand_xoshiro::Xoshiro256StarStar;
+use crate::paths::*;
+use crate::vertex_program::*;
+use rand::SeedableRng;
+use rand_xoshiro::Xoroshiro128StarStar;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+use timely::communication::Allocate;
 use timely::dataflow::operators::*;
 use timely::dataflow::Scope;
 use timely::dataflow::Stream;
 use timely::order::Product;
 
-#[derive(Debug, Clone, Abomonation)]
-struct State {
-    distance: Option<u32>,
+#[derive(Debug, Clone, Abomonation, Serialize, Deserialize)]
+struct State<D> {
+    distance: Option<D>,
+    /// The neighbour the distance comes from, none for the root
+    parent: Option<Node>,
     updated: bool,
 }
 
-impl Default for State {
+impl<D> Default for State<D> {
     fn default() -> Self {
         Self {
             distance: None,
+            parent: None,
             updated: false,
         }
     }
 }
 
-impl State {
+impl<D: Distance> State<D> {
     fn root() -> Self {
         Self {
-            distance: Some(0),
+            distance: Some(D::zero()),
+            parent: None,
             updated: true,
         }
     }
@@ -54,160 +69,468 @@ impl State {
         }
     }
 
-    fn update_distance(&self, distance: u32) -> Self {
-        if let Some(prev_distance) = self.distance {
-            if distance < prev_distance {
-                Self {
-                    distance: Some(distance),
-                    updated: true,
-                }
-            } else {
-                Self {
-                    distance: Some(prev_distance),
-                    updated: false,
-                }
-            }
-        } else {
+    fn update_distance(&self, (distance, parent): (D, Node)) -> Self {
+        if self.distance.map(|prev| distance < prev).unwrap_or(true) {
             Self {
                 distance: Some(distance),
+                parent: Some(parent),
                 updated: true,
             }
+        } else {
+            Self {
+                updated: false,
+                ..self.clone()
+            }
         }
     }
 
-    fn send_light(step: u32, delta: u32, state: &Self, weight: u32) -> Option<u32> {
-        let bucket_limit = delta * (step + 1);
-        if weight <= delta && state.distance.expect("missing distance") <= bucket_limit {
-            Some(weight + state.distance.expect("missing distance"))
+    fn send_light(step: u32, delta: D, id: Node, state: &Self, weight: u32) -> Option<(D, Node)> {
+        let bucket_limit = delta.times(step + 1);
+        if D::from_u32(weight) <= delta && state.distance.expect("missing distance") <= bucket_limit
+        {
+            Some((
+                state.distance.expect("missing distance").add_weight(weight),
+                id,
+            ))
         } else {
             None
         }
     }
 
-    fn send_heavy(_step: u32, delta: u32, state: &Self, weight: u32) -> Option<u32> {
-        if weight > delta {
-            Some(weight + state.distance.expect("missing distance"))
+    fn send_heavy(_step: u32, delta: D, id: Node, state: &Self, weight: u32) -> Option<(D, Node)> {
+        if D::from_u32(weight) > delta {
+            Some((
+                state.distance.expect("missing distance").add_weight(weight),
+                id,
+            ))
         } else {
             None
         }
     }
 }
 
-fn delta_step<G: Scope<Timestamp = Product<(), u32>>>(
-    edges: &DistributedAdjacencies,
-    nodes: &Stream<G, (u32, State)>,
-    delta: u32,
-) -> Stream<G, (u32, State)> {
-    use timely::dataflow::operators::*;
-
-    let lightly_updated = nodes.scope().iterative::<u32, _, _>(|subscope| {
-        let nodes = nodes
-            .enter(subscope)
-            .map(|(id, state)| (id, state.reactivate()));
-        let (handle, cycle) = subscope.feedback(Product::new(Default::default(), 1));
-
-        let (output, further) = edges
-            .send(
-                &nodes.concat(&cycle),
-                true,
-                |_, state| state.should_send(),
-                move |time, state, weight| {
-                    State::send_light(time.outer.inner, delta, state, weight)
-                },
-                |d1, d2| std::cmp::min(*d1, *d2), // aggregate messages
-                |state, message| state.update_distance(*message),
-                |state| state.deactivate(), // deactivate nodes with no messages
-            )
-            .branch_all("updated", |_, pair| pair.1.updated, 0);
-
-        further
-            // .inspect(|p| info!("{:?}", p))
-            .connect_loop(handle);
-
-        output.leave()
-    });
+/// Relaxation of the light edges in the current bucket
+#[derive(Clone)]
+struct LightEdges<D> {
+    delta: D,
+}
+
+impl<D: Distance> VertexProgram<Product<Product<(), u32>, u32>> for LightEdges<D> {
+    type State = State<D>;
+    /// The distance through the sender, and the sender
+    type Message = (D, Node);
+
+    fn should_send(&self, _time: &Product<Product<(), u32>, u32>, state: &State<D>) -> bool {
+        state.should_send()
+    }
+
+    fn message(
+        &self,
+        time: &Product<Product<(), u32>, u32>,
+        id: Node,
+        state: &State<D>,
+        weight: u32,
+    ) -> Option<(D, Node)> {
+        State::send_light(time.outer.inner, self.delta, id, state, weight)
+    }
+
+    /// The shortest distance, and the smallest parent among the ties
+    fn combine(m1: &(D, Node), m2: &(D, Node)) -> (D, Node) {
+        std::cmp::min(*m1, *m2)
+    }
+
+    fn apply(
+        &self,
+        _time: &Product<Product<(), u32>, u32>,
+        state: &State<D>,
+        message: Option<&(D, Node)>,
+    ) -> State<D> {
+        match message {
+            Some(message) => state.update_distance(*message),
+            None => state.deactivate(), // deactivate nodes with no messages
+        }
+    }
+
+    fn is_active(&self, _time: &Product<Product<(), u32>, u32>, state: &State<D>) -> bool {
+        state.updated
+    }
+}
 
-    // do the heavy updates and return
-    edges.send(
-        &lightly_updated,
-        true,
-        |_, state| state.distance.is_some(),
-        move |time, state, weight| State::send_heavy(time.inner, delta, state, weight),
-        |d1, d2| std::cmp::min(*d1, *d2), // aggregate messages
-        |state, message| state.update_distance(*message),
-        // |state| state.deactivate(), // deactivate nodes with no messages
-        |state| state.clone(),
+/// Relaxation of the heavy edges, done once at the end of each bucket
+#[derive(Clone)]
+struct HeavyEdges<D> {
+    delta: D,
+}
+
+impl<D: Distance> VertexProgram<Product<(), u32>> for HeavyEdges<D> {
+    type State = State<D>;
+    /// The distance through the sender, and the sender
+    type Message = (D, Node);
+
+    fn should_send(&self, _time: &Product<(), u32>, state: &State<D>) -> bool {
+        state.distance.is_some()
+    }
+
+    fn message(
+        &self,
+        time: &Product<(), u32>,
+        id: Node,
+        state: &State<D>,
+        weight: u32,
+    ) -> Option<(D, Node)> {
+        State::send_heavy(time.inner, self.delta, id, state, weight)
+    }
+
+    fn combine(m1: &(D, Node), m2: &(D, Node)) -> (D, Node) {
+        std::cmp::min(*m1, *m2)
+    }
+
+    fn apply(
+        &self,
+        _time: &Product<(), u32>,
+        state: &State<D>,
+        message: Option<&(D, Node)>,
+    ) -> State<D> {
+        match message {
+            Some(message) => state.update_distance(*message),
+            None => state.clone(),
+        }
+    }
+
+    fn is_active(&self, _time: &Product<(), u32>, state: &State<D>) -> bool {
+        state.updated
+    }
+}
+
+/// Splits the nodes not settled yet, which may come more than once, into
+/// the ones beyond the bucket of the current step and the ones in it, which
+/// are reactivated to relax their light edges.
+fn split_at_bucket<G: Scope<Timestamp = Product<(), u32>>, D: Distance>(
+    adjacencies: &DistributedAdjacencies,
+    pending: &Stream<G, Node>,
+    states: Rc<RefCell<LocalStates<State<D>>>>,
+    delta: D,
+) -> (Stream<G, Node>, Stream<G, Node>) {
+    use timely::dataflow::channels::pact::Pipeline;
+    use timely::dataflow::operators::generic::operator::Operator;
+
+    let mut stash = adjacencies.round_buffers::<G::Timestamp, ()>();
+
+    let (beyond, bucket) = pending
+        .unary_notify(
+            Pipeline,
+            "split at bucket",
+            None,
+            move |input, output, notificator| {
+                notificator.for_each(|t, _, _| {
+                    let mut session = output.session(&t);
+                    let mut pending = stash.remove(t.time());
+                    let mut states = states.borrow_mut();
+                    let bucket_limit = delta.times(t.time().inner + 1);
+                    for (id, ()) in pending.drain() {
+                        let state = states.get(id).expect("missing state");
+                        let in_bucket = state.distance.expect("missing distance") <= bucket_limit;
+                        if in_bucket {
+                            let state = state.reactivate();
+                            states.insert(id, state);
+                        }
+                        session.give((id, in_bucket));
+                    }
+                    stash.recycle(pending);
+                });
+                input.for_each(|t, data| {
+                    let pending = stash.entry(t.time());
+                    for id in data.replace(Vec::new()).into_iter() {
+                        pending.insert(id, ());
+                    }
+                    notificator.notify_at(t.retain());
+                });
+            },
+        )
+        .branch(|_t, (_id, in_bucket)| *in_bucket);
+
+    (
+        beyond.map(|(id, _in_bucket)| id),
+        bucket.map(|(id, _in_bucket)| id),
     )
 }
 
-pub fn delta_stepping<A: timely::communication::Allocate>(
+/// How many light edges the nodes should have on average, with the
+/// automatically picked delta
+const LIGHT_DEGREE: f64 = 4.0;
+/// How many edge weights are sampled, among all the workers, to pick delta
+const WEIGHT_SAMPLES: usize = 10_000;
+
+/// Picks delta so that the nodes have `LIGHT_DEGREE` light edges on
+/// average, as the quantile of a sample of the edge weights of all the
+/// workers. Too small a delta leaves many nearly empty buckets, one after
+/// the other, while too large a one relaxes the same edges over and over
+/// within a bucket. Without samples, the weights are assumed to be uniform
+/// between the extremes in the metadata.
+pub fn auto_delta<A: Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    meta: &Metadata,
+    seed: u64,
+) -> u32 {
+    if meta.min_weight == meta.max_weight {
+        // All the edges are light, and each bucket is a level of a BFS
+        return meta.max_weight;
+    }
+    // Each edge is in the adjacencies of both its endpoints
+    let average_degree = 2.0 * meta.num_edges as f64 / meta.num_nodes as f64;
+    let light_fraction = (LIGHT_DEGREE / average_degree).min(1.0);
+
+    let mut rng = Xoroshiro128StarStar::seed_from_u64(seed.wrapping_add(worker.index() as u64));
+    let local_sample = adjacencies.sample_weights(WEIGHT_SAMPLES / worker.peers(), &mut rng);
+    let sample = Rc::new(RefCell::new(Vec::new()));
+    let sample_ref = Rc::clone(&sample);
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        local_sample
+            .to_stream(scope)
+            .broadcast()
+            .inspect(move |&w| sample_ref.borrow_mut().push(w))
+            .probe()
+    });
+    run_to_completion(worker, probe);
+
+    let mut sample = sample.replace(Vec::new());
+    let delta = if sample.is_empty() {
+        let range = (meta.max_weight - meta.min_weight) as f64;
+        meta.min_weight + (light_fraction * range) as u32
+    } else {
+        sample.sort_unstable();
+        sample[(light_fraction * (sample.len() - 1) as f64).round() as usize]
+    };
+    let delta = std::cmp::min(std::cmp::max(delta, meta.min_weight), meta.max_weight);
+    info!(
+        "Picked delta {} for an average degree of {:.2}, from {} sampled weights",
+        delta,
+        average_degree,
+        sample.len()
+    );
+    delta
+}
+
+pub fn delta_stepping<D: Distance, A: timely::communication::Allocate>(
     adjacencies: DistributedAdjacencies,
     worker: &mut timely::worker::Worker<A>,
     delta: u32,
-    n: u32,
+    n: Node,
     seed: u64,
-) -> (Option<u32>, std::time::Duration) {
-    let (diameter_box, probe) = worker.dataflow::<(), _, _>(|scope| {
-        let nodes = if scope.index() == 0 {
-            let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
-            let dist = Uniform::new(0u32, n + 1);
-            let root: u32 = dist.sample(&mut rng);
-
-            vec![(root, State::root())]
-        } else {
-            vec![]
+    checkpoints: &Checkpoints,
+    eccentricities: &Eccentricities,
+    paths: &Paths,
+) -> (Option<DiameterBounds<D>>, std::time::Duration) {
+    let root = random_root(&adjacencies, worker, n, seed);
+    let (farthest, tree, elapsed) = delta_stepping_from(
+        &adjacencies,
+        worker,
+        delta,
+        root,
+        checkpoints,
+        eccentricities.is_enabled() || paths.is_enabled(),
+    );
+
+    if let Some((eccentricity, _far)) = farthest.filter(|_| eccentricities.is_enabled()) {
+        let mut node_bounds = EccentricityBounds::new();
+        for (&id, &(distance, _parent)) in tree.iter() {
+            node_bounds.refine_from_source(id, distance.to_u64(), eccentricity.to_u64());
+        }
+        eccentricities.save(&node_bounds);
+    }
+    if let Some((_eccentricity, far)) = farthest.filter(|_| paths.is_enabled()) {
+        let path = trace_path(worker, tree, far);
+        if worker.index() == 0 {
+            paths.save(&path);
         }
-        .to_stream(scope)
-        .exchange(|p| p.0 as u64);
+    }
+
+    (
+        farthest.map(|(eccentricity, far)| DiameterBounds::from_sweep(eccentricity, root, far)),
+        elapsed,
+    )
+}
+
+/// Runs delta-stepping from the given root, returning its eccentricity
+/// along with the farthest node from it, on all the workers. If asked to,
+/// also returns the shortest paths tree restricted to the nodes of this worker.
+pub fn delta_stepping_from<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    delta: u32,
+    root: Node,
+    checkpoints: &Checkpoints,
+    keep_tree: bool,
+) -> (Option<(D, Node)>, ShortestPathsTree<D>, std::time::Duration) {
+    let delta = D::from_u32(delta);
+    let resume = checkpoints.resume_point(worker);
+
+    // The states stay on their worker across the buckets, and only the
+    // nodes that are not settled yet go around the loop
+    let states = Rc::new(RefCell::new(adjacencies.local_states::<State<D>>()));
+    let (pending, retired) = if let Some(round) = resume {
+        checkpoints.load::<State<D>>(round)
+    } else if root % worker.peers() as Node == worker.index() as Node {
+        (vec![(root, State::root())], vec![])
+    } else {
+        (vec![], vec![])
+    };
+    let pending: Vec<Node> = {
+        let mut states = states.borrow_mut();
+        for (id, state) in retired.into_iter() {
+            states.insert(id, state);
+        }
+        pending
+            .into_iter()
+            .map(|(id, state)| {
+                states.insert(id, state);
+                id
+            })
+            .collect()
+    };
+    let start = resume.map(|round| round + 1).unwrap_or(0);
+
+    let loop_states = Rc::clone(&states);
+    let probe = worker.dataflow::<(), _, _>(|scope| {
+        let pending = pending.to_stream(scope);
+        let checkpoints = checkpoints.clone();
 
         // Perform the delta steps, retiring at the end of each
-        // delta step the stable nodes
-        let distances = nodes.scope().iterative::<u32, _, _>(move |inner_scope| {
-            let nodes = nodes.enter(inner_scope);
-            let (handle, cycle) = inner_scope.feedback(Product::new(Default::default(), 1));
+        // delta step the nodes settled in its bucket
+        scope
+            .iterative::<u32, _, _>(move |inner_scope| {
+                let pending = pending.enter_at(inner_scope, move |_| start);
+                let (handle, cycle) = inner_scope.feedback(Product::new(Default::default(), 1));
 
-            let (stable, further) = delta_step(
-                &adjacencies,
-                &nodes.concat(&cycle),
-                // .inspect_batch(move |t, data| {
-                //     l1.log((CountEvent::Active(t.inner), data.len() as u64))
-                // }),
-                delta,
-            )
-            // We should also keep the old states because otherwise we enter
-            // and endless loop
-            .branch_all(
-                "updated",
-                move |t, (_id, state)| {
-                    state
-                        .distance
-                        .map(|d| d > delta * (t.inner + 1))
-                        .unwrap_or(true)
-                },
-                0,
-            );
+                let (waiting, bucket) = split_at_bucket(
+                    adjacencies,
+                    &pending.concat(&cycle),
+                    Rc::clone(&loop_states),
+                    delta,
+                );
 
-            further.connect_loop(handle);
+                let light_states = Rc::clone(&loop_states);
+                let distance = move |id: Node| {
+                    light_states
+                        .borrow()
+                        .get(id)
+                        .and_then(|state| state.distance)
+                        .expect("missing distance")
+                };
+                let earlier_distance = distance.clone();
+                let (beyond, reached) = run_until_quiescent_in(
+                    adjacencies,
+                    &bucket,
+                    Rc::clone(&loop_states),
+                    LightEdges { delta },
+                )
+                .branch(move |t, id| distance(*id) <= delta.times(t.inner + 1));
+                // The light edges also lead back to nodes settled in earlier buckets
+                let (_earlier, settled) = reached.branch(move |t, id| {
+                    t.inner == 0 || earlier_distance(*id) > delta.times(t.inner)
+                });
 
-            stable.leave()
-        });
+                // The heavy edges of each node are relaxed once, when it settles
+                let relaxed = adjacencies
+                    .send_frontier(&settled, Rc::clone(&loop_states), HeavyEdges { delta })
+                    .filter(|(_id, updated)| *updated)
+                    .map(|(id, _updated)| id);
 
-        distances
-            .map(|(_id, state)| state.distance.expect("unreached node"))
-            .accumulate(0, |max, data| {
-                *max = std::cmp::max(*data.iter().max().expect("empty collection"), *max)
+                waiting
+                    .concat(&beyond)
+                    .concat(&relaxed)
+                    .checkpoint(&settled, &loop_states, &checkpoints)
+                    .connect_loop(handle);
+
+                settled.leave()
             })
-            .inspect(|partial| info!("Partial maximum {:?}", partial))
+            .probe()
+    });
+    let mut elapsed = run_to_completion(worker, probe);
+
+    // Nodes out of the component of the root are never reached
+    let mut local_tree = ShortestPathsTree::new();
+    let mut local_farthest = (D::zero(), root);
+    for (id, state) in states.borrow().iter() {
+        if let Some(distance) = state.distance {
+            local_farthest = std::cmp::max(local_farthest, (distance, id));
+            if keep_tree {
+                local_tree.insert(id, (distance, state.parent));
+            }
+        }
+    }
+    info!("Partial maximum {:?}", local_farthest);
+
+    let (farthest_box, probe) = worker.dataflow::<(), _, _>(move |scope| {
+        vec![local_farthest]
+            .to_stream(scope)
             .exchange(|_| 0)
-            .accumulate(0, |max, data| {
+            .accumulate((D::zero(), root), |max, data| {
                 *max = std::cmp::max(*data.iter().max().expect("empty collection"), *max)
             })
+            .broadcast()
             .collect_single()
     });
+    elapsed += run_to_completion(worker, probe);
+
+    let farthest = farthest_box.borrow_mut().take();
+
+    (farthest, local_tree, elapsed)
+}
 
-    let elapsed = run_to_completion(worker, probe);
+#[test]
+fn test_delta_stepping_and_bfs_match_dijkstra() {
+    use crate::sequential::{dijkstra, init_neighbourhoods};
 
-    let diameter = diameter_box.borrow_mut().take();
+    // A weighted graph whose lightest paths take detours, and the isolated node 7
+    let edges: Vec<((Node, Node), u32)> = vec![
+        ((0, 1), 7),
+        ((0, 2), 1),
+        ((2, 1), 2),
+        ((1, 3), 1),
+        ((2, 4), 10),
+        ((3, 4), 3),
+        ((4, 5), 1),
+        ((3, 6), 8),
+    ];
+    let n = 8;
+    let root = 0;
 
-    (diameter, elapsed)
+    let weighted = dijkstra(&init_neighbourhoods(edges.clone(), n), root).0;
+    let hops = dijkstra(
+        &init_neighbourhoods(edges.iter().map(|&(e, _)| (e, 1u32)), n),
+        root,
+    )
+    .0;
+    let farthest = move |distances: &Vec<Option<u32>>| {
+        (0..n)
+            .filter_map(|id| distances[id as usize].map(|d| (d, id)))
+            .max()
+    };
+
+    timely::execute_directly(move |worker| {
+        let adjacencies = DistributedAdjacencies::from_edge_list(0, 1, n, &edges);
+        let checkpoints = Checkpoints::new(&std::env::temp_dir(), "none", 0, None, false);
+        for &delta in &[1, 3, 100] {
+            let (ecc, tree, _) = delta_stepping_from::<u32, _>(
+                &adjacencies,
+                worker,
+                delta,
+                root,
+                &checkpoints,
+                true,
+            );
+            assert_eq!(ecc, farthest(&weighted));
+            for id in 0..n {
+                assert_eq!(tree.get(&id).map(|&(d, _)| d), weighted[id as usize]);
+            }
+        }
+
+        let (ecc, tree, _) = crate::bfs::bfs_from(&adjacencies, worker, root, true);
+        assert_eq!(ecc, farthest(&hops));
+        for id in 0..n {
+            assert_eq!(tree.get(&id).map(|&(d, _)| d), hops[id as usize]);
+        }
+    });
 }
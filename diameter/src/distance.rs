@@ -0,0 +1,119 @@
+use crate::datasets::Metadata;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use timely::ExchangeData;
+
+/// The type of the distances between nodes, which are sums of edge weights.
+///
+/// Arithmetic is checked for overflow in debug builds, and wraps around
+/// in release builds: pick a type large enough for the graph with `fits_u32`.
+pub trait Distance:
+    ExchangeData + Copy + Ord + Hash + Default + Debug + Display + Serialize + DeserializeOwned
+{
+    fn zero() -> Self;
+
+    fn from_u32(x: u32) -> Self;
+
+    fn from_u64(x: u64) -> Self;
+
+    fn to_u64(self) -> u64;
+
+    fn plus(self, other: Self) -> Self;
+
+    fn times(self, k: u32) -> Self;
+
+    /// Like `times`, but stopping at the largest distance rather than
+    /// overflowing, for upper bounds that may not fit
+    fn saturating_times(self, k: u32) -> Self;
+
+    /// The distance obtained by following an edge of the given weight
+    fn add_weight<W: Weight>(self, weight: W) -> Self {
+        self.plus(Self::from_u64(weight.into()))
+    }
+}
+
+/// The type of the edge weights: 32 bits in the input graphs, and 64 bits
+/// in the auxiliary graphs of the clusterings, which are sums of distances.
+pub trait Weight: ExchangeData + Copy + Ord + Debug + Into<u64> {}
+
+impl Weight for u32 {}
+impl Weight for u64 {}
+
+macro_rules! impl_distance {
+    ($t:ty) => {
+        impl Distance for $t {
+            fn zero() -> Self {
+                0
+            }
+
+            fn from_u32(x: u32) -> Self {
+                x as $t
+            }
+
+            fn from_u64(x: u64) -> Self {
+                if cfg!(debug_assertions) {
+                    std::convert::TryFrom::try_from(x).expect("distance overflow")
+                } else {
+                    x as $t
+                }
+            }
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            #[inline]
+            fn plus(self, other: Self) -> Self {
+                if cfg!(debug_assertions) {
+                    self.checked_add(other).expect("distance overflow")
+                } else {
+                    self.wrapping_add(other)
+                }
+            }
+
+            #[inline]
+            fn times(self, k: u32) -> Self {
+                if cfg!(debug_assertions) {
+                    self.checked_mul(k as $t).expect("distance overflow")
+                } else {
+                    self.wrapping_mul(k as $t)
+                }
+            }
+
+            fn saturating_times(self, k: u32) -> Self {
+                self.saturating_mul(k as $t)
+            }
+        }
+    };
+}
+
+impl_distance!(u32);
+impl_distance!(u64);
+
+/// Whether the distances of the given graph fit in 32 bits, with room for
+/// the sum of two of them, as in the upper bounds doubling an eccentricity
+/// or in the limits of the delta-stepping buckets. This is the case if twice
+/// a path through all the nodes along the heaviest edges fits. The larger
+/// values that the clusterings derive from their distances are 64 bits wide.
+pub fn fits_u32(meta: &Metadata) -> bool {
+    2 * meta.max_weight as u64 * meta.num_nodes as u64 <= std::u32::MAX as u64
+}
+
+#[test]
+fn test_fits_u32() {
+    let meta = Metadata {
+        num_nodes: 1 << 16,
+        num_edges: 0,
+        min_weight: 1,
+        max_weight: (1 << 15) - 1,
+    };
+    assert!(fits_u32(&meta));
+    // A path through all the nodes fits, but not twice
+    let meta = Metadata {
+        max_weight: 1 << 15,
+        ..meta
+    };
+    assert!(!fits_u32(&meta));
+}
@@ -1,20 +1,76 @@
+use crate::distance::{Distance, Weight};
+use crate::node::Node;
+use crate::vertex_program::VertexProgram;
 use crate::Dataset;
 use bytes::Matrix;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use timely::dataflow::Scope;
 use timely::dataflow::Stream;
+use timely::order::Product;
+use timely::progress::Timestamp;
 use timely::ExchangeData;
 
-pub struct DistributedAdjacencies {
-    n: u32,
-    proc_id: u32,
-    num_processors: u32,
-    adjacencies: Rc<HashMap<u32, Vec<(u32, u32)>>>,
+/// The states of the nodes of this processor, one dense array for each
+/// timestamp of the enclosing scope, shared among the operators of a loop.
+pub type SharedStates<T, S> = Rc<RefCell<HashMap<T, LocalStates<S>>>>;
+
+/// Where `send_frontier` looks up and updates the states of the nodes of
+/// this processor, at each timestamp of the enclosing scope.
+pub trait StateStore<T, S> {
+    fn states(&self, time: &T) -> Option<&LocalStates<S>>;
+
+    /// The states of the given time, allocated with `allocate` if missing
+    fn states_mut<F: FnOnce() -> LocalStates<S>>(
+        &mut self,
+        time: &T,
+        allocate: F,
+    ) -> &mut LocalStates<S>;
+}
+
+impl<T: Timestamp, S> StateStore<T, S> for HashMap<T, LocalStates<S>> {
+    fn states(&self, time: &T) -> Option<&LocalStates<S>> {
+        self.get(time)
+    }
+
+    fn states_mut<F: FnOnce() -> LocalStates<S>>(
+        &mut self,
+        time: &T,
+        allocate: F,
+    ) -> &mut LocalStates<S> {
+        self.entry(time.clone()).or_insert_with(allocate)
+    }
+}
+
+/// A single array for all the timestamps, which must then come one after
+/// the other, with the states of each one carried over to the next.
+impl<T, S> StateStore<T, S> for LocalStates<S> {
+    fn states(&self, _time: &T) -> Option<&LocalStates<S>> {
+        Some(self)
+    }
+
+    fn states_mut<F: FnOnce() -> LocalStates<S>>(
+        &mut self,
+        _time: &T,
+        _allocate: F,
+    ) -> &mut LocalStates<S> {
+        self
+    }
+}
+
+/// The adjacencies of the nodes of this processor, with edges weighing
+/// 32 bits unless the graph is an auxiliary one, as in `rand_cluster`.
+pub struct DistributedAdjacencies<W = u32> {
+    n: Node,
+    proc_id: Node,
+    num_processors: Node,
+    adjacencies: Rc<HashMap<Node, Vec<(Node, W)>>>,
 }
 
 impl DistributedAdjacencies {
     pub fn from_edges(proc_id: u32, num_processors: u32, edges: &Dataset) -> Self {
+        let (proc_id, num_processors) = (proc_id as Node, num_processors as Node);
         let meta = edges.metadata();
         let n = meta.num_nodes;
         let m = meta.num_edges;
@@ -44,6 +100,70 @@ impl DistributedAdjacencies {
         }
     }
 
+    /// The adjacencies of the nodes of this processor in the graph on `n`
+    /// nodes with the given weighted edges
+    #[cfg(test)]
+    pub fn from_edge_list(
+        proc_id: u32,
+        num_processors: u32,
+        n: Node,
+        edges: &[((Node, Node), u32)],
+    ) -> Self {
+        let (proc_id, num_processors) = (proc_id as Node, num_processors as Node);
+        let mut adjacencies = HashMap::new();
+        for &((u, v), w) in edges {
+            if u % num_processors == proc_id {
+                adjacencies.entry(u).or_insert_with(Vec::new).push((v, w));
+            }
+            if v % num_processors == proc_id {
+                adjacencies.entry(v).or_insert_with(Vec::new).push((u, w));
+            }
+        }
+        Self {
+            n,
+            proc_id,
+            num_processors,
+            adjacencies: Rc::new(adjacencies),
+        }
+    }
+
+    /// Up to `count` weights of the edges of the nodes of this processor,
+    /// picked uniformly at random with reservoir sampling
+    pub fn sample_weights<R: rand::Rng>(&self, count: usize, rng: &mut R) -> Vec<u32> {
+        let mut sample = Vec::with_capacity(count);
+        let weights = self
+            .adjacencies
+            .values()
+            .flat_map(|edges| edges.iter().map(|&(_v, w)| w));
+        for (i, w) in weights.enumerate() {
+            if sample.len() < count {
+                sample.push(w);
+            } else {
+                let j = rng.gen_range(0, i + 1);
+                if j < count {
+                    sample[j] = w;
+                }
+            }
+        }
+        sample
+    }
+}
+
+impl<W: Weight> DistributedAdjacencies<W> {
+    /// A graph on the same nodes, partitioned among the processors in the
+    /// same way, with the given adjacencies of the nodes of this processor
+    pub fn with_adjacencies<V: Weight>(
+        &self,
+        adjacencies: HashMap<Node, Vec<(Node, V)>>,
+    ) -> DistributedAdjacencies<V> {
+        DistributedAdjacencies {
+            n: self.n,
+            proc_id: self.proc_id,
+            num_processors: self.num_processors,
+            adjacencies: Rc::new(adjacencies),
+        }
+    }
+
     pub fn clone(obj: &Self) -> Self {
         Self {
             n: obj.n,
@@ -53,39 +173,70 @@ impl DistributedAdjacencies {
         }
     }
 
-    pub fn nodes<G: Scope, S: ExchangeData + Default>(&self, scope: &mut G) -> Stream<G, (u32, S)> {
+    /// The neighbours of a node of this processor, with the weights of the
+    /// edges to them. Nodes without edges have no neighbours.
+    pub fn neighbours(&self, id: Node) -> &[(Node, W)] {
+        self.adjacencies
+            .get(&id)
+            .map(|edges| edges.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn nodes<G: Scope, S: ExchangeData + Default>(
+        &self,
+        scope: &mut G,
+    ) -> Stream<G, (Node, S)> {
         use timely::dataflow::operators::*;
 
         // We materialize the iterator to satisfy the borrow checker
-        let keys = self.adjacencies.keys().copied().collect::<Vec<u32>>();
+        let keys = self.adjacencies.keys().copied().collect::<Vec<Node>>();
         keys.to_stream(scope).map(|id| (id, S::default()))
     }
 
+    /// How many nodes of this processor have adjacencies
+    pub fn num_local_nodes(&self) -> usize {
+        self.adjacencies.len()
+    }
+
+    /// Allocates an empty dense array for the states of the nodes of this processor
+    pub fn local_states<S>(&self) -> LocalStates<S> {
+        let size = (self.n / self.num_processors + 1) as usize;
+        let mut states = Vec::with_capacity(size);
+        states.resize_with(size, || None);
+        LocalStates {
+            proc_id: self.proc_id,
+            num_processors: self.num_processors,
+            states,
+        }
+    }
+
+    /// Allocates the reusable per-round buffers for values indexed by the nodes of this processor
+    pub fn round_buffers<T: Timestamp, S>(&self) -> RoundBuffers<T, S> {
+        RoundBuffers {
+            proc_id: self.proc_id,
+            num_processors: self.num_processors,
+            n: self.n,
+            buffers: HashMap::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Runs a single round of the given vertex program: nodes send messages
+    /// along their edges, and update their state with the messages they receive.
     #[allow(unused)]
-    pub fn send<G: Scope, S: ExchangeData + Default, M: ExchangeData, P, Fm, Fa, Fu, Fun>(
+    pub fn send<G: Scope, P: VertexProgram<G::Timestamp, W>>(
         &self,
-        nodes: &Stream<G, (u32, S)>,
-        with_default: bool,
-        should_send: P,
-        message: Fm,
-        aggregate: Fa,
-        update: Fu,
-        update_no_msg: Fun,
-    ) -> Stream<G, (u32, S)>
-    where
-        // G::Timestamp: ToPair,
-        P: Fn(G::Timestamp, &S) -> bool + 'static,
-        Fm: Fn(G::Timestamp, &S, u32) -> Option<M> + 'static,
-        Fa: Fn(&M, &M) -> M + Copy + 'static,
-        Fu: Fn(&S, &M) -> S + 'static,
-        Fun: Fn(&S) -> S + 'static,
-    {
+        nodes: &Stream<G, (Node, P::State)>,
+        program: P,
+    ) -> Stream<G, (Node, P::State)> {
         use timely::dataflow::channels::pact::{Exchange as ExchangePact, Pipeline};
         use timely::dataflow::operators::*;
 
-        let adjs = Rc::clone(&self.adjacencies);
-        let mut message_stash = HashMap::new();
-        let mut node_stash = HashMap::new();
+        let adjacencies = Self::clone(&self);
+        let mut message_stash = self.round_buffers::<G::Timestamp, P::Message>();
+        let mut node_stash = self.round_buffers::<G::Timestamp, P::State>();
+        let with_default = program.with_default();
+        let sender = program.clone();
 
         nodes
             .unary(Pipeline, "send messages", move |_, _| {
@@ -94,9 +245,9 @@ impl DistributedAdjacencies {
                         let mut session = output.session(&t);
                         let data = data.replace(Vec::new());
                         for (id, state) in data.into_iter() {
-                            if should_send(t.time().clone(), &state) {
-                                for (dst, w) in adjs[&id].iter() {
-                                    if let Some(msg) = message(t.time().clone(), &state, *w) {
+                            if sender.should_send(t.time(), &state) {
+                                for (dst, w) in adjacencies.neighbours(id).iter() {
+                                    if let Some(msg) = sender.message(t.time(), id, &state, *w) {
                                         session.give((*dst, msg));
                                     }
                                 }
@@ -114,41 +265,150 @@ impl DistributedAdjacencies {
                 move |message_input, node_input, output, notificator| {
                     notificator.for_each(|t, _, _| {
                         let mut session = output.session(&t);
-                        let msgs = message_stash.remove(t.time()).unwrap_or_else(HashMap::new);
-                        let mut nodes = node_stash.remove(t.time()).unwrap_or_else(HashMap::new);
-                        for (id, message) in msgs.into_iter() {
-                            if let Some(state) = nodes.remove(&id) {
-                                session.give((id, update(&state, &message)));
+                        let mut msgs = message_stash.remove(t.time());
+                        let mut nodes = node_stash.remove(t.time());
+                        for (id, message) in msgs.drain() {
+                            if let Some(state) = nodes.remove(id) {
+                                session.give((id, program.apply(t.time(), &state, Some(&message))));
                             } else if with_default {
                                 let state = Default::default();
-                                session.give((id, update(&state, &message)));
+                                session.give((id, program.apply(t.time(), &state, Some(&message))));
                             }
                         }
                         // Exhaust un-messaged nodes
                         for (id, state) in nodes.drain() {
-                            session.give((id, update_no_msg(&state)));
+                            session.give((id, program.apply(t.time(), &state, None)));
                         }
+                        message_stash.recycle(msgs);
+                        node_stash.recycle(nodes);
                     });
 
                     message_input.for_each(|t, data| {
                         let data = data.replace(Vec::new());
-                        let mut stash = message_stash
-                            .entry(t.time().clone())
-                            .or_insert_with(HashMap::new);
+                        let stash = message_stash.entry(t.time());
                         for (id, msg) in data.into_iter() {
-                            stash
-                                .entry(id)
-                                .and_modify(|acc| *acc = aggregate(acc, &msg))
-                                .or_insert(msg);
+                            stash.merge(id, msg, P::combine);
                         }
                         notificator.notify_at(t.retain());
                     });
 
                     node_input.for_each(|t, data| {
                         let data = data.replace(Vec::new());
-                        node_stash
+                        let stash = node_stash.entry(t.time());
+                        for (id, state) in data.into_iter() {
+                            stash.insert(id, state);
+                        }
+                        notificator.notify_at(t.retain());
+                    });
+                },
+            )
+    }
+
+    /// Runs a round of the given vertex program only on the nodes in the frontier.
+    ///
+    /// The states of the nodes are not part of the stream: they are looked up
+    /// in, and updated into, the given local arrays. Only node identifiers and
+    /// messages travel through the dataflow. The output contains all the nodes
+    /// touched in this round (i.e. that were in the frontier or that got a message),
+    /// each paired with a flag telling whether it is still active.
+    pub fn send_frontier<G, T, P, St>(
+        &self,
+        frontier: &Stream<G, Node>,
+        states: Rc<RefCell<St>>,
+        program: P,
+    ) -> Stream<G, (Node, bool)>
+    where
+        T: Timestamp,
+        G: Scope<Timestamp = Product<T, u32>>,
+        P: VertexProgram<Product<T, u32>, W>,
+        St: StateStore<T, P::State> + 'static,
+    {
+        use timely::dataflow::channels::pact::{Exchange as ExchangePact, Pipeline};
+        use timely::dataflow::operators::*;
+
+        let adjacencies = Self::clone(&self);
+        let allocator = Self::clone(&self);
+        let sender_states = Rc::clone(&states);
+        let mut message_stash = self.round_buffers::<Product<T, u32>, P::Message>();
+        let mut frontier_stash = HashMap::new();
+        let with_default = program.with_default();
+        let sender = program.clone();
+
+        frontier
+            .unary(Pipeline, "send messages", move |_, _| {
+                move |input, output| {
+                    input.for_each(|t, data| {
+                        let mut session = output.session(&t);
+                        let data = data.replace(Vec::new());
+                        let states = sender_states.borrow();
+                        let local_states = states
+                            .states(&t.time().outer)
+                            .expect("missing states for the frontier");
+                        for id in data.into_iter() {
+                            let state = local_states.get(id).expect("missing state");
+                            if sender.should_send(t.time(), state) {
+                                for (dst, w) in adjacencies.neighbours(id).iter() {
+                                    if let Some(msg) = sender.message(t.time(), id, state, *w) {
+                                        session.give((*dst, msg));
+                                    }
+                                }
+                            }
+                        }
+                    })
+                }
+            })
+            .binary_notify(
+                &frontier,
+                ExchangePact::new(|(id, _msg)| *id as u64),
+                Pipeline,
+                "apply messages",
+                None,
+                move |message_input, frontier_input, output, notificator| {
+                    notificator.for_each(|t, _, _| {
+                        let mut session = output.session(&t);
+                        let mut msgs = message_stash.remove(t.time());
+                        let frontier = frontier_stash.remove(t.time()).unwrap_or_else(Vec::new);
+                        let mut states = states.borrow_mut();
+                        let local_states =
+                            states.states_mut(&t.time().outer, || allocator.local_states());
+                        // Nodes of the frontier with no messages
+                        for id in frontier.into_iter() {
+                            if !msgs.contains(id) {
+                                let state = local_states.get(id).expect("missing state");
+                                let state = program.apply(t.time(), state, None);
+                                session.give((id, program.is_active(t.time(), &state)));
+                                local_states.insert(id, state);
+                            }
+                        }
+                        for (id, message) in msgs.drain() {
+                            let state = match local_states.get(id) {
+                                Some(state) => program.apply(t.time(), state, Some(&message)),
+                                None if with_default => {
+                                    let state = Default::default();
+                                    program.apply(t.time(), &state, Some(&message))
+                                }
+                                None => continue,
+                            };
+                            session.give((id, program.is_active(t.time(), &state)));
+                            local_states.insert(id, state);
+                        }
+                        message_stash.recycle(msgs);
+                    });
+
+                    message_input.for_each(|t, data| {
+                        let data = data.replace(Vec::new());
+                        let stash = message_stash.entry(t.time());
+                        for (id, msg) in data.into_iter() {
+                            stash.merge(id, msg, P::combine);
+                        }
+                        notificator.notify_at(t.retain());
+                    });
+
+                    frontier_input.for_each(|t, data| {
+                        let data = data.replace(Vec::new());
+                        frontier_stash
                             .entry(t.time().clone())
-                            .or_insert_with(HashMap::new)
+                            .or_insert_with(Vec::new)
                             .extend(data.into_iter());
                         notificator.notify_at(t.retain());
                     });
@@ -156,27 +416,65 @@ impl DistributedAdjacencies {
             )
     }
 
+    /// Relaxes the given distance proposals as soon as they arrive, without
+    /// waiting for the other proposals of the same round. Nodes whose distance
+    /// improves propose new distances to their neighbours, which are returned.
+    /// The distances are kept in the given shared array.
+    pub fn relax<G: Scope, D: Distance>(
+        &self,
+        proposals: &Stream<G, (Node, D)>,
+        distances: Rc<RefCell<LocalStates<D>>>,
+    ) -> Stream<G, (Node, D)> {
+        use timely::dataflow::channels::pact::Exchange as ExchangePact;
+        use timely::dataflow::operators::*;
+
+        let adjacencies = Self::clone(&self);
+
+        proposals.unary(
+            ExchangePact::new(|(id, _dist)| *id as u64),
+            "relax",
+            move |_, _| {
+                move |input, output| {
+                    input.for_each(|t, data| {
+                        let mut session = output.session(&t);
+                        let data = data.replace(Vec::new());
+                        let mut distances = distances.borrow_mut();
+                        for (id, dist) in data.into_iter() {
+                            let improved = distances.get(id).map(|d| dist < *d).unwrap_or(true);
+                            if improved {
+                                distances.insert(id, dist);
+                                for (dst, w) in adjacencies.neighbours(id).iter() {
+                                    session.give((*dst, dist.add_weight(*w)));
+                                }
+                            }
+                        }
+                    })
+                }
+            },
+        )
+    }
+
     /// Brings together the states of the endpoints of each edge with the edge itself.
+    ///
+    /// Each node sends its state along its edges, so that the triplet is put
+    /// together on the processor of the other endpoint, whose state is there
+    /// in a dense buffer, like the states sent along the same edges by `send`.
     #[allow(unused)]
     pub fn triplets<G: Scope, S: ExchangeData, F, O>(
         &self,
-        nodes: &Stream<G, (u32, S)>,
+        nodes: &Stream<G, (Node, S)>,
         action: F,
     ) -> Stream<G, O>
     where
-        F: Fn(((u32, S), (u32, S), u32)) -> Option<O> + 'static,
+        F: Fn(((Node, S), (Node, S), W)) -> Option<O> + 'static,
         O: ExchangeData,
     {
         use timely::dataflow::channels::pact::{Exchange as ExchangePact, Pipeline};
         use timely::dataflow::operators::*;
 
-        let adjs = Rc::clone(&self.adjacencies);
-        let adjs2 = Rc::clone(&self.adjacencies);
-        let mut processor_targets = Vec::new();
-        let mut message_stash = HashMap::new();
-        let mut node_stash: HashMap<G::Timestamp, Vec<(u32, S)>> = HashMap::new();
-
-        let peers = nodes.scope().peers() as u32;
+        let sender = Self::clone(&self);
+        let mut message_stash = self.round_buffers::<G::Timestamp, Vec<(Node, S, W)>>();
+        let mut node_stash = self.round_buffers::<G::Timestamp, S>();
 
         nodes
             .unary(Pipeline, "send states", move |_, _| {
@@ -185,12 +483,8 @@ impl DistributedAdjacencies {
                         let mut session = output.session(&t);
                         let data = data.replace(Vec::new());
                         for (id, state) in data.into_iter() {
-                            processor_targets.clear();
-                            processor_targets.extend(adjs[&id].iter().map(|pair| pair.0 % peers));
-                            processor_targets.sort();
-                            processor_targets.dedup();
-                            for proc in &processor_targets {
-                                session.give((*proc, (id, state.clone())));
+                            for &(dst, w) in sender.neighbours(id).iter() {
+                                session.give((dst, (id, state.clone(), w)));
                             }
                         }
                     })
@@ -198,50 +492,512 @@ impl DistributedAdjacencies {
             })
             .binary_notify(
                 &nodes,
-                ExchangePact::new(|(id, _msg)| *id as u64),
+                ExchangePact::new(|(dst, _msg)| *dst as u64),
                 Pipeline,
-                "exchange messages",
+                "exchange states",
                 None,
                 move |message_input, node_input, output, notificator| {
                     notificator.for_each(|t, _, _| {
                         let mut session = output.session(&t);
-                        let other_states: HashMap<u32, S> =
-                            message_stash.remove(t.time()).expect("missing state");
-                        let mut nodes = node_stash.remove(t.time()).expect("missing nodes");
-                        for (src, src_state) in nodes.into_iter() {
-                            for (dst, w) in adjs2[&src].iter() {
-                                let dst_state = &other_states[dst];
-                                if let Some(res) = action((
-                                    (src, src_state.clone()),
-                                    (*dst, dst_state.clone()),
-                                    *w,
-                                )) {
+                        let mut msgs = message_stash.remove(t.time());
+                        let nodes = node_stash.remove(t.time());
+                        for (dst, incoming) in msgs.drain() {
+                            let dst_state = nodes.get(dst).expect("missing state");
+                            for (src, src_state, w) in incoming.into_iter() {
+                                if let Some(res) =
+                                    action(((src, src_state), (dst, dst_state.clone()), w))
+                                {
                                     session.give(res);
                                 }
                             }
                         }
+                        message_stash.recycle(msgs);
+                        node_stash.recycle(nodes);
                     });
 
                     message_input.for_each(|t, data| {
                         let data = data.replace(Vec::new());
-                        let mut stash = message_stash
-                            .entry(t.time().clone())
-                            .or_insert_with(HashMap::new);
-                        for (_proc, (id, msg)) in data.into_iter() {
-                            stash.insert(id, msg);
+                        let stash = message_stash.entry(t.time());
+                        for (dst, msg) in data.into_iter() {
+                            stash.get_or_insert_with(dst, Vec::new).push(msg);
                         }
                         notificator.notify_at(t.retain());
                     });
 
                     node_input.for_each(|t, data| {
                         let data = data.replace(Vec::new());
-                        node_stash
-                            .entry(t.time().clone())
-                            .or_insert_with(Vec::new)
-                            .extend(data.into_iter());
+                        let stash = node_stash.entry(t.time());
+                        for (id, state) in data.into_iter() {
+                            stash.insert(id, state);
+                        }
                         notificator.notify_at(t.retain());
                     });
                 },
             )
     }
 }
+
+/// Dense array holding the states of the nodes assigned to a processor,
+/// indexed by their local ordinal `id / num_processors`.
+pub struct LocalStates<S> {
+    proc_id: Node,
+    num_processors: Node,
+    states: Vec<Option<S>>,
+}
+
+impl<S> LocalStates<S> {
+    /// The slot of the given node, which must belong to this processor:
+    /// the node of another one would silently take the slot of a local node
+    fn ordinal(&self, id: Node) -> usize {
+        assert!(
+            id % self.num_processors == self.proc_id,
+            "node {} does not belong to processor {}",
+            id,
+            self.proc_id
+        );
+        (id / self.num_processors) as usize
+    }
+
+    pub fn get(&self, id: Node) -> Option<&S> {
+        self.states[self.ordinal(id)].as_ref()
+    }
+
+    pub fn insert(&mut self, id: Node, state: S) {
+        let idx = self.ordinal(id);
+        self.states[idx] = Some(state);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Node, &S)> {
+        let proc_id = self.proc_id;
+        let num_processors = self.num_processors;
+        self.states
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, state)| {
+                state
+                    .as_ref()
+                    .map(|s| (i as Node * num_processors + proc_id, s))
+            })
+    }
+
+    /// Consumes the array, iterating over the nodes that have a state
+    pub fn into_states(self) -> impl Iterator<Item = (Node, S)> {
+        let proc_id = self.proc_id;
+        let num_processors = self.num_processors;
+        self.states
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(i, state)| state.map(|s| (i as Node * num_processors + proc_id, s)))
+    }
+}
+
+/// Dense buffer holding at most one value for each node of a processor,
+/// indexed by local ordinal. The ordinals of the occupied slots are tracked
+/// separately, so that draining and clearing cost time proportional to
+/// the number of values, rather than to the number of nodes.
+pub struct RoundBuffer<S> {
+    proc_id: Node,
+    num_processors: Node,
+    slots: Vec<Option<S>>,
+    occupied: Vec<usize>,
+    len: usize,
+}
+
+impl<S> RoundBuffer<S> {
+    fn new(proc_id: Node, num_processors: Node, n: Node) -> Self {
+        let size = (n / num_processors + 1) as usize;
+        let mut slots = Vec::with_capacity(size);
+        slots.resize_with(size, || None);
+        Self {
+            proc_id,
+            num_processors,
+            slots,
+            occupied: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// The slot of the given node, which must belong to this processor:
+    /// the node of another one would silently take the slot of a local node
+    fn ordinal(&self, id: Node) -> usize {
+        assert!(
+            id % self.num_processors == self.proc_id,
+            "node {} does not belong to processor {}",
+            id,
+            self.proc_id
+        );
+        (id / self.num_processors) as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, id: Node) -> bool {
+        self.slots[self.ordinal(id)].is_some()
+    }
+
+    pub fn get(&self, id: Node) -> Option<&S> {
+        self.slots[self.ordinal(id)].as_ref()
+    }
+
+    /// Sets the value of the given node, replacing the previous one
+    pub fn insert(&mut self, id: Node, value: S) {
+        let idx = self.ordinal(id);
+        if self.slots[idx].replace(value).is_none() {
+            self.occupied.push(idx);
+            self.len += 1;
+        }
+    }
+
+    /// Sets the value of the given node, combining it with the previous one, if any
+    pub fn merge<F: Fn(&S, &S) -> S>(&mut self, id: Node, value: S, combine: F) {
+        let idx = self.ordinal(id);
+        if let Some(prev) = self.slots[idx].as_mut() {
+            *prev = combine(prev, &value);
+        } else {
+            self.slots[idx] = Some(value);
+            self.occupied.push(idx);
+            self.len += 1;
+        }
+    }
+
+    /// The value of the given node, set with `default` if missing
+    pub fn get_or_insert_with<F: FnOnce() -> S>(&mut self, id: Node, default: F) -> &mut S {
+        let idx = self.ordinal(id);
+        if self.slots[idx].is_none() {
+            self.slots[idx] = Some(default());
+            self.occupied.push(idx);
+            self.len += 1;
+        }
+        self.slots[idx].as_mut().expect("missing value")
+    }
+
+    pub fn remove(&mut self, id: Node) -> Option<S> {
+        let idx = self.ordinal(id);
+        let value = self.slots[idx].take();
+        if value.is_some() {
+            self.len -= 1;
+        }
+        value
+    }
+
+    /// Removes all the values, in insertion order, leaving the buffer empty
+    /// even if the iterator is dropped before the end
+    pub fn drain(&mut self) -> Drain<'_, S> {
+        self.len = 0;
+        Drain {
+            proc_id: self.proc_id,
+            num_processors: self.num_processors,
+            slots: &mut self.slots,
+            occupied: self.occupied.drain(..),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for idx in self.occupied.drain(..) {
+            self.slots[idx] = None;
+        }
+        self.len = 0;
+    }
+}
+
+/// The iterator of `RoundBuffer::drain`, which drops the values it does
+/// not get to, so that no slot is left occupied without being tracked.
+pub struct Drain<'a, S> {
+    proc_id: Node,
+    num_processors: Node,
+    slots: &'a mut Vec<Option<S>>,
+    occupied: std::vec::Drain<'a, usize>,
+}
+
+impl<'a, S> Iterator for Drain<'a, S> {
+    type Item = (Node, S);
+
+    fn next(&mut self) -> Option<(Node, S)> {
+        // Removed values leave a stale ordinal behind, which is simply skipped
+        while let Some(idx) = self.occupied.next() {
+            if let Some(value) = self.slots[idx].take() {
+                return Some((idx as Node * self.num_processors + self.proc_id, value));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, S> Drop for Drain<'a, S> {
+    fn drop(&mut self) {
+        for idx in &mut self.occupied {
+            self.slots[idx] = None;
+        }
+    }
+}
+
+/// The round buffers of an operator, one for each timestamp in flight.
+/// Buffers of completed rounds are recycled, so that after the first few
+/// rounds no allocation takes place.
+pub struct RoundBuffers<T, S> {
+    proc_id: Node,
+    num_processors: Node,
+    n: Node,
+    buffers: HashMap<T, RoundBuffer<S>>,
+    free: Vec<RoundBuffer<S>>,
+}
+
+impl<T: Timestamp, S> RoundBuffers<T, S> {
+    fn allocate(&mut self) -> RoundBuffer<S> {
+        let (proc_id, num_processors, n) = (self.proc_id, self.num_processors, self.n);
+        self.free
+            .pop()
+            .unwrap_or_else(|| RoundBuffer::new(proc_id, num_processors, n))
+    }
+
+    /// The buffer of the given time, allocated if needed
+    pub fn entry(&mut self, time: &T) -> &mut RoundBuffer<S> {
+        if !self.buffers.contains_key(time) {
+            let buffer = self.allocate();
+            self.buffers.insert(time.clone(), buffer);
+        }
+        self.buffers.get_mut(time).expect("missing buffer")
+    }
+
+    /// Takes out the buffer of the given time, which is empty if no value
+    /// was ever inserted for it. Give it back with `recycle` once done.
+    pub fn remove(&mut self, time: &T) -> RoundBuffer<S> {
+        match self.buffers.remove(time) {
+            Some(buffer) => buffer,
+            None => self.allocate(),
+        }
+    }
+
+    pub fn recycle(&mut self, mut buffer: RoundBuffer<S>) {
+        buffer.clear();
+        self.free.push(buffer);
+    }
+}
+
+#[test]
+fn test_round_buffer_drain_dropped_early() {
+    let mut buffer: RoundBuffer<u32> = RoundBuffer::new(1, 2, 10);
+    for &id in &[1, 3, 5, 7] {
+        buffer.insert(id, id * 10);
+    }
+    buffer.remove(3);
+    assert_eq!(buffer.drain().next(), Some((1, 10)));
+    // The values left behind by the dropped iterator are gone as well
+    assert!(buffer.is_empty());
+    assert_eq!(buffer.get(5), None);
+    buffer.insert(5, 51);
+    assert_eq!(buffer.drain().collect::<Vec<_>>(), vec![(5, 51)]);
+}
+
+#[test]
+#[should_panic(expected = "does not belong")]
+fn test_round_buffer_rejects_other_processors() {
+    let mut buffer: RoundBuffer<u32> = RoundBuffer::new(1, 2, 10);
+    buffer.insert(2, 20);
+}
+
+#[cfg(test)]
+mod benches {
+    use super::*;
+
+    /// Compares the per-round cost of stashing and draining messages with
+    /// nested hash maps and with the recycled dense buffers.
+    /// Run with `RUST_LOG=info cargo test --release bench_round_buffers -- --ignored`
+    #[test]
+    #[ignore]
+    fn bench_round_buffers() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        use rand::prelude::*;
+        use rand_xoshiro::Xoshiro256StarStar;
+        use std::time::Instant;
+
+        let num_processors = 4;
+        let proc_id = 1;
+        let n = 10_000_000;
+        let rounds = 50;
+
+        let mut rng = Xoshiro256StarStar::seed_from_u64(1234);
+        for &messages in &[10_000usize, 100_000, 1_000_000] {
+            let batches: Vec<Vec<(Node, u32)>> = (0..rounds)
+                .map(|_| {
+                    (0..messages)
+                        .map(|_| {
+                            let ordinal = rng.gen_range(0, n / num_processors);
+                            (ordinal * num_processors + proc_id, rng.gen::<u32>())
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let mut check_hash = 0u64;
+            let mut hash_stash: HashMap<u32, HashMap<Node, u32>> = HashMap::new();
+            let timer = Instant::now();
+            for (round, batch) in batches.iter().enumerate() {
+                let stash = hash_stash.entry(round as u32).or_insert_with(HashMap::new);
+                for &(id, msg) in batch.iter() {
+                    stash
+                        .entry(id)
+                        .and_modify(|acc| *acc = std::cmp::min(*acc, msg))
+                        .or_insert(msg);
+                }
+                let stash = hash_stash.remove(&(round as u32)).expect("missing round");
+                for (id, msg) in stash.into_iter() {
+                    check_hash += id as u64 ^ msg as u64;
+                }
+            }
+            let hash_elapsed = timer.elapsed() / rounds;
+
+            let mut check_dense = 0u64;
+            let mut dense_stash: RoundBuffers<u32, u32> = RoundBuffers {
+                proc_id,
+                num_processors,
+                n,
+                buffers: HashMap::new(),
+                free: Vec::new(),
+            };
+            let timer = Instant::now();
+            for (round, batch) in batches.iter().enumerate() {
+                let stash = dense_stash.entry(&(round as u32));
+                for &(id, msg) in batch.iter() {
+                    stash.merge(id, msg, |a, b| std::cmp::min(*a, *b));
+                }
+                let mut stash = dense_stash.remove(&(round as u32));
+                for (id, msg) in stash.drain() {
+                    check_dense += id as u64 ^ msg as u64;
+                }
+                dense_stash.recycle(stash);
+            }
+            let dense_elapsed = timer.elapsed() / rounds;
+
+            assert_eq!(check_hash, check_dense);
+            info!(
+                "{:>8} messages per round: hash maps {:?}/round, round buffers {:?}/round",
+                messages, hash_elapsed, dense_elapsed
+            );
+        }
+    }
+
+    /// Compares the per-round cost of putting together the triplets of the
+    /// edges of a processor with the remote states in a sorted array, looked up
+    /// from the local endpoints, and with the states sent along the edges and
+    /// grouped by local endpoint in the recycled dense buffers.
+    /// Run with `RUST_LOG=info cargo test --release bench_triplet_buffers -- --ignored`
+    #[test]
+    #[ignore]
+    fn bench_triplet_buffers() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        use crate::distributed_graph::ArrayMap;
+        use rand::prelude::*;
+        use rand_xoshiro::Xoshiro256StarStar;
+        use std::time::Instant;
+
+        let num_processors = 4;
+        let proc_id = 1;
+        let n = 10_000_000;
+        let local_nodes = 100_000;
+        let rounds = 10;
+
+        let mut rng = Xoshiro256StarStar::seed_from_u64(1234);
+        let state = |id: Node| id.wrapping_mul(2_654_435_761);
+        let local: Vec<Node> = (0..local_nodes)
+            .map(|ordinal| ordinal * num_processors + proc_id)
+            .collect();
+        for &degree in &[4usize, 16, 64] {
+            // Edges from the local nodes to arbitrary ones
+            let mut adjacencies: HashMap<Node, Vec<(Node, u32)>> = HashMap::new();
+            for &u in local.iter() {
+                let edges = (0..degree)
+                    .map(|_| (rng.gen_range(0, n), rng.gen::<u32>() % 100))
+                    .collect();
+                adjacencies.insert(u, edges);
+            }
+            let mut remote: Vec<Node> = adjacencies
+                .values()
+                .flat_map(|edges| edges.iter().map(|&(v, _w)| v))
+                .collect();
+            remote.sort_unstable();
+            remote.dedup();
+            let triplet = |u: Node, v: Node, w: u32| {
+                (state(u) ^ state(v)) as u64 + w as u64 + (u as u64 ^ v as u64)
+            };
+
+            let mut check_array = 0u64;
+            let mut array_stash: HashMap<u32, Vec<(Node, Node)>> = HashMap::new();
+            let timer = Instant::now();
+            for round in 0..rounds {
+                array_stash
+                    .entry(round)
+                    .or_insert_with(Vec::new)
+                    .extend(remote.iter().map(|&v| (v, state(v))));
+                let other_states =
+                    ArrayMap::new(array_stash.remove(&round).expect("missing round"));
+                for &u in local.iter() {
+                    for &(v, w) in adjacencies[&u].iter() {
+                        let v_state = other_states.get(v).expect("missing state");
+                        check_array +=
+                            (state(u) ^ v_state) as u64 + w as u64 + (u as u64 ^ v as u64);
+                    }
+                }
+            }
+            let array_elapsed = timer.elapsed() / rounds;
+
+            let mut check_dense = 0u64;
+            fn buffers<S>(proc_id: Node, num_processors: Node, n: Node) -> RoundBuffers<u32, S> {
+                RoundBuffers {
+                    proc_id,
+                    num_processors,
+                    n,
+                    buffers: HashMap::new(),
+                    free: Vec::new(),
+                }
+            }
+            let mut node_stash: RoundBuffers<u32, Node> = buffers(proc_id, num_processors, n);
+            let mut message_stash: RoundBuffers<u32, Vec<(Node, Node, u32)>> =
+                buffers(proc_id, num_processors, n);
+            let timer = Instant::now();
+            for round in 0..rounds {
+                let stash = node_stash.entry(&round);
+                for &u in local.iter() {
+                    stash.insert(u, state(u));
+                }
+                // The same states, as sent along the edges towards the local endpoints
+                let stash = message_stash.entry(&round);
+                for (&u, edges) in adjacencies.iter() {
+                    for &(v, w) in edges.iter() {
+                        stash.get_or_insert_with(u, Vec::new).push((v, state(v), w));
+                    }
+                }
+                let mut msgs = message_stash.remove(&round);
+                let nodes = node_stash.remove(&round);
+                for (u, incoming) in msgs.drain() {
+                    let u_state = nodes.get(u).expect("missing state");
+                    for (v, v_state, w) in incoming.into_iter() {
+                        check_dense +=
+                            (u_state ^ v_state) as u64 + w as u64 + (u as u64 ^ v as u64);
+                    }
+                }
+                message_stash.recycle(msgs);
+                node_stash.recycle(nodes);
+            }
+            let dense_elapsed = timer.elapsed() / rounds;
+
+            let expected: u64 = adjacencies
+                .iter()
+                .flat_map(|(&u, edges)| edges.iter().map(move |&(v, w)| triplet(u, v, w)))
+                .sum::<u64>()
+                * rounds as u64;
+            assert_eq!(check_array, expected);
+            assert_eq!(check_dense, expected);
+            info!(
+                "{:>3} edges per node: sorted array {:?}/round, round buffers {:?}/round",
+                degree, array_elapsed, dense_elapsed
+            );
+        }
+    }
+}
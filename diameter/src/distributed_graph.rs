@@ -1,3 +1,4 @@
+use crate::node::Node;
 use bytes::*;
 use timely::communication::Push;
 use timely::dataflow::channels::pushers::buffer::Session;
@@ -13,14 +14,14 @@ use timely::dataflow::Stream;
 use timely::ExchangeData;
 
 pub struct DistributedEdgesBuilder {
-    edges: Rc<RefCell<Option<CompressedEdgesBlockSet>>>,
-    proc_neighs: Rc<RefCell<HashMap<u32, Vec<usize>>>>,
+    edges: Rc<RefCell<Option<CompressedEdgesBlockSet<Node>>>>,
+    proc_neighs: Rc<RefCell<HashMap<Node, Vec<usize>>>>,
     num_procs: usize,
 }
 
 impl DistributedEdgesBuilder {
     pub fn new<G: Scope>(
-        arrangement: Matrix,
+        arrangement: Matrix<Node>,
         load_type: LoadType,
         stream: &Stream<G, (String, Option<String>)>,
     ) -> (Self, ProbeHandle<G::Timestamp>) {
@@ -77,7 +78,7 @@ impl DistributedEdgesBuilder {
                 },
             )
             .unary_notify(
-                ExchangePact::new(|pair: &(u32, usize)| pair.0 as u64),
+                ExchangePact::new(|pair: &(Node, usize)| pair.0 as u64),
                 "neighborhoods builder",
                 None,
                 move |input, output, notificator| {
@@ -120,15 +121,15 @@ impl DistributedEdgesBuilder {
 
 /// A distributed static collection of edges that can be accessed by
 pub struct DistributedEdges {
-    edges: Rc<CompressedEdgesBlockSet>,
-    procs_neighs: Rc<HashMap<u32, Vec<usize>>>,
+    edges: Rc<CompressedEdgesBlockSet<Node>>,
+    procs_neighs: Rc<HashMap<Node, Vec<usize>>>,
     num_procs: usize,
 }
 
 impl DistributedEdges {
     fn new(
-        edges: Rc<CompressedEdgesBlockSet>,
-        procs_neighs: Rc<HashMap<u32, Vec<usize>>>,
+        edges: Rc<CompressedEdgesBlockSet<Node>>,
+        procs_neighs: Rc<HashMap<Node, Vec<usize>>>,
         num_procs: usize,
     ) -> Self {
         Self {
@@ -148,13 +149,16 @@ impl DistributedEdges {
 
     pub fn for_each<F>(&self, action: F)
     where
-        F: FnMut(u32, u32, u32),
+        F: FnMut(Node, Node, u32),
     {
         self.edges.for_each(action);
         // info!("time to iterate over all the edges {:?}", timer.elapsed());
     }
 
-    pub fn nodes<G: Scope, S: ExchangeData + Default>(&self, scope: &mut G) -> Stream<G, (u32, S)> {
+    pub fn nodes<G: Scope, S: ExchangeData + Default>(
+        &self,
+        scope: &mut G,
+    ) -> Stream<G, (Node, S)> {
         use timely::dataflow::operators::to_stream::ToStream;
         use timely::dataflow::operators::Map;
 
@@ -166,7 +170,7 @@ impl DistributedEdges {
             .map(|u| (u, S::default()))
     }
 
-    fn for_each_processor<F: FnMut(usize)>(&self, node: u32, mut action: F) {
+    fn for_each_processor<F: FnMut(usize)>(&self, node: Node, mut action: F) {
         for &p in self.procs_neighs[&node].iter() {
             action(p);
         }
@@ -176,11 +180,11 @@ impl DistributedEdges {
     /// Brings together the states of the endpoints of each edge with the edge itself
     pub fn triplets<G: Scope, S: ExchangeData, F, O>(
         &self,
-        nodes: &Stream<G, (u32, S)>,
+        nodes: &Stream<G, (Node, S)>,
         action: F,
     ) -> Stream<G, O>
     where
-        F: Fn(((u32, S), (u32, S), u32)) -> Option<O> + 'static,
+        F: Fn(((Node, S), (Node, S), u32)) -> Option<O> + 'static,
         O: ExchangeData,
     {
         use timely::dataflow::channels::pact::{Exchange as ExchangePact, Pipeline};
@@ -205,7 +209,7 @@ impl DistributedEdges {
             })
             // Propagate to the destination
             .unary_notify(
-                ExchangePact::new(|pair: &(usize, (u32, S))| pair.0 as u64),
+                ExchangePact::new(|pair: &(usize, (Node, S))| pair.0 as u64),
                 "create_triplets",
                 None,
                 move |input, output, notificator| {
@@ -246,14 +250,14 @@ impl DistributedEdges {
     /// Send messages that can be aggregated along the edges
     pub fn send<G: Scope, S: ExchangeData + Default, M: ExchangeData, P, Fm, Fa, Fu, Fun>(
         &self,
-        nodes: &Stream<G, (u32, S)>,
+        nodes: &Stream<G, (Node, S)>,
         with_default: bool,
         should_send: P,
         message: Fm,
         aggregate: Fa,
         update: Fu,
         update_no_msg: Fun,
-    ) -> Stream<G, (u32, S)>
+    ) -> Stream<G, (Node, S)>
     where
         // G::Timestamp: ToPair,
         P: Fn(G::Timestamp, &S) -> bool + 'static,
@@ -300,7 +304,7 @@ impl DistributedEdges {
             })
             // Propagate to the destination
             .unary_frontier(
-                ExchangePact::new(|pair: &(usize, (u32, S))| pair.0 as u64),
+                ExchangePact::new(|pair: &(usize, (Node, S))| pair.0 as u64),
                 "msg_propagate",
                 move |_, info| {
                     use timely::scheduling::Scheduler;
@@ -449,12 +453,12 @@ where
     T: Timestamp,
     M: ExchangeData,
     F: Fn(&M, &M) -> M + 'static,
-    P: Push<Bundle<T, (u32, M)>>,
+    P: Push<Bundle<T, (Node, M)>>,
 {
     capacity: usize,
-    buffer: Vec<(u32, M)>,
+    buffer: Vec<(Node, M)>,
     merger: F,
-    session: Session<'a, T, (u32, M), P>,
+    session: Session<'a, T, (Node, M), P>,
 }
 
 impl<'a, T, M, F, P> MessageBuffer<'a, T, M, F, P>
@@ -462,9 +466,9 @@ where
     T: Timestamp,
     M: ExchangeData,
     F: Fn(&M, &M) -> M + 'static,
-    P: Push<Bundle<T, (u32, M)>>,
+    P: Push<Bundle<T, (Node, M)>>,
 {
-    fn with_capacity(capacity: usize, merger: F, session: Session<'a, T, (u32, M), P>) -> Self {
+    fn with_capacity(capacity: usize, merger: F, session: Session<'a, T, (Node, M), P>) -> Self {
         Self {
             capacity,
             buffer: Vec::with_capacity(capacity),
@@ -473,7 +477,7 @@ where
         }
     }
 
-    fn push(&mut self, dest: u32, msg: M) {
+    fn push(&mut self, dest: Node, msg: M) {
         self.buffer.push((dest, msg));
         if self.buffer.len() == self.capacity {
             self.flush()
@@ -511,29 +515,29 @@ where
     T: Timestamp,
     M: ExchangeData,
     F: Fn(&M, &M) -> M + 'static,
-    P: Push<Bundle<T, (u32, M)>>,
+    P: Push<Bundle<T, (Node, M)>>,
 {
     fn drop(&mut self) {
         self.flush()
     }
 }
 
-struct ArrayMap<S> {
-    data: Vec<(u32, S)>,
+pub struct ArrayMap<S> {
+    data: Vec<(Node, S)>,
 }
 
 impl<S> ArrayMap<S> {
-    fn new<I: IntoIterator<Item = (u32, S)>>(iter: I) -> Self {
-        let mut data: Vec<(u32, S)> = iter.into_iter().collect();
+    pub fn new<I: IntoIterator<Item = (Node, S)>>(iter: I) -> Self {
+        let mut data: Vec<(Node, S)> = iter.into_iter().collect();
         data.sort_by_key(|pair| pair.0);
         Self { data }
     }
 
-    fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.data.len()
     }
 
-    fn get(&self, key: u32) -> Option<&S> {
+    pub fn get(&self, key: Node) -> Option<&S> {
         match self.data.binary_search_by_key(&key, |pair| pair.0) {
             Ok(index) => Some(&self.data[index].1),
             Err(_) => None,
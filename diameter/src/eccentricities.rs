@@ -0,0 +1,101 @@
+use crate::node::Node;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the bounds to the eccentricities of the nodes are saved, in the data
+/// directory under the sha of the run. Each worker writes the bounds of its
+/// own nodes to a text file named after its index, with a line per node made
+/// of the node, the lower bound and the upper bound, separated by tabs.
+#[derive(Clone)]
+pub struct Eccentricities {
+    dir: PathBuf,
+    worker: usize,
+    enabled: bool,
+}
+
+impl Eccentricities {
+    pub fn new(ddir: &Path, sha: &str, worker: usize, enabled: bool) -> Self {
+        let mut dir = ddir.to_path_buf();
+        dir.push("eccentricities");
+        dir.push(sha);
+        Self {
+            dir,
+            worker,
+            enabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn save(&self, bounds: &EccentricityBounds) {
+        if !self.enabled {
+            return;
+        }
+        let timer = std::time::Instant::now();
+        std::fs::create_dir_all(&self.dir).expect("problem creating eccentricities directory");
+        let path = self.dir.join(format!("{}.tsv", self.worker));
+        let mut writer =
+            BufWriter::new(File::create(&path).expect("problem creating eccentricities file"));
+        let mut nodes: Vec<(&Node, &(u64, u64))> = bounds.bounds.iter().collect();
+        nodes.sort_unstable();
+        for (node, (lower, upper)) in nodes {
+            writeln!(writer, "{}\t{}\t{}", node, lower, upper)
+                .expect("problem writing eccentricities");
+        }
+        writer.flush().expect("problem writing eccentricities");
+        info!(
+            "Eccentricities of {} nodes saved to {:?} in {:?}",
+            bounds.bounds.len(),
+            path,
+            timer.elapsed()
+        );
+    }
+}
+
+/// Lower and upper bounds to the eccentricities of the nodes of a worker
+#[derive(Debug, Default)]
+pub struct EccentricityBounds {
+    bounds: HashMap<Node, (u64, u64)>,
+}
+
+impl EccentricityBounds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tightens the bounds of the node with the given ones
+    pub fn refine(&mut self, node: Node, lower: u64, upper: u64) {
+        let bounds = self.bounds.entry(node).or_insert((lower, upper));
+        bounds.0 = std::cmp::max(bounds.0, lower);
+        bounds.1 = std::cmp::min(bounds.1, upper);
+    }
+
+    /// By the triangle inequality, the eccentricity of a node at the given
+    /// distance from a source is at least the distance and at least the
+    /// eccentricity of the source minus the distance, and at most their sum
+    pub fn refine_from_source(&mut self, node: Node, distance: u64, source_eccentricity: u64) {
+        let lower = std::cmp::max(distance, source_eccentricity.saturating_sub(distance));
+        self.refine(node, lower, distance + source_eccentricity);
+    }
+
+    /// The nodes with their lower and upper bounds
+    pub fn iter(&self) -> impl Iterator<Item = (Node, (u64, u64))> + '_ {
+        self.bounds.iter().map(|(node, bounds)| (*node, *bounds))
+    }
+}
+
+#[test]
+fn test_refine_from_source() {
+    let mut bounds = EccentricityBounds::new();
+    bounds.refine_from_source(1, 3, 10);
+    assert_eq!(bounds.bounds.get(&1).copied(), Some((7, 13)));
+    bounds.refine_from_source(1, 8, 12);
+    assert_eq!(bounds.bounds.get(&1).copied(), Some((8, 13)));
+    bounds.refine(1, 0, 11);
+    assert_eq!(bounds.bounds.get(&1).copied(), Some((8, 11)));
+    assert_eq!(bounds.bounds.get(&2).copied(), None);
+}
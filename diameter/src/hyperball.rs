@@ -1,26 +1,50 @@
+use crate::bounds::DiameterBounds;
+use crate::centralities::*;
+use crate::checkpoint::*;
 use crate::distributed_adjacencies::*;
 use crate::distributed_graph::*;
+use crate::node::Node;
 use crate::operators::*;
-use std::collections::hash_map::DefaultHasher;
+use crate::vertex_program::*;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use timely::dataflow::channels::pact::Pipeline;
-use timely::dataflow::operators::generic::operator::Operator;
+use std::rc::Rc;
 use timely::dataflow::operators::*;
-use timely::dataflow::Scope;
+use timely::progress::Timestamp;
 
 use timely::order::Product;
 
-#[derive(Clone, PartialOrd, Ord, Eq, PartialEq, Abomonation, Debug, Hash)]
+/// The number of bits of each register: 5 bits count up to 31 leading
+/// zeros, enough to estimate cardinalities up to 2^31 times the number
+/// of registers
+const REGISTER_BITS: usize = 5;
+/// How many registers fit in a word, none of them straddling two words
+const REGISTERS_PER_WORD: usize = 64 / REGISTER_BITS;
+const REGISTER_MASK: u64 = (1 << REGISTER_BITS) - 1;
+/// The lowest bit of each register of a word
+const LOW_BITS: u64 = 0x0084_2108_4210_8421;
+/// The highest bit of each register of a word
+const HIGH_BITS: u64 = LOW_BITS << (REGISTER_BITS - 1);
+
+/// A HyperLogLog counter with `2^p` registers, packed in 64 bit words
+#[derive(
+    Clone, PartialOrd, Ord, Eq, PartialEq, Abomonation, Debug, Hash, Serialize, Deserialize,
+)]
 struct HyperLogLogCounter {
-    registers: Vec<u8>,
+    p: u8,
+    words: Vec<u64>,
 }
 
 impl HyperLogLogCounter {
     fn new<V: Hash + std::fmt::Debug, H: Hasher>(value: &V, mut hasher: H, p: usize) -> Self {
         assert!(p >= 4 && p <= 16);
         let m = 2usize.pow(p as u32);
-        let mut registers = vec![0u8; m];
+        let mut counter = HyperLogLogCounter {
+            p: p as u8,
+            words: vec![0u64; (m + REGISTERS_PER_WORD - 1) / REGISTERS_PER_WORD],
+        };
 
         value.hash(&mut hasher);
         let h: u64 = hasher.finish();
@@ -32,20 +56,61 @@ impl HyperLogLogCounter {
         let idx = h & mask;
         let w = h >> p;
 
-        registers[idx as usize] = Self::rho(w);
-
-        HyperLogLogCounter { registers }
+        counter.set(idx as usize, Self::rho(w));
+        counter
     }
 
+    /// The position of the lowest set bit, saturating at the largest value of a register
     fn rho(hash: u64) -> u8 {
-        (hash.trailing_zeros() + 1) as u8
+        std::cmp::min(hash.trailing_zeros() + 1, REGISTER_MASK as u32) as u8
+    }
+
+    fn num_registers(&self) -> usize {
+        1 << self.p
+    }
+
+    fn get(&self, i: usize) -> u8 {
+        let shift = (i % REGISTERS_PER_WORD) * REGISTER_BITS;
+        ((self.words[i / REGISTERS_PER_WORD] >> shift) & REGISTER_MASK) as u8
+    }
+
+    fn set(&mut self, i: usize, register: u8) {
+        let shift = (i % REGISTERS_PER_WORD) * REGISTER_BITS;
+        let word = &mut self.words[i / REGISTERS_PER_WORD];
+        *word = (*word & !(REGISTER_MASK << shift)) | ((register as u64) << shift);
+    }
+
+    fn registers(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.num_registers()).map(move |i| self.get(i))
     }
 
-    fn merge_inplace(&mut self, other: &Self) {
-        let m = self.registers.len();
-        for i in 0..m {
-            self.registers[i] = std::cmp::max(self.registers[i], other.registers[i]);
+    /// Takes the maximum of each pair of registers, returning whether
+    /// any register of this counter changed
+    fn merge_inplace(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (x, &y) in self.words.iter_mut().zip(other.words.iter()) {
+            if *x == y {
+                continue;
+            }
+            let max = Self::max_registers(*x, y);
+            changed |= max != *x;
+            *x = max;
         }
+        changed
+    }
+
+    /// The maximum of each pair of registers of two words, computed with
+    /// broadword operations on all the registers at once, as in HyperANF
+    fn max_registers(x: u64, y: u64) -> u64 {
+        // With the highest bit of each register of `x` set, and the one of
+        // `y` cleared, the subtraction never borrows across registers, and
+        // keeps the highest bit only if the lower bits of `x` are at least
+        // the ones of `y`
+        let difference = (x | HIGH_BITS) - (y & !HIGH_BITS);
+        let less = ((!x & y) | (!(x ^ y) & !difference)) & HIGH_BITS;
+        // All the bits of the registers in which `x` is smaller
+        let mask = (less << 1) - (less >> (REGISTER_BITS - 1));
+        (x & !mask) | (y & mask)
     }
 
     fn merge(&self, other: &Self) -> Self {
@@ -53,33 +118,337 @@ impl HyperLogLogCounter {
         c.merge_inplace(&other);
         c
     }
+
+    /// Merges the given registers into the counter, pushing the ones that
+    /// changed to `changes`, by increasing index
+    fn apply(&mut self, update: &CounterUpdate, changes: &mut Vec<(u16, u8)>) {
+        match update {
+            CounterUpdate::Full(other) => {
+                for (w, (x, &y)) in self.words.iter_mut().zip(other.words.iter()).enumerate() {
+                    if *x == y {
+                        continue;
+                    }
+                    let max = Self::max_registers(*x, y);
+                    for r in 0..REGISTERS_PER_WORD {
+                        let shift = r * REGISTER_BITS;
+                        if ((max ^ *x) >> shift) & REGISTER_MASK != 0 {
+                            let register = ((max >> shift) & REGISTER_MASK) as u8;
+                            changes.push(((w * REGISTERS_PER_WORD + r) as u16, register));
+                        }
+                    }
+                    *x = max;
+                }
+            }
+            CounterUpdate::Sparse(registers) => {
+                for &(i, register) in registers.iter() {
+                    if register > self.get(i as usize) {
+                        self.set(i as usize, register);
+                        changes.push((i, register));
+                    }
+                }
+            }
+        }
+    }
+
+    /// The update that brings a counter that already has all the
+    /// registers of this one but the given changes up to date with it
+    fn update_from(&self, changes: &[(u16, u8)]) -> CounterUpdate {
+        let sparse_size = changes.len() * std::mem::size_of::<(u16, u8)>();
+        if sparse_size < self.words.len() * std::mem::size_of::<u64>() {
+            CounterUpdate::Sparse(changes.to_vec())
+        } else {
+            CounterUpdate::Full(self.clone())
+        }
+    }
+
+    /// The estimated number of distinct values in the counter: the raw
+    /// estimate corrected by the constant of Flajolet et al., switching
+    /// to linear counting on the empty registers for small cardinalities.
+    fn estimate(&self) -> f64 {
+        let m = self.num_registers() as f64;
+        let alpha = match self.num_registers() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers()
+            .map(|register| 2f64.powi(-(register as i32)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers().filter(|&register| register == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+/// The registers of a counter sent to a neighbour: either all of them, or
+/// only the ones that changed at the last update, when they are few enough
+/// to take less space
+#[derive(Clone, Abomonation, Debug, Serialize, Deserialize)]
+enum CounterUpdate {
+    Full(HyperLogLogCounter),
+    /// Pairs of register index and value, by increasing index
+    Sparse(Vec<(u16, u8)>),
+}
+
+impl CounterUpdate {
+    fn combine(&self, other: &Self) -> Self {
+        match (self, other) {
+            (Self::Full(c1), Self::Full(c2)) => Self::Full(c1.merge(c2)),
+            (Self::Full(counter), sparse) | (sparse, Self::Full(counter)) => {
+                let mut counter = counter.clone();
+                counter.apply(sparse, &mut Vec::new());
+                Self::Full(counter)
+            }
+            (Self::Sparse(r1), Self::Sparse(r2)) => {
+                let mut registers = Vec::with_capacity(r1.len() + r2.len());
+                let (mut i1, mut i2) = (r1.iter().peekable(), r2.iter().peekable());
+                loop {
+                    let next = match (i1.peek(), i2.peek()) {
+                        (Some(&&(j1, v1)), Some(&&(j2, v2))) if j1 == j2 => {
+                            i1.next();
+                            i2.next();
+                            (j1, std::cmp::max(v1, v2))
+                        }
+                        (Some(&&(j1, _)), Some(&&(j2, _))) if j1 < j2 => *i1.next().unwrap(),
+                        (Some(_), Some(_)) | (None, Some(_)) => *i2.next().unwrap(),
+                        (Some(_), None) => *i1.next().unwrap(),
+                        (None, None) => break,
+                    };
+                    registers.push(next);
+                }
+                Self::Sparse(registers)
+            }
+        }
+    }
+}
+
+/// A 64 bit hash function keyed by a seed, mixing each word of the input
+/// into the state with the finalizer of MurmurHash3
+struct SeededHasher {
+    state: u64,
+}
+
+impl SeededHasher {
+    fn new(seed: u64) -> Self {
+        Self {
+            state: Self::mix(seed ^ 0x9e37_79b9_7f4a_7c15),
+        }
+    }
+
+    fn mix(mut x: u64) -> u64 {
+        x ^= x >> 33;
+        x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        x ^= x >> 33;
+        x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        x ^ (x >> 33)
+    }
 }
 
-#[derive(Debug, Clone, Abomonation)]
+impl Hasher for SeededHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.state = Self::mix(self.state ^ i).wrapping_add(i);
+    }
+
+    fn finish(&self) -> u64 {
+        Self::mix(self.state)
+    }
+}
+
+/// The neighbourhood function of the graph: the number of pairs of nodes
+/// within distance `t` of each other, for `t` from 0 up to the distance at
+/// which it stops growing. Self pairs count, so the first value is the
+/// number of nodes.
+#[derive(Debug, Clone)]
+pub struct NeighbourhoodFunction {
+    pub pairs: Vec<f64>,
+}
+
+impl NeighbourhoodFunction {
+    /// The number of pairs within distance `t`, which stays the same past the end
+    pub fn at(&self, t: usize) -> f64 {
+        self.pairs[std::cmp::min(t, self.pairs.len() - 1)]
+    }
+
+    /// The function restricted to the distances up to `t`
+    pub fn up_to(&self, t: usize) -> Self {
+        Self {
+            pairs: self.pairs[..=t].to_vec(),
+        }
+    }
+
+    /// The distance within which the given fraction of the reachable pairs
+    /// lie, interpolating linearly between consecutive distances
+    pub fn effective_diameter(&self, fraction: f64) -> f64 {
+        let target = fraction * self.pairs.last().expect("empty neighbourhood function");
+        let t = self
+            .pairs
+            .iter()
+            .position(|&pairs| pairs >= target)
+            .expect("the target exceeds the reachable pairs");
+        if t == 0 {
+            0.0
+        } else {
+            let previous = self.pairs[t - 1];
+            (t - 1) as f64 + (target - previous) / (self.pairs[t] - previous)
+        }
+    }
+
+    /// The average distance between distinct nodes that reach each other
+    pub fn average_distance(&self) -> f64 {
+        let reachable = self.pairs.last().expect("empty neighbourhood function") - self.pairs[0];
+        if reachable <= 0.0 {
+            return 0.0;
+        }
+        let total: f64 = self
+            .pairs
+            .windows(2)
+            .enumerate()
+            .map(|(t, pairs)| (t + 1) as f64 * (pairs[1] - pairs[0]))
+            .sum();
+        total / reachable
+    }
+
+    /// The harmonic mean of the distances between all the pairs of distinct
+    /// nodes, where unreachable pairs are infinitely far apart, and so is
+    /// the whole graph if no pair reaches each other
+    pub fn harmonic_diameter(&self) -> f64 {
+        let n = self.pairs[0];
+        let inverse: f64 = self
+            .pairs
+            .windows(2)
+            .enumerate()
+            .map(|(t, pairs)| (pairs[1] - pairs[0]) / (t + 1) as f64)
+            .sum();
+        if inverse > 0.0 {
+            n * (n - 1.0) / inverse
+        } else {
+            std::f64::INFINITY
+        }
+    }
+}
+
+#[derive(Debug, Clone, Abomonation, Serialize, Deserialize)]
 struct State {
-    counter: HyperLogLogCounter,
+    /// One counter for each repetition, each with its own hash function
+    counters: Vec<HyperLogLogCounter>,
+    /// For each counter, the registers that changed at its last update,
+    /// which are all the neighbours miss; empty once the node is not active
+    changes: Vec<Vec<(u16, u8)>>,
     updated: bool,
+    /// For each counter, the number of rounds after which it stopped changing
+    stable_since: Vec<u32>,
+    /// For each counter, its estimate, which takes a pass over all its
+    /// registers, computed again only when some of them change
+    estimates: Vec<f64>,
+    /// For each counter, how much its estimate grew at its last update
+    growth: Vec<f64>,
+    /// The sum over the rounds `t` of the average growth of the ball of radius `t` over `t`
+    harmonic: f64,
+    /// The sum over the rounds `t` of the average growth of the ball of radius `t` times `t`
+    distances: f64,
 }
 
 impl State {
-    fn new(p: usize, id: u32) -> Self {
+    fn new(p: usize, id: Node, seed: u64, repetitions: usize) -> Self {
+        let counters: Vec<HyperLogLogCounter> = (0..repetitions)
+            .map(|i| {
+                let hasher = SeededHasher::new(seed.wrapping_add(i as u64));
+                HyperLogLogCounter::new(&id, hasher, p)
+            })
+            .collect();
+        let changes = counters
+            .iter()
+            .map(|counter| {
+                counter
+                    .registers()
+                    .enumerate()
+                    .filter(|&(_i, register)| register > 0)
+                    .map(|(i, register)| (i as u16, register))
+                    .collect()
+            })
+            .collect();
+        let estimates = counters.iter().map(|counter| counter.estimate()).collect();
         Self {
-            counter: HyperLogLogCounter::new(&id, DefaultHasher::new(), p),
+            counters,
+            changes,
+            estimates,
             updated: true,
+            stable_since: vec![0; repetitions],
+            growth: vec![0.0; repetitions],
+            harmonic: 0.0,
+            distances: 0.0,
         }
     }
 
-    fn update(&self, counter: &HyperLogLogCounter) -> Self {
-        let new_counter = self.counter.merge(counter);
-        let updated = new_counter != self.counter;
+    fn update(&self, updates: &[CounterUpdate], round: u32) -> Self {
+        let mut new_counters = self.counters.clone();
+        let mut changes = Vec::with_capacity(self.counters.len());
+        let mut updated = false;
+        let mut stable_since = self.stable_since.clone();
+        let mut estimates = self.estimates.clone();
+        let mut growth = vec![0.0; self.counters.len()];
+        for (i, (counter, update)) in new_counters.iter_mut().zip(updates.iter()).enumerate() {
+            let mut counter_changes = Vec::new();
+            counter.apply(update, &mut counter_changes);
+            if !counter_changes.is_empty() {
+                updated = true;
+                stable_since[i] = round + 1;
+                let estimate = counter.estimate();
+                growth[i] = estimate - estimates[i];
+                estimates[i] = estimate;
+            }
+            changes.push(counter_changes);
+        }
+        if !updated {
+            changes.clear();
+        }
+        let average_growth = growth.iter().sum::<f64>() / growth.len() as f64;
+        // The balls have radius `round + 1` after this round
+        let radius = (round + 1) as f64;
         Self {
-            counter: new_counter,
+            counters: new_counters,
+            changes,
+            estimates,
             updated,
+            stable_since,
+            growth,
+            harmonic: self.harmonic + average_growth / radius,
+            distances: self.distances + average_growth * radius,
+        }
+    }
+
+    fn centrality(&self) -> Centrality {
+        Centrality {
+            harmonic: self.harmonic,
+            closeness: if self.distances > 0.0 {
+                1.0 / self.distances
+            } else {
+                0.0
+            },
         }
     }
 
     fn deactivate(&self) -> Self {
         Self {
+            changes: Vec::new(),
             updated: false,
             ..self.clone()
         }
@@ -92,96 +461,452 @@ impl Default for State {
     }
 }
 
+/// Combines the updates of each repetition
+fn combine_all(u1: &[CounterUpdate], u2: &[CounterUpdate]) -> Vec<CounterUpdate> {
+    u1.iter()
+        .zip(u2.iter())
+        .map(|(u1, u2)| u1.combine(u2))
+        .collect()
+}
+
+#[derive(Clone)]
+struct HyperBallProgram;
+
+impl<T: Timestamp> VertexProgram<Product<T, u32>> for HyperBallProgram {
+    type State = State;
+    type Message = Vec<CounterUpdate>;
+
+    fn with_default(&self) -> bool {
+        false
+    }
+
+    fn should_send(&self, _time: &Product<T, u32>, _state: &State) -> bool {
+        true
+    }
+
+    /// Only active nodes send, and their neighbours already merged all
+    /// their registers but the ones that changed at the last update
+    fn message(
+        &self,
+        _time: &Product<T, u32>,
+        _id: Node,
+        state: &State,
+        _weight: u32,
+    ) -> Option<Vec<CounterUpdate>> {
+        Some(
+            state
+                .counters
+                .iter()
+                .zip(state.changes.iter())
+                .map(|(counter, changes)| counter.update_from(changes))
+                .collect(),
+        )
+    }
+
+    fn combine(u1: &Vec<CounterUpdate>, u2: &Vec<CounterUpdate>) -> Vec<CounterUpdate> {
+        combine_all(u1, u2)
+    }
+
+    fn apply(
+        &self,
+        time: &Product<T, u32>,
+        state: &State,
+        message: Option<&Vec<CounterUpdate>>,
+    ) -> State {
+        match message {
+            Some(updates) => state.update(updates, time.inner),
+            None => state.deactivate(),
+        }
+    }
+
+    fn is_active(&self, _time: &Product<T, u32>, state: &State) -> bool {
+        state.updated
+    }
+}
+
+/// What each of the independent repetitions of HyperBall found
+#[derive(Debug, Clone)]
+pub struct HyperBallRepetitions {
+    /// The round after which the counters of each repetition stopped changing
+    pub diameters: Vec<u32>,
+    /// The neighbourhood function estimated by each repetition, empty when
+    /// resuming from a checkpoint
+    pub neighbourhoods: Vec<NeighbourhoodFunction>,
+}
+
+impl HyperBallRepetitions {
+    /// The neighbourhood function averaged over the repetitions, with its
+    /// standard deviation at each distance
+    pub fn neighbourhood_mean_and_stddev(&self) -> Vec<(f64, f64)> {
+        let max_distance = self
+            .neighbourhoods
+            .iter()
+            .map(|neighbourhood| neighbourhood.pairs.len())
+            .max()
+            .unwrap_or(0);
+        (0..max_distance)
+            .map(|t| mean_and_stddev(self.neighbourhoods.iter().map(|n| n.at(t))))
+            .collect()
+    }
+}
+
+/// The mean and the (population) standard deviation of the given values
+pub fn mean_and_stddev<I: Iterator<Item = f64> + Clone>(values: I) -> (f64, f64) {
+    let count = values.clone().count() as f64;
+    let mean = values.clone().sum::<f64>() / count;
+    let variance = values.map(|x| (x - mean) * (x - mean)).sum::<f64>() / count;
+    (mean, variance.sqrt())
+}
+
+/// Estimates the diameter as the number of rounds it takes for all the
+/// counters to stop changing. Each node has `repetitions` counters, with
+/// hash functions seeded independently from `seed`, so that the estimates
+/// of the repetitions, gathered on the first worker, give error bars.
+/// Unless resuming from a checkpoint, the growth of the counters in each
+/// round also gives the neighbourhood function of each repetition. The
+/// final counters give the harmonic and closeness centralities of the nodes,
+/// averaged over the repetitions, saved if `centralities` is enabled.
 pub fn hyperball<A: timely::communication::Allocate>(
     adjacencies: DistributedAdjacencies,
     worker: &mut timely::worker::Worker<A>,
     p: usize,
-    _seed: u64,
-) -> (Option<u32>, std::time::Duration) {
-    let (diameter_box, probe) = worker.dataflow::<(), _, _>(|scope| {
-        // Init nodes
-        let nodes = adjacencies
-            .nodes::<_, ()>(scope)
-            .map(move |(id, ())| (id, State::new(p, id)));
+    repetitions: usize,
+    seed: u64,
+    checkpoints: &Checkpoints,
+    centralities: &Centralities,
+) -> (
+    Option<DiameterBounds<u32>>,
+    Option<HyperBallRepetitions>,
+    std::time::Duration,
+) {
+    let resume = checkpoints.resume_point(worker);
+    // The growth of the sum of the estimates of the local counters, by
+    // repetition and distance
+    let growth = Rc::new(RefCell::new(HashMap::new()));
+    let initial_growth = Rc::clone(&growth);
+    let round_growth = Rc::clone(&growth);
+    let node_centralities = Rc::new(RefCell::new(Vec::new()));
+    let final_centralities = Rc::clone(&node_centralities);
+    let keep_centralities = centralities.is_enabled();
+    let diameters = Rc::new(RefCell::new(None));
+    let diameters_ref = Rc::clone(&diameters);
+
+    let probe = worker.dataflow::<(), _, _>(|scope| {
+        // Init nodes, or get them from the checkpoint
+        let nodes = match resume {
+            Some(round) => checkpoints.load::<State>(round).0.to_stream(scope),
+            None => adjacencies
+                .nodes::<_, ()>(scope)
+                .map(move |(id, ())| (id, State::new(p, id, seed, repetitions)))
+                .inspect(move |(_id, state)| {
+                    let mut growth = initial_growth.borrow_mut();
+                    for (i, estimate) in state.estimates.iter().enumerate() {
+                        *growth.entry((i as u32, 0)).or_insert(0.0) += estimate;
+                    }
+                }),
+        };
 
         // let l1 = nodes.scope().count_logger().expect("missing logger");
 
-        let stop_times = nodes.scope().iterative::<u32, _, _>(|subscope| {
-            let nodes = nodes.enter(subscope);
-            let (handle, cycle) = subscope.feedback(Product::new(Default::default(), 1));
-
-            let (stable, updated) = adjacencies
-                .send(
-                    &nodes.concat(&cycle),
-                    // .inspect_batch(move |t, data| {
-                    //     l1.log((CountEvent::Active(t.inner), data.len() as u64))
-                    // }),
-                    false,
-                    // Should send?
-                    |_, _| true,
-                    // create message
-                    |_time, state, _weight| Some(state.counter.clone()),
-                    // Combine messages
-                    HyperLogLogCounter::merge,
-                    // Update state for nodes with messages
-                    |state, message| state.update(message),
-                    // Update the state for nodes without messages
-                    |state| state.deactivate(),
-                )
-                // .branch(|_t, (_id, state)| state.updated);
-                // We have to keep cycling nodes because they may get re-activated
-                // at a later iteration
-                .branch_all("updated", |_, (_id, state)| state.updated, 0);
-
-            updated.connect_loop(handle);
-
-            let mut counters = HashMap::new();
-            // Get the iteration we are returning from, and output just that
-            stable
-                .unary_notify(
-                    Pipeline,
-                    "get_iteration",
-                    None,
-                    move |input, output, notificator| {
-                        input.for_each(|t, data| {
-                            counters
-                                .entry(t.time().clone())
-                                .and_modify(|c| *c += data.len())
-                                .or_insert(data.len());
-                            notificator.notify_at(t.retain());
-                        });
-                        notificator.for_each(|t, _, _| {
-                            info!(
-                                "{:?} are exiting at time {:?}",
-                                counters.remove(t.time()),
-                                t.time()
-                            );
-                            output.session(&t).give(t.time().inner);
-                        });
-                    },
-                )
-                .leave()
-        });
+        let stop_times = run_until_quiescent_observed(
+            &adjacencies,
+            &nodes,
+            HyperBallProgram,
+            checkpoints,
+            resume,
+            move |round, _id, state: &State| {
+                let mut growth = round_growth.borrow_mut();
+                for (i, counter_growth) in state.growth.iter().enumerate() {
+                    *growth.entry((i as u32, round + 1)).or_insert(0.0) += counter_growth;
+                }
+            },
+        )
+        .inspect(move |(id, state)| {
+            if keep_centralities {
+                final_centralities
+                    .borrow_mut()
+                    .push((*id, state.centrality()));
+            }
+        })
+        .map(|(_id, state)| state.stable_since);
 
         stop_times
-            .accumulate(0u32, |max, data| {
-                *max = std::cmp::max(*data.iter().max().expect("empty collection"), *max)
+            .accumulate(vec![0u32; repetitions], |max, data| {
+                for stop_times in data.iter() {
+                    max_each(max, stop_times);
+                }
             })
-            .inspect(|partial| info!("Partial maximum {:?}", partial))
             .exchange(|_| 0)
-            .accumulate(0u32, |max, data| {
-                *max = std::cmp::max(*data.iter().max().expect("empty collection"), *max)
+            .accumulate(vec![0u32; repetitions], |max, data| {
+                for stop_times in data.iter() {
+                    max_each(max, stop_times);
+                }
             })
-            .collect_single()
+            .inspect(move |diameters| {
+                info!("Diameter estimate of each repetition {:?}", diameters);
+                diameters_ref.borrow_mut().replace(diameters.clone());
+            })
+            .probe()
     });
 
     let elapsed = run_to_completion(worker, probe);
-    let diameter = diameter_box.borrow_mut().take();
 
-    (diameter, elapsed)
+    if let Some(k) = centralities.top() {
+        let mut node_centralities = node_centralities.replace(Vec::new());
+        centralities.save(&mut node_centralities);
+        let harmonic = node_centralities
+            .iter()
+            .map(|(id, centrality)| (centrality.harmonic, *id))
+            .collect();
+        let closeness = node_centralities
+            .iter()
+            .map(|(id, centrality)| (centrality.closeness, *id))
+            .collect();
+        if let Some(top) = gather_top(worker, k, harmonic) {
+            centralities.save_top("harmonic", &top);
+        }
+        if let Some(top) = gather_top(worker, k, closeness) {
+            centralities.save_top("closeness", &top);
+        }
+    }
+
+    let neighbourhoods = if resume.is_some() {
+        info!("Resumed from a checkpoint, the neighbourhood function is not available");
+        Vec::new()
+    } else {
+        let local_growth = growth.borrow_mut().drain().collect();
+        gather_growth(worker, repetitions, local_growth)
+    };
+
+    // Some ball still grows at the last round in which a counter changes,
+    // but the estimate cannot tell how far the balls are from covering the graph
+    match diameters.replace(None) {
+        Some(diameters) => {
+            let max = *diameters.iter().max().expect("no repetitions");
+            // The functions of repetitions that stopped earlier have constant tails
+            let neighbourhoods = neighbourhoods
+                .into_iter()
+                .zip(diameters.iter())
+                .map(|(neighbourhood, &diameter)| neighbourhood.up_to(diameter as usize))
+                .collect();
+            let repetitions = HyperBallRepetitions {
+                diameters,
+                neighbourhoods,
+            };
+            (
+                Some(DiameterBounds::lower_only(max)),
+                Some(repetitions),
+                elapsed,
+            )
+        }
+        None => (None, None, elapsed),
+    }
+}
+
+/// Keeps the maximum of the stop times of each repetition
+fn max_each(max: &mut [u32], stop_times: &[u32]) {
+    for (max, &stop_time) in max.iter_mut().zip(stop_times.iter()) {
+        *max = std::cmp::max(*max, stop_time);
+    }
+}
+
+/// Gathers on the first worker the `k` most central nodes of all the workers
+fn gather_top<A: timely::communication::Allocate>(
+    worker: &mut timely::worker::Worker<A>,
+    k: usize,
+    mut local: Vec<(f64, Node)>,
+) -> Option<Vec<(f64, Node)>> {
+    sort_by_centrality(&mut local);
+    local.truncate(k);
+
+    let result = Rc::new(RefCell::new(None));
+    let result_ref = Rc::clone(&result);
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        vec![local]
+            .to_stream(scope)
+            .exchange(|_| 0)
+            .accumulate(Vec::new(), move |top, data| {
+                for local in data.iter() {
+                    top.extend(local.iter().copied());
+                }
+                sort_by_centrality(top);
+                top.truncate(k);
+            })
+            .inspect(move |top| {
+                result_ref.borrow_mut().replace(top.clone());
+            })
+            .probe()
+    });
+    run_to_completion(worker, probe);
+    result.replace(None)
+}
+
+/// Sums the growth of the counters of all the workers on the first one,
+/// accumulating it into the neighbourhood function of each repetition
+fn gather_growth<A: timely::communication::Allocate>(
+    worker: &mut timely::worker::Worker<A>,
+    repetitions: usize,
+    local_growth: Vec<((u32, u32), f64)>,
+) -> Vec<NeighbourhoodFunction> {
+    let growth = Rc::new(RefCell::new(HashMap::new()));
+    let growth_ref = Rc::clone(&growth);
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        local_growth
+            .to_stream(scope)
+            .exchange(|_| 0)
+            .inspect(move |&(key, growth)| {
+                *growth_ref.borrow_mut().entry(key).or_insert(0.0) += growth;
+            })
+            .probe()
+    });
+    run_to_completion(worker, probe);
+
+    let growth = growth.borrow();
+    let max_distance = match growth.keys().map(|(_i, t)| *t).max() {
+        Some(max_distance) => max_distance,
+        None => return Vec::new(),
+    };
+    (0..repetitions as u32)
+        .map(|i| {
+            let mut pairs = Vec::with_capacity(max_distance as usize + 1);
+            let mut total = 0.0;
+            for t in 0..=max_distance {
+                total += growth.get(&(i, t)).copied().unwrap_or(0.0);
+                pairs.push(total);
+            }
+            NeighbourhoodFunction { pairs }
+        })
+        .collect()
 }
 
 #[test]
 fn test_rho() {
     assert_eq!(HyperLogLogCounter::rho(1 << 2), 3);
+    assert_eq!(HyperLogLogCounter::rho(0), 31);
+}
+
+#[test]
+fn test_merge() {
+    use rand::prelude::*;
+    use rand_xoshiro::Xoshiro256StarStar;
+
+    let mut rng = Xoshiro256StarStar::seed_from_u64(1234);
+    for _ in 0..100 {
+        let p = 6;
+        let mut c1 = HyperLogLogCounter::new(&0u32, SeededHasher::new(0), p);
+        let mut c2 = c1.clone();
+        for i in 0..c1.num_registers() {
+            c1.set(i, rng.gen_range(0, 32));
+            c2.set(i, rng.gen_range(0, 32));
+        }
+        let merged = c1.merge(&c2);
+        for i in 0..c1.num_registers() {
+            assert_eq!(merged.get(i), std::cmp::max(c1.get(i), c2.get(i)));
+        }
+        assert_eq!(c1.clone().merge_inplace(&c2), merged != c1);
+        assert!(!merged.clone().merge_inplace(&c1));
+    }
+}
+
+#[test]
+fn test_estimate() {
+    let single = HyperLogLogCounter::new(&1u32, SeededHasher::new(0), 10);
+    assert!((single.estimate() - 1.0).abs() < 0.01);
+
+    let mut counter = single.clone();
+    for value in 2..=10_000u32 {
+        counter.merge_inplace(&HyperLogLogCounter::new(&value, SeededHasher::new(0), 10));
+    }
+    assert!((counter.estimate() / 10_000.0 - 1.0).abs() < 0.1);
+}
+
+#[test]
+fn test_state_update_keeps_estimates() {
+    let state = State::new(6, 0, 0, 2);
+    let neighbour = State::new(6, 1, 0, 2);
+    let updates: Vec<CounterUpdate> = neighbour
+        .counters
+        .iter()
+        .map(|counter| CounterUpdate::Full(counter.clone()))
+        .collect();
+    let updated = state.update(&updates, 0);
+    for i in 0..2 {
+        assert_eq!(updated.estimates[i], updated.counters[i].estimate());
+        assert_eq!(
+            updated.growth[i],
+            updated.estimates[i] - state.counters[i].estimate()
+        );
+    }
+    // Nothing changes the second time, and the estimates stay the same
+    let again = updated.update(&updates, 1);
+    assert!(!again.updated);
+    assert_eq!(again.estimates, updated.estimates);
+}
+
+#[test]
+fn test_neighbourhood_function() {
+    // A path of three nodes
+    let function = NeighbourhoodFunction {
+        pairs: vec![3.0, 7.0, 9.0],
+    };
+    assert!((function.effective_diameter(0.9) - 1.55).abs() < 1e-9);
+    assert!((function.average_distance() - 8.0 / 6.0).abs() < 1e-9);
+    assert!((function.harmonic_diameter() - 1.2).abs() < 1e-9);
+    assert_eq!(function.up_to(1).pairs, vec![3.0, 7.0]);
+}
+
+#[test]
+fn test_seeded_hasher() {
+    let hash = |seed: u64, value: u32| {
+        let mut hasher = SeededHasher::new(seed);
+        value.hash(&mut hasher);
+        hasher.finish()
+    };
+    assert_eq!(hash(1, 42), hash(1, 42));
+    assert_ne!(hash(1, 42), hash(2, 42));
+    assert_ne!(hash(1, 42), hash(1, 43));
+}
+
+#[test]
+fn test_mean_and_stddev() {
+    let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+    assert_eq!(mean_and_stddev(values.iter().copied()), (5.0, 2.0));
+}
+
+#[test]
+fn test_counter_updates() {
+    let mut counter = HyperLogLogCounter::new(&0u32, SeededHasher::new(0), 6);
+    let mut other = counter.clone();
+    other.set(1, 7);
+    other.set(40, 3);
+    let mut sparse = counter.clone();
+    sparse.set(40, 5);
+    sparse.set(63, 2);
+
+    let sparse_update = CounterUpdate::Sparse(vec![(40, 5), (63, 2)]);
+    let mut changes = Vec::new();
+    counter.apply(&CounterUpdate::Full(other.clone()), &mut changes);
+    assert_eq!(changes, vec![(1, 7), (40, 3)]);
+    assert_eq!(counter, other);
+    match other.update_from(&changes) {
+        CounterUpdate::Sparse(registers) => assert_eq!(registers, changes),
+        CounterUpdate::Full(_) => panic!("few changes make a sparse update"),
+    }
+    let combined = sparse_update.combine(&CounterUpdate::Sparse(vec![(1, 7), (40, 3)]));
+    match &combined {
+        CounterUpdate::Sparse(registers) => {
+            assert_eq!(registers, &vec![(1, 7), (40, 5), (63, 2)])
+        }
+        CounterUpdate::Full(_) => panic!("sparse updates combine into a sparse one"),
+    }
+
+    changes.clear();
+    counter.apply(&combined, &mut changes);
+    assert_eq!(changes, vec![(40, 5), (63, 2)]);
+    assert_eq!(counter, other.merge(&sparse));
+    let full = CounterUpdate::Full(other.clone()).combine(&sparse_update);
+    match full {
+        CounterUpdate::Full(full) => assert_eq!(full, other.merge(&sparse)),
+        CounterUpdate::Sparse(_) => panic!("a full update stays full"),
+    }
 }
@@ -12,24 +12,42 @@ extern crate tar;
 extern crate timely;
 extern crate url;
 
+mod async_sssp;
 mod bfs;
+mod bounds;
+mod centralities;
+mod checkpoint;
+mod components;
 mod datasets;
 mod delta_stepping;
+mod distance;
 mod distributed_adjacencies;
 mod distributed_graph;
+mod eccentricities;
 mod hyperball;
 mod logging;
+mod ms_bfs;
+mod node;
 mod operators;
+mod paths;
+mod point_to_point;
+mod radius;
 mod rand_cluster;
 mod reporter;
 mod sequential;
+mod sweep;
+mod vertex_program;
 
 use anyhow::Result;
 use argh::FromArgs;
+use bounds::DiameterBounds;
 use bytes::*;
 use datasets::*;
 use delta_stepping::*;
+use distance::Distance;
 use distributed_adjacencies::DistributedAdjacencies;
+use node::Node;
+use operators::random_root;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -123,19 +141,110 @@ impl Hosts {
 enum Algorithm {
     Sequential,
     SequentialSingle,
+    /// Exact diameter, with iFUB
+    SequentialExact,
     DeltaStepping(u32),
+    /// Delta-stepping with delta picked from the weights of the graph
+    DeltaSteppingAuto,
+    AsyncSssp,
     HyperBall(usize),
+    /// HyperBall with the given number of independent repetitions
+    HyperBallRepeat(usize, usize),
     RandCluster(u32, f64),
     /// Parameterized by the maximum size of the auxiliary graph, the initial radius, and the multiplicative step
     RandClusterGuess(u32, u32, u32),
+    /// Same parameters as `RandClusterGuess`, predicting the radius from the previous
+    /// attempts rather than multiplying it, and optionally reusing their clusters
+    RandClusterSearch(u32, u32, u32, bool),
+    /// Same parameters as `RandClusterGuess`, clustering the auxiliary graph rather than the input one
+    RandClusterRecursive(u32, u32, u32),
+    /// Clustering with exponentially distributed start times, parameterized by their rate
+    Mpx(f64),
     Bfs,
+    /// Exact eccentricities of the given number of random nodes, with a
+    /// single multi-source BFS
+    MsBfs(usize),
+    /// Parameterized by the number of sweeps
+    Sweep(u32),
+    /// Radius and centers, parameterized by the number of single source
+    /// computations and the radius of the clustering
+    Radius(u32, u32),
+    /// Distance between the given source and target
+    PointToPoint(Node, Node),
+    /// Distances between the pairs of nodes in the file of queries
+    PointToPointBatch,
 }
 
 impl Algorithm {
+    /// Whether the algorithm estimates the diameter from above, rather than
+    /// from below, so that its upper bound is the diameter it reports
+    fn estimates_from_above(&self) -> bool {
+        match self {
+            Self::RandCluster(_, _) => true,
+            Self::RandClusterGuess(_, _, _) => true,
+            Self::RandClusterSearch(_, _, _, _) => true,
+            Self::RandClusterRecursive(_, _, _) => true,
+            Self::Mpx(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the algorithm can save bounds to the eccentricities of the nodes
+    fn supports_eccentricities(&self) -> bool {
+        match self {
+            Self::DeltaStepping(_) => true,
+            Self::DeltaSteppingAuto => true,
+            Self::Bfs => true,
+            Self::MsBfs(_) => true,
+            Self::Sweep(_) => true,
+            Self::Radius(_, _) => true,
+            Self::RandCluster(_, _) => true,
+            Self::RandClusterGuess(_, _, _) => true,
+            Self::RandClusterSearch(_, _, _, _) => true,
+            Self::Mpx(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the algorithm can compute the centralities of the nodes
+    fn supports_centralities(&self) -> bool {
+        match self {
+            Self::HyperBall(_) => true,
+            Self::HyperBallRepeat(_, _) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the algorithm can output a path between the witness endpoints
+    /// of the given graph
+    fn supports_paths(&self, meta: &Metadata) -> bool {
+        match self {
+            Self::DeltaStepping(_) => true,
+            Self::DeltaSteppingAuto => true,
+            // The fewest hops are the shortest path only if the weights are all the same
+            Self::Bfs => meta.min_weight == meta.max_weight,
+            Self::Sequential => true,
+            Self::SequentialSingle => true,
+            Self::SequentialExact => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the bounds of the algorithm are to the diameter, and should
+    /// account for the components of the graph it does not look at
+    fn checks_components(&self) -> bool {
+        match self {
+            Self::PointToPoint(_, _) => false,
+            Self::PointToPointBatch => false,
+            _ => true,
+        }
+    }
+
     fn is_sequential(&self) -> bool {
         match self {
             Self::Sequential => true,
             Self::SequentialSingle => true,
+            Self::SequentialExact => true,
             _ => false,
         }
     }
@@ -144,11 +253,23 @@ impl Algorithm {
         match self {
             Self::Sequential => "Sequential".to_owned(),
             Self::SequentialSingle => "SequentialSingle".to_owned(),
+            Self::SequentialExact => "SequentialExact".to_owned(),
             Self::DeltaStepping(_) => "DeltaStepping".to_owned(),
+            Self::DeltaSteppingAuto => "DeltaStepping".to_owned(),
+            Self::AsyncSssp => "AsyncSssp".to_owned(),
             Self::HyperBall(_) => "HyperBall".to_owned(),
+            Self::HyperBallRepeat(_, _) => "HyperBallRepeat".to_owned(),
             Self::RandCluster(_, _) => "RandCluster".to_owned(),
             Self::RandClusterGuess(_, _, _) => "RandClusterGuess".to_owned(),
+            Self::RandClusterSearch(_, _, _, _) => "RandClusterSearch".to_owned(),
+            Self::RandClusterRecursive(_, _, _) => "RandClusterRecursive".to_owned(),
+            Self::Mpx(_) => "Mpx".to_owned(),
             Self::Bfs => "Bfs".to_owned(),
+            Self::MsBfs(_) => "MsBfs".to_owned(),
+            Self::Sweep(_) => "Sweep".to_owned(),
+            Self::Radius(_, _) => "Radius".to_owned(),
+            Self::PointToPoint(_, _) => "Distance".to_owned(),
+            Self::PointToPointBatch => "Distance".to_owned(),
         }
     }
 
@@ -156,11 +277,28 @@ impl Algorithm {
         match self {
             Self::Sequential => "".to_owned(),
             Self::SequentialSingle => "".to_owned(),
+            Self::SequentialExact => "".to_owned(),
             Self::DeltaStepping(delta) => format!("{}", delta),
+            Self::DeltaSteppingAuto => "auto".to_owned(),
+            Self::AsyncSssp => "".to_owned(),
             Self::HyperBall(p) => format!("{}", p),
+            Self::HyperBallRepeat(p, repetitions) => format!("{}:{}", p, repetitions),
             Self::RandCluster(radius, base) => format!("{}:{}", radius, base),
             Self::RandClusterGuess(memory, init, step) => format!("{}:{},{}", memory, init, step),
+            Self::RandClusterSearch(memory, init, step, reuse) => {
+                let reuse = if *reuse { ",reuse" } else { "" };
+                format!("{}:{},{}{}", memory, init, step, reuse)
+            }
+            Self::RandClusterRecursive(memory, init, step) => {
+                format!("{}:{},{}", memory, init, step)
+            }
+            Self::Mpx(beta) => format!("{}", beta),
             Self::Bfs => "".to_owned(),
+            Self::MsBfs(k) => format!("{}", k),
+            Self::Sweep(k) => format!("{}", k),
+            Self::Radius(k, radius) => format!("{}:{}", k, radius),
+            Self::PointToPoint(source, target) => format!("{}:{}", source, target),
+            Self::PointToPointBatch => "batch".to_owned(),
         }
     }
 }
@@ -172,18 +310,56 @@ impl TryFrom<&str> for Algorithm {
         use regex::Regex;
         let re_sequential = Regex::new(r"sequential").unwrap();
         let re_sequential_single = Regex::new(r"sequential-single").unwrap();
+        let re_sequential_exact = Regex::new(r"sequential-exact").unwrap();
         let re_delta_stepping = Regex::new(r"delta-stepping\((\d+)\)").unwrap();
+        let re_delta_stepping_auto = Regex::new(r"delta-stepping\(auto\)").unwrap();
+        let re_async_sssp = Regex::new(r"async-sssp").unwrap();
         let re_hyperball = Regex::new(r"hyperball\((\d+)\)").unwrap();
+        let re_hyperball_repeat = Regex::new(r"hyperball-repeat\((\d+), *(\d+)\)").unwrap();
         let re_rand_cluster = Regex::new(r"rand-cluster\((\d+), *(\d+)\)").unwrap();
         let re_rand_cluster_guess =
             Regex::new(r"rand-cluster-guess\((\d+), *(\d+), *(\d+)\)").unwrap();
+        let re_rand_cluster_search =
+            Regex::new(r"rand-cluster-search\((\d+), *(\d+), *(\d+)(, *reuse)?\)").unwrap();
+        let re_rand_cluster_recursive =
+            Regex::new(r"rand-cluster-recursive\((\d+), *(\d+), *(\d+)\)").unwrap();
+        let re_mpx = Regex::new(r"mpx\(([0-9.]+)\)").unwrap();
+        let re_ms_bfs = Regex::new(r"ms-bfs\((\d+)\)").unwrap();
         let re_bfs = Regex::new(r"bfs").unwrap();
+        let re_sweep = Regex::new(r"sweep\((\d+)\)").unwrap();
+        let re_radius = Regex::new(r"radius\((\d+), *(\d+)\)").unwrap();
+        let re_point_to_point = Regex::new(r"distance\((\d+), *(\d+)\)").unwrap();
+        let re_point_to_point_batch = Regex::new(r"distance\(batch\)").unwrap();
+        if let Some(_captures) = re_point_to_point_batch.captures(value) {
+            return Ok(Self::PointToPointBatch);
+        }
+        if let Some(captures) = re_point_to_point.captures(value) {
+            let source = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<Node>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let target = captures
+                .get(2)
+                .ok_or_else(|| format!("unable to get second capture"))?
+                .as_str()
+                .parse::<Node>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            return Ok(Self::PointToPoint(source, target));
+        }
         if let Some(_captures) = re_sequential_single.captures(value) {
             return Ok(Self::SequentialSingle);
         }
+        if let Some(_captures) = re_sequential_exact.captures(value) {
+            return Ok(Self::SequentialExact);
+        }
         if let Some(_captures) = re_sequential.captures(value) {
             return Ok(Self::Sequential);
         }
+        if let Some(_captures) = re_delta_stepping_auto.captures(value) {
+            return Ok(Self::DeltaSteppingAuto);
+        }
         if let Some(captures) = re_delta_stepping.captures(value) {
             let delta = captures
                 .get(1)
@@ -193,6 +369,33 @@ impl TryFrom<&str> for Algorithm {
                 .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
             return Ok(Self::DeltaStepping(delta));
         }
+        if let Some(_captures) = re_async_sssp.captures(value) {
+            return Ok(Self::AsyncSssp);
+        }
+        if let Some(captures) = re_hyperball_repeat.captures(value) {
+            let p = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<usize>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let repetitions = captures
+                .get(2)
+                .ok_or_else(|| format!("unable to get second capture"))?
+                .as_str()
+                .parse::<usize>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            if p < 4 || p > 16 {
+                return Err(format!(
+                    "Hyperball parameter should be between 4 and 16, got {} instead",
+                    p
+                ));
+            }
+            if repetitions < 1 {
+                return Err("HyperBall needs at least one repetition".to_owned());
+            }
+            return Ok(Self::HyperBallRepeat(p, repetitions));
+        }
         if let Some(captures) = re_hyperball.captures(value) {
             let p_str = captures
                 .get(1)
@@ -245,9 +448,109 @@ impl TryFrom<&str> for Algorithm {
                 .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
             return Ok(Self::RandClusterGuess(memory, init, step));
         }
+        if let Some(captures) = re_rand_cluster_search.captures(value) {
+            let memory = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let init = captures
+                .get(2)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let step = captures
+                .get(3)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let reuse = captures.get(4).is_some();
+            return Ok(Self::RandClusterSearch(memory, init, step, reuse));
+        }
+        if let Some(captures) = re_rand_cluster_recursive.captures(value) {
+            let memory = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let init = captures
+                .get(2)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let step = captures
+                .get(3)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            return Ok(Self::RandClusterRecursive(memory, init, step));
+        }
+        if let Some(captures) = re_mpx.captures(value) {
+            let beta = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<f64>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            if beta <= 0.0 {
+                return Err(format!("The rate should be positive, got {} instead", beta));
+            }
+            return Ok(Self::Mpx(beta));
+        }
+        if let Some(captures) = re_ms_bfs.captures(value) {
+            let k = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<usize>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            if k < 1 || k > 128 {
+                return Err(format!(
+                    "The number of sources should be between 1 and 128, got {} instead",
+                    k
+                ));
+            }
+            return Ok(Self::MsBfs(k));
+        }
         if let Some(_captures) = re_bfs.captures(value) {
             return Ok(Self::Bfs);
         }
+        if let Some(captures) = re_sweep.captures(value) {
+            let k = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            if k == 0 {
+                return Err("The number of sweeps should be at least 1".to_owned());
+            }
+            return Ok(Self::Sweep(k));
+        }
+        if let Some(captures) = re_radius.captures(value) {
+            let k = captures
+                .get(1)
+                .ok_or_else(|| format!("unable to get first capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            let radius = captures
+                .get(2)
+                .ok_or_else(|| format!("unable to get second capture"))?
+                .as_str()
+                .parse::<u32>()
+                .or_else(|e| Err(format!("error parsing number: {:?}", e)))?;
+            if k == 0 {
+                return Err("The number of sources should be at least 1".to_owned());
+            }
+            return Ok(Self::Radius(k, radius));
+        }
         Err(format!("Unrecognized algorithm: {}", value))
     }
 }
@@ -278,6 +581,48 @@ pub struct Config {
     verbose: bool,
     #[argh(switch, description = "keep the datasets on disk")]
     offline: bool,
+    #[argh(
+        option,
+        description = "save the node states every this many rounds, to be able to resume the run"
+    )]
+    checkpoint: Option<u32>,
+    #[argh(
+        option,
+        description = "resume the run with the given sha from its last checkpoint"
+    )]
+    resume: Option<String>,
+    #[argh(
+        switch,
+        description = "save bounds to the eccentricity of each node in the data directory"
+    )]
+    eccentricities: bool,
+    #[argh(
+        option,
+        description = "save the harmonic and closeness centralities of the nodes in the data directory, along with the given number of most central ones"
+    )]
+    centralities: Option<usize>,
+    #[argh(
+        switch,
+        description = "save a shortest path between the witness endpoints of the lower bound to the diameter in the data directory"
+    )]
+    paths: bool,
+    #[argh(
+        option,
+        description = "a file with a pair of nodes per line, whose distances are computed by distance(batch)"
+    )]
+    queries: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "bound on their own the diameters of this many largest connected components, if the graph is disconnected (one by default). Zero skips the check, as do the largest connected components datasets"
+    )]
+    components: Option<usize>,
+    #[argh(
+        option,
+        description = "the delta of the delta-stepping runs of sweep(k), radius(k) and of the check of the components, picked from the weights of the graph by default"
+    )]
+    delta: Option<u32>,
+    #[argh(option, description = "set automatically. Don't set manually")]
+    sha: Option<String>,
     #[argh(
         positional,
         description = "algortihm to use",
@@ -481,7 +826,7 @@ macro_rules! map(
 );
 
 fn list_datasets(datasets: &HashMap<String, Dataset>) {
-    let mut table: Vec<(String, Option<u32>, Option<u64>, Option<u32>, Option<u32>)> = datasets
+    let mut table: Vec<(String, Option<Node>, Option<u64>, Option<u32>, Option<u32>)> = datasets
         .iter()
         .map(|(name, dataset)| {
             if dataset.is_prepared() {
@@ -589,6 +934,76 @@ fn datasets_map(ddir: PathBuf) -> HashMap<String, Dataset> {
     datasets
 }
 
+/// Logs the mean and the standard deviation of the estimates of the
+/// repetitions of HyperBall
+fn log_hyperball_repetitions(repetitions: &hyperball::HyperBallRepetitions) {
+    let (mean, stddev) =
+        hyperball::mean_and_stddev(repetitions.diameters.iter().map(|&d| d as f64));
+    info!(
+        "HyperBall diameter {:.2} ± {:.2} over {} repetitions",
+        mean,
+        stddev,
+        repetitions.diameters.len()
+    );
+    if repetitions.neighbourhoods.is_empty() {
+        return;
+    }
+    let statistics: [(&str, fn(&hyperball::NeighbourhoodFunction) -> f64); 3] = [
+        ("effective diameter", |n| n.effective_diameter(0.9)),
+        ("average distance", |n| n.average_distance()),
+        ("harmonic diameter", |n| n.harmonic_diameter()),
+    ];
+    for (name, statistic) in statistics.iter() {
+        let (mean, stddev) =
+            hyperball::mean_and_stddev(repetitions.neighbourhoods.iter().map(statistic));
+        info!("HyperBall {} {:.2} ± {:.2}", name, mean, stddev);
+    }
+    for (t, (mean, stddev)) in repetitions
+        .neighbourhood_mean_and_stddev()
+        .into_iter()
+        .enumerate()
+    {
+        info!("N({}) = {:.0} ± {:.0}", t, mean, stddev);
+    }
+}
+
+/// Converts the result of an algorithm to the widest distance type
+fn widen<D: Distance>(
+    (bounds, elapsed): (Option<DiameterBounds<D>>, Duration),
+) -> (Option<DiameterBounds<u64>>, Duration) {
+    (bounds.map(|b| b.to_u64()), elapsed)
+}
+
+/// Evaluates `body` with the distance type `D` standing for 64 bits if
+/// `wide`, and for 32 bits otherwise
+macro_rules! with_width(
+    ($wide:expr, $d:ident, $body:expr) => {
+        if $wide {
+            type $d = u64;
+            $body
+        } else {
+            type $d = u32;
+            $body
+        }
+    };
+);
+
+fn sequential_diameter<D: Distance>(
+    algorithm: Algorithm,
+    edges: Vec<((Node, Node), u32)>,
+    n: Node,
+) -> (DiameterBounds<u64>, Vec<(Node, u64)>, Duration) {
+    let edges = edges.into_iter().map(|(e, w)| (e, D::from_u32(w)));
+    let (bounds, path, elapsed) = match algorithm {
+        Algorithm::Sequential => sequential::approx_diameter(edges, n),
+        Algorithm::SequentialSingle => sequential::single_pass(edges, n),
+        Algorithm::SequentialExact => sequential::ifub_diameter(edges, n),
+        _ => panic!(),
+    };
+    let path = path.into_iter().map(|(x, d)| (x, d.to_u64())).collect();
+    (bounds.to_u64(), path, elapsed)
+}
+
 fn main() -> Result<()> {
     if let Some("list") = std::env::args().nth(1).as_ref().map(|s| s.as_str()) {
         if let Some(ddir) = std::env::args().nth(2) {
@@ -616,12 +1031,21 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    let config = Config::create();
+    let mut config = Config::create();
     logging::init_logging(config.verbose);
     if let Some(sha) = reporter::Reporter::new(config.clone()).already_run() {
         info!("Parameter configuration already run (sha {}), exiting", sha);
         return Ok(());
     }
+    if config.sha.is_none() {
+        // Fix the sha upfront, so that all the processes save their checkpoints under it
+        let sha = match &config.resume {
+            Some(sha) => sha.clone(),
+            None => reporter::Reporter::new(config.clone()).sha(),
+        };
+        info!("Run sha {}", sha);
+        config.sha.replace(sha);
+    }
 
     let mut datasets = datasets_map(config.ddir.clone());
 
@@ -638,29 +1062,64 @@ fn main() -> Result<()> {
         config.hosts.as_ref().unwrap().rsync(config.ddir.clone());
     }
 
+    // Distances are 64 bits wide only if they may not fit in 32 bits
+    let wide = !distance::fits_u32(&meta);
+    if wide {
+        info!("Using 64 bits distances");
+    }
+
     let algorithm = config.algorithm;
     let seed = config.seed();
+    if config.eccentricities && !algorithm.supports_eccentricities() {
+        warn!(
+            "{} gives no bounds to the eccentricities of the nodes, they won't be saved",
+            algorithm.name()
+        );
+    }
+    if config.centralities.is_some() && !algorithm.supports_centralities() {
+        warn!(
+            "{} does not compute the centralities of the nodes, they won't be saved",
+            algorithm.name()
+        );
+    }
+    // The components are checked unless the graph is connected by construction
+    let num_components = if dataset.is_connected() {
+        if config.components.unwrap_or(0) > 0 {
+            info!(
+                "{} is connected, skipping the check of its components",
+                config.dataset
+            );
+        }
+        0
+    } else {
+        config.components.unwrap_or(1)
+    };
+    if config.paths && !algorithm.supports_paths(&meta) {
+        warn!(
+            "{} keeps no shortest paths tree, the path won't be saved",
+            algorithm.name()
+        );
+    }
     let config2 = config.clone();
 
     if algorithm.is_sequential() {
         let mut reporter = reporter::Reporter::new(config2.clone());
         let edges = dataset.as_vec();
         let timer = std::time::Instant::now();
-        let (eccentricities, diam_elapsed) = match algorithm {
-            Algorithm::Sequential => sequential::approx_diameter(edges, n),
-            Algorithm::SequentialSingle => sequential::single_pass(edges, n),
-            _ => panic!(),
-        };
-        let (diam, _) = eccentricities
-            .into_iter()
-            .max_by_key(|pair| pair.0)
-            .unwrap();
+        let (bounds, path, diam_elapsed) =
+            with_width!(wide, D, sequential_diameter::<D>(algorithm, edges, n));
         let elapsed = timer.elapsed();
         info!(
-            "Diameter {}, computed in {:?} ({:?} with data rearrangement)",
-            diam, diam_elapsed, elapsed
+            "Diameter bounds {:?}, computed in {:?} ({:?} with data rearrangement)",
+            bounds, diam_elapsed, elapsed
         );
-        reporter.set_result(diam, diam_elapsed);
+        paths::Paths::new(
+            &config2.ddir,
+            config2.sha.as_ref().expect("missing sha"),
+            config2.paths,
+        )
+        .save(&path);
+        reporter.set_result(bounds, diam_elapsed);
         reporter.report();
     } else {
         let ret_status = config.execute(move |worker| {
@@ -698,46 +1157,380 @@ fn main() -> Result<()> {
             worker.step_while(|| !probe.done());
 
             info!("loaded adjacencies statically ({:?})", adj_timer.elapsed());
+            let checkpoints = checkpoint::Checkpoints::new(
+                &config2.ddir,
+                config2.sha.as_ref().expect("missing sha"),
+                worker.index(),
+                config2.checkpoint,
+                config2.resume.is_some(),
+            );
+            let eccentricities = eccentricities::Eccentricities::new(
+                &config2.ddir,
+                config2.sha.as_ref().expect("missing sha"),
+                worker.index(),
+                config2.eccentricities,
+            );
+            let centralities = centralities::Centralities::new(
+                &config2.ddir,
+                config2.sha.as_ref().expect("missing sha"),
+                worker.index(),
+                config2.centralities,
+            );
+            let paths = paths::Paths::new(
+                &config2.ddir,
+                config2.sha.as_ref().expect("missing sha"),
+                config2.paths && algorithm.supports_paths(&meta),
+            );
             let mut final_approx_probe = None;
             let mut iteration_info = Vec::new();
+            let mut radius_bounds = None;
+            let mut hyperball_repetitions = None;
+            let mut distance_answers = None;
+            let mut picked_delta = None;
+
+            let component_adjacencies = DistributedAdjacencies::clone(&adjacencies);
+            let algorithm = match algorithm {
+                Algorithm::DeltaSteppingAuto => {
+                    let delta = auto_delta(&adjacencies, worker, &meta, seed);
+                    info!("Running delta-stepping with delta {}", delta);
+                    picked_delta.replace(delta);
+                    Algorithm::DeltaStepping(delta)
+                }
+                algorithm => algorithm,
+            };
 
-            let (diameter, elapsed): (Option<u32>, Duration) = match algorithm {
+            let (bounds, elapsed): (Option<DiameterBounds<u64>>, Duration) = match algorithm {
                 Algorithm::DeltaStepping(delta) => {
-                    delta_stepping(adjacencies, worker, delta, n, seed)
+                    with_width!(
+                        wide,
+                        D,
+                        widen(delta_stepping::<D, _>(
+                            adjacencies,
+                            worker,
+                            delta,
+                            n,
+                            seed,
+                            &checkpoints,
+                            &eccentricities,
+                            &paths,
+                        ))
+                    )
+                }
+                Algorithm::AsyncSssp => {
+                    with_width!(
+                        wide,
+                        D,
+                        widen(async_sssp::async_sssp::<D, _>(adjacencies, worker, n, seed))
+                    )
+                }
+                Algorithm::HyperBall(p) => {
+                    let (bounds, repetitions, elapsed) = hyperball::hyperball(
+                        adjacencies,
+                        worker,
+                        p,
+                        1,
+                        seed,
+                        &checkpoints,
+                        &centralities,
+                    );
+                    hyperball_repetitions = repetitions;
+                    widen((bounds, elapsed))
                 }
-                Algorithm::HyperBall(p) => hyperball::hyperball(adjacencies, worker, p, seed),
-                Algorithm::Bfs => bfs::bfs(adjacencies, worker, n, seed),
-                Algorithm::RandCluster(radius, base) => rand_cluster::rand_cluster(
+                Algorithm::HyperBallRepeat(p, repetitions) => {
+                    let (bounds, repetitions, elapsed) = hyperball::hyperball(
+                        adjacencies,
+                        worker,
+                        p,
+                        repetitions,
+                        seed,
+                        &checkpoints,
+                        &centralities,
+                    );
+                    hyperball_repetitions = repetitions;
+                    widen((bounds, elapsed))
+                }
+                Algorithm::Bfs => bfs::bfs(
+                    adjacencies,
+                    worker,
+                    n,
+                    meta.max_weight,
+                    seed,
+                    &eccentricities,
+                    &paths,
+                ),
+                Algorithm::MsBfs(k) => ms_bfs::ms_bfs_sample(
                     adjacencies,
                     worker,
-                    radius,
-                    base,
+                    k,
                     n,
+                    meta.max_weight,
                     seed,
-                    &mut final_approx_probe,
+                    &eccentricities,
                 ),
+                Algorithm::Sweep(k) => {
+                    let weights = (meta.min_weight, meta.max_weight);
+                    let delta = config2
+                        .delta
+                        .unwrap_or_else(|| auto_delta(&adjacencies, worker, &meta, seed));
+                    picked_delta.replace(delta);
+                    with_width!(
+                        wide,
+                        D,
+                        widen(sweep::sweep::<D, _>(
+                            adjacencies,
+                            worker,
+                            k,
+                            n,
+                            weights,
+                            delta,
+                            seed,
+                            &checkpoints,
+                            &eccentricities,
+                        ))
+                    )
+                }
+                Algorithm::Radius(k, cluster_radius) => {
+                    let weights = (meta.min_weight, meta.max_weight);
+                    let delta = config2
+                        .delta
+                        .unwrap_or_else(|| auto_delta(&adjacencies, worker, &meta, seed));
+                    picked_delta.replace(delta);
+                    let (bounds, radius, elapsed) = with_width!(
+                        wide,
+                        D,
+                        radius::radius::<D, _>(
+                            adjacencies,
+                            worker,
+                            k,
+                            cluster_radius,
+                            n,
+                            weights,
+                            delta,
+                            seed,
+                            &checkpoints,
+                            &eccentricities,
+                        )
+                    );
+                    radius_bounds = radius;
+                    (bounds, elapsed)
+                }
+                Algorithm::RandCluster(radius, base) => {
+                    with_width!(
+                        wide,
+                        D,
+                        widen(rand_cluster::rand_cluster::<D, _>(
+                            adjacencies,
+                            worker,
+                            radius,
+                            base,
+                            n,
+                            seed,
+                            &mut final_approx_probe,
+                            &checkpoints,
+                            &eccentricities,
+                        ))
+                    )
+                }
                 Algorithm::RandClusterGuess(memory, init, step) => {
-                    rand_cluster::rand_cluster_guess(
-                        adjacencies,
-                        worker,
-                        memory,
-                        init,
-                        step,
-                        n,
-                        seed,
-                        &mut final_approx_probe,
-                        &mut iteration_info,
+                    with_width!(
+                        wide,
+                        D,
+                        widen(rand_cluster::rand_cluster_guess::<D, _>(
+                            adjacencies,
+                            worker,
+                            memory,
+                            init,
+                            step,
+                            rand_cluster::RadiusSearch::Geometric,
+                            n,
+                            seed,
+                            &mut final_approx_probe,
+                            &mut iteration_info,
+                            &checkpoints,
+                            &eccentricities,
+                        ))
+                    )
+                }
+                Algorithm::RandClusterSearch(memory, init, step, reuse) => {
+                    with_width!(
+                        wide,
+                        D,
+                        widen(rand_cluster::rand_cluster_guess::<D, _>(
+                            adjacencies,
+                            worker,
+                            memory,
+                            init,
+                            step,
+                            rand_cluster::RadiusSearch::Interpolated { reuse },
+                            n,
+                            seed,
+                            &mut final_approx_probe,
+                            &mut iteration_info,
+                            &checkpoints,
+                            &eccentricities,
+                        ))
+                    )
+                }
+                Algorithm::RandClusterRecursive(memory, init, step) => {
+                    with_width!(
+                        wide,
+                        D,
+                        widen(rand_cluster::rand_cluster_recursive::<D, _>(
+                            adjacencies,
+                            worker,
+                            memory,
+                            init,
+                            step,
+                            n,
+                            seed,
+                            &mut final_approx_probe,
+                            &mut iteration_info,
+                            &checkpoints,
+                        ))
+                    )
+                }
+                Algorithm::Mpx(beta) => {
+                    with_width!(
+                        wide,
+                        D,
+                        widen(rand_cluster::mpx::<D, _>(
+                            adjacencies,
+                            worker,
+                            beta,
+                            n,
+                            seed,
+                            &mut final_approx_probe,
+                            &eccentricities,
+                        ))
                     )
                 }
-                Algorithm::Sequential | Algorithm::SequentialSingle => {
+                Algorithm::PointToPoint(_, _) | Algorithm::PointToPointBatch => {
+                    // Only the first worker needs the queries
+                    let queries = match algorithm {
+                        _ if worker.index() != 0 => Vec::new(),
+                        Algorithm::PointToPoint(source, target) => vec![(source, target)],
+                        _ => point_to_point::read_queries(
+                            config2
+                                .queries
+                                .as_ref()
+                                .expect("distance(batch) needs a file of queries"),
+                        ),
+                    };
+                    // On unweighted graphs the searches are BFSs
+                    let delta = if meta.min_weight == meta.max_weight {
+                        0
+                    } else {
+                        auto_delta(&adjacencies, worker, &meta, seed)
+                    };
+                    let (bounds, answers, elapsed) = with_width!(wide, D, {
+                        let (bounds, answers, elapsed) = point_to_point::point_to_point::<D, _>(
+                            &adjacencies,
+                            worker,
+                            delta,
+                            &queries,
+                        );
+                        let answers = answers
+                            .into_iter()
+                            .map(|(pair, distance)| (pair, distance.map(|d| d.to_u64())))
+                            .collect();
+                        (bounds.map(|b| b.to_u64()), answers, elapsed)
+                    });
+                    distance_answers.replace(answers);
+                    (bounds, elapsed)
+                }
+                Algorithm::Sequential
+                | Algorithm::SequentialSingle
+                | Algorithm::SequentialExact => {
                     panic!("sequential algorithm not supported in dataflow")
                 }
+                Algorithm::DeltaSteppingAuto => unreachable!("delta is picked before running"),
             };
 
+            // Most algorithms only look at the component of their root
+            let (bounds, components) = if num_components > 0 && algorithm.checks_components() {
+                let weights = (meta.min_weight, meta.max_weight);
+                let root = random_root(&component_adjacencies, worker, n, seed);
+                let delta = config2
+                    .delta
+                    .unwrap_or_else(|| auto_delta(&component_adjacencies, worker, &meta, seed));
+                let checkpoints = checkpoints.phase("components");
+                let (components, components_elapsed) = with_width!(
+                    wide,
+                    D,
+                    components::components::<D, _>(
+                        &component_adjacencies,
+                        worker,
+                        num_components,
+                        n,
+                        root,
+                        weights,
+                        delta,
+                        &checkpoints,
+                    )
+                );
+                info!(
+                    "Found {} connected components in {:?}",
+                    components.count, components_elapsed
+                );
+                (
+                    bounds.map(|b| components.cover(b)),
+                    Some((components, components_elapsed)),
+                )
+            } else {
+                (bounds, None)
+            };
+
+            // The run is complete, we have no use for its checkpoints anymore
+            checkpoints.clear();
+
             if worker.index() == 0 {
-                reporter
-                    .borrow_mut()
-                    .set_result(diameter.expect("missing diameter"), elapsed);
+                let bounds = bounds.expect("missing diameter bounds");
+                info!(
+                    "Diameter bounds {:?}, with ratio {:?}",
+                    bounds,
+                    bounds.ratio()
+                );
+                reporter.borrow_mut().set_result(bounds, elapsed);
+                if let Some((components, components_elapsed)) = components {
+                    if !components.is_connected() {
+                        warn!(
+                            "The graph is disconnected, {} nodes are out of the largest component",
+                            components.unreachable()
+                        );
+                        for component in components.largest.iter() {
+                            info!(
+                                "Component of {} with {} nodes, diameter bounds {:?}",
+                                component.root, component.size, component.bounds
+                            );
+                        }
+                    }
+                    reporter
+                        .borrow_mut()
+                        .set_components(components, components_elapsed);
+                }
+                if let Some(radius) = radius_bounds.take() {
+                    info!(
+                        "Radius between {} and {} (center {}), with {} candidate centers",
+                        radius.lower, radius.upper, radius.center, radius.num_candidates
+                    );
+                    reporter.borrow_mut().set_radius(radius);
+                }
+                if let Some(repetitions) = hyperball_repetitions.take() {
+                    log_hyperball_repetitions(&repetitions);
+                    reporter.borrow_mut().set_hyperball_repetitions(repetitions);
+                }
+                if let Some(answers) = distance_answers.take() {
+                    for ((source, target), distance) in answers.iter() {
+                        match distance {
+                            Some(distance) => info!("d({}, {}) = {}", source, target, distance),
+                            None => info!("{} is unreachable from {}", target, source),
+                        }
+                    }
+                    reporter.borrow_mut().set_distance_answers(answers);
+                }
+                if let Some(delta) = picked_delta.take() {
+                    reporter.borrow_mut().set_delta(delta);
+                }
                 if let Some(final_approx_time) = final_approx_probe.take() {
                     reporter
                         .borrow_mut()
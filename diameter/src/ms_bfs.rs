@@ -0,0 +1,305 @@
+use crate::bounds::DiameterBounds;
+use crate::distributed_adjacencies::*;
+use crate::eccentricities::*;
+use crate::node::Node;
+use crate::operators::*;
+use crate::vertex_program::*;
+use rand::SeedableRng;
+use rand_xoshiro::Xoroshiro128StarStar;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use timely::communication::Allocate;
+use timely::dataflow::operators::*;
+use timely::progress::Timestamp;
+use timely::ExchangeData;
+
+/// A set of sources of a multi-source BFS, as a bitset with a bit per source
+pub trait SourceSet: ExchangeData + Serialize + Copy + Default + Debug {
+    /// How many sources fit in a set
+    const CAPACITY: usize;
+
+    fn singleton(source: usize) -> Self;
+
+    fn union(self, other: Self) -> Self;
+
+    /// The sources in `self` that are not in `other`
+    fn difference(self, other: Self) -> Self;
+
+    fn is_empty(self) -> bool;
+
+    /// Calls `action` on each source in the set, in increasing order
+    fn for_each<F: FnMut(usize)>(self, action: F);
+}
+
+fn for_each_bit<F: FnMut(usize)>(mut word: u64, offset: usize, action: &mut F) {
+    while word != 0 {
+        action(offset + word.trailing_zeros() as usize);
+        word &= word - 1;
+    }
+}
+
+impl SourceSet for u64 {
+    const CAPACITY: usize = 64;
+
+    fn singleton(source: usize) -> Self {
+        1 << source
+    }
+
+    fn union(self, other: Self) -> Self {
+        self | other
+    }
+
+    fn difference(self, other: Self) -> Self {
+        self & !other
+    }
+
+    fn is_empty(self) -> bool {
+        self == 0
+    }
+
+    fn for_each<F: FnMut(usize)>(self, mut action: F) {
+        for_each_bit(self, 0, &mut action);
+    }
+}
+
+impl SourceSet for [u64; 2] {
+    const CAPACITY: usize = 128;
+
+    fn singleton(source: usize) -> Self {
+        let mut set = [0; 2];
+        set[source / 64] = 1 << (source % 64);
+        set
+    }
+
+    fn union(self, other: Self) -> Self {
+        [self[0] | other[0], self[1] | other[1]]
+    }
+
+    fn difference(self, other: Self) -> Self {
+        [self[0] & !other[0], self[1] & !other[1]]
+    }
+
+    fn is_empty(self) -> bool {
+        self == [0, 0]
+    }
+
+    fn for_each<F: FnMut(usize)>(self, mut action: F) {
+        for_each_bit(self[0], 0, &mut action);
+        for_each_bit(self[1], 64, &mut action);
+    }
+}
+
+#[derive(Debug, Clone, Default, Abomonation, Serialize, Deserialize)]
+struct State<B> {
+    /// The sources that reached the node
+    seen: B,
+    /// The sources that reached the node in the last round
+    frontier: B,
+}
+
+/// Each node forwards the sources that reached it in the last round, which
+/// its neighbours keep if they see them for the first time
+#[derive(Clone)]
+struct MsBfsProgram<B> {
+    sources: PhantomData<B>,
+}
+
+impl<T: Timestamp, B: SourceSet> VertexProgram<T> for MsBfsProgram<B> {
+    type State = State<B>;
+    type Message = B;
+
+    fn should_send(&self, _time: &T, state: &State<B>) -> bool {
+        !state.frontier.is_empty()
+    }
+
+    fn message(&self, _time: &T, _id: Node, state: &State<B>, _weight: u32) -> Option<B> {
+        Some(state.frontier)
+    }
+
+    fn combine(s1: &B, s2: &B) -> B {
+        s1.union(*s2)
+    }
+
+    fn apply(&self, _time: &T, state: &State<B>, message: Option<&B>) -> State<B> {
+        match message {
+            Some(sources) => {
+                let new = sources.difference(state.seen);
+                State {
+                    seen: state.seen.union(new),
+                    frontier: new,
+                }
+            }
+            None => State {
+                seen: state.seen,
+                frontier: B::default(),
+            },
+        }
+    }
+
+    fn is_active(&self, _time: &T, state: &State<B>) -> bool {
+        !state.frontier.is_empty()
+    }
+}
+
+/// Computes the exact eccentricities in hops of `k` random nodes with a
+/// single multi-source BFS, bounding the diameter with each of them as
+/// `bfs` does with its root.
+pub fn ms_bfs_sample<A: Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    k: usize,
+    n: Node,
+    max_weight: u32,
+    seed: u64,
+    eccentricities: &Eccentricities,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
+    let mut rng = Xoroshiro128StarStar::seed_from_u64(seed);
+    let sources: Vec<Node> =
+        rand::seq::index::sample(&mut rng, n as usize, std::cmp::min(k, n as usize))
+            .into_iter()
+            .map(|x| x as Node)
+            .collect();
+    let (farthest, elapsed) = if sources.len() <= u64::CAPACITY {
+        ms_bfs::<u64, _>(&adjacencies, worker, &sources)
+    } else {
+        ms_bfs::<[u64; 2], _>(&adjacencies, worker, &sources)
+    };
+
+    let mut node_bounds = EccentricityBounds::new();
+    let mut bounds: Option<DiameterBounds<u64>> = None;
+    for (&source, &(hops, far)) in sources.iter().zip(farthest.iter()) {
+        if worker.index() == 0 {
+            info!(
+                "Source {}: eccentricity {} hops, reaching {}",
+                source, hops, far
+            );
+        }
+        if source % worker.peers() as Node == worker.index() as Node {
+            node_bounds.refine(source, hops as u64, hops as u64 * max_weight as u64);
+        }
+        // As for `bfs`, the upper bound must account for the heaviest edge
+        let sweep_bounds = DiameterBounds::from_sweep(hops as u64, source, far);
+        let sweep_bounds = DiameterBounds {
+            upper: sweep_bounds.upper.map(|upper| upper * max_weight as u64),
+            ..sweep_bounds
+        };
+        bounds = Some(match bounds {
+            Some(bounds) => bounds.intersect(sweep_bounds),
+            None => sweep_bounds,
+        });
+    }
+    eccentricities.save(&node_bounds);
+
+    (bounds, elapsed)
+}
+
+/// Runs a BFS from each of the given sources at once, in the style of
+/// MS-BFS: each node keeps the set of the sources that reached it, so that
+/// the BFSs share the rounds and the messages between the nodes. Returns,
+/// on all the workers, the eccentricity in hops of each source, along with
+/// the farthest node from it (the largest one among the ties).
+pub fn ms_bfs<B: SourceSet, A: Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    sources: &[Node],
+) -> (Vec<(u32, Node)>, std::time::Duration) {
+    assert!(
+        sources.len() <= B::CAPACITY,
+        "at most {} sources per multi-source BFS, got {}",
+        B::CAPACITY,
+        sources.len()
+    );
+
+    let mut sets: HashMap<Node, B> = HashMap::new();
+    for (i, &source) in sources.iter().enumerate() {
+        let set = sets.entry(source).or_insert_with(B::default);
+        *set = set.union(B::singleton(i));
+    }
+    let roots: Vec<(Node, State<B>)> = if worker.index() == 0 {
+        sets.into_iter()
+            .map(|(id, set)| {
+                let state = State {
+                    seen: set,
+                    frontier: set,
+                };
+                (id, state)
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+
+    // The last round each source reached a node of this worker, and the node
+    let local_farthest = Rc::new(RefCell::new(
+        sources
+            .iter()
+            .map(|&source| (0, source))
+            .collect::<Vec<_>>(),
+    ));
+    let local_farthest_ref = Rc::clone(&local_farthest);
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        let nodes = roots.to_stream(scope).exchange(|p| p.0 as u64);
+        let program = MsBfsProgram {
+            sources: PhantomData,
+        };
+        run_until_quiescent_observing(
+            adjacencies,
+            &nodes,
+            program,
+            move |round, id, state: &State<B>| {
+                let mut farthest = local_farthest_ref.borrow_mut();
+                state.frontier.for_each(|i| {
+                    farthest[i] = std::cmp::max(farthest[i], (round + 1, id));
+                });
+            },
+        )
+        .probe()
+    });
+    let elapsed = run_to_completion(worker, probe);
+
+    // Every worker gets the farthest nodes of all the others
+    let local_farthest = local_farthest.replace(Vec::new());
+    let farthest = Rc::new(RefCell::new(local_farthest.clone()));
+    let farthest_ref = Rc::clone(&farthest);
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        local_farthest
+            .into_iter()
+            .enumerate()
+            .collect::<Vec<_>>()
+            .to_stream(scope)
+            .broadcast()
+            .inspect(move |&(i, far)| {
+                let mut farthest = farthest_ref.borrow_mut();
+                farthest[i] = std::cmp::max(farthest[i], far);
+            })
+            .probe()
+    });
+    run_to_completion(worker, probe);
+
+    let farthest = farthest.replace(Vec::new());
+    (farthest, elapsed)
+}
+
+#[test]
+fn test_source_sets() {
+    let set = <[u64; 2]>::singleton(3)
+        .union(<[u64; 2]>::singleton(64))
+        .union(<[u64; 2]>::singleton(127));
+    let mut sources = Vec::new();
+    set.for_each(|i| sources.push(i));
+    assert_eq!(sources, vec![3, 64, 127]);
+
+    let rest = set.difference(<[u64; 2]>::singleton(64));
+    let mut sources = Vec::new();
+    rest.for_each(|i| sources.push(i));
+    assert_eq!(sources, vec![3, 127]);
+    assert!(rest.difference(set).is_empty());
+
+    let mut sources = Vec::new();
+    (u64::singleton(0) | u64::singleton(63)).for_each(|i| sources.push(i));
+    assert_eq!(sources, vec![0, 63]);
+}
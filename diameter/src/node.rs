@@ -0,0 +1,14 @@
+/// The identifier of a node. It is 32 bits wide, which is enough for all
+/// the graphs with less than 2^32 nodes and keeps messages and states compact.
+/// Larger graphs need the `wide-ids` feature, which makes it 64 bits wide.
+#[cfg(not(feature = "wide-ids"))]
+pub type Node = u32;
+#[cfg(feature = "wide-ids")]
+pub type Node = u64;
+
+/// Appended to the names of the files holding data that depends on the width
+/// of the identifiers, so that both widths can share a data directory.
+#[cfg(not(feature = "wide-ids"))]
+pub const WIDTH_SUFFIX: &str = "";
+#[cfg(feature = "wide-ids")]
+pub const WIDTH_SUFFIX: &str = "-64";
@@ -1,3 +1,8 @@
+use crate::distributed_adjacencies::DistributedAdjacencies;
+use crate::node::Node;
+use rand::distributions::Uniform;
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256StarStar;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -10,6 +15,7 @@ use timely::dataflow::Stream;
 use timely::progress::Timestamp;
 use timely::worker::Worker;
 use timely::Data;
+use timely::ExchangeData;
 
 pub trait BranchAll<G: Scope, D: Data> {
     /// The right branch contains nodes if and only if
@@ -128,12 +134,12 @@ impl<G: Scope, D: Data> MapTimed<G, D> for Stream<G, D> {
     }
 }
 
-pub trait CollectSingle<T: Timestamp> {
-    fn collect_single(&self) -> (Rc<RefCell<Option<u32>>>, ProbeHandle<T>);
+pub trait CollectSingle<T: Timestamp, D> {
+    fn collect_single(&self) -> (Rc<RefCell<Option<D>>>, ProbeHandle<T>);
 }
 
-impl<G: Scope> CollectSingle<G::Timestamp> for Stream<G, u32> {
-    fn collect_single(&self) -> (Rc<RefCell<Option<u32>>>, ProbeHandle<G::Timestamp>) {
+impl<G: Scope, D: Data + Copy> CollectSingle<G::Timestamp, D> for Stream<G, D> {
+    fn collect_single(&self) -> (Rc<RefCell<Option<D>>>, ProbeHandle<G::Timestamp>) {
         use timely::dataflow::operators::{Operator, Probe};
 
         let result = Rc::new(RefCell::new(None));
@@ -162,3 +168,66 @@ pub fn run_to_completion<A: Allocate, T: Timestamp>(
     worker.step_while(|| !probe.done());
     timer.elapsed()
 }
+
+/// The smallest of the values of the workers, on all of them
+pub fn global_min<T: ExchangeData + Ord + Copy, A: Allocate>(
+    worker: &mut Worker<A>,
+    local: Option<T>,
+) -> Option<T> {
+    use timely::dataflow::operators::*;
+
+    let (result, probe) = worker.dataflow::<(), _, _>(move |scope| {
+        local
+            .into_iter()
+            .to_stream(scope)
+            .exchange(|_| 0)
+            .accumulate(None, |min: &mut Option<T>, data| {
+                for &x in data.iter() {
+                    *min = Some(min.map_or(x, |min| std::cmp::min(min, x)));
+                }
+            })
+            .broadcast()
+            .collect_single()
+    });
+    run_to_completion(worker, probe);
+    result.replace(None).flatten()
+}
+
+/// How many candidate roots are drawn at once by `random_root`
+const ROOT_CANDIDATES: usize = 64;
+
+/// Samples the root of a single source computation among the nodes with
+/// at least an edge, so that it is not isolated: given the same seed, all
+/// the workers agree on the same root. The candidates are drawn uniformly
+/// among the `n` nodes, a batch at a time, and the first one with edges is
+/// the root. Must be called by all the workers.
+pub fn random_root<A: Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut Worker<A>,
+    n: Node,
+    seed: u64,
+) -> Node {
+    let mut rng = Xoshiro256StarStar::seed_from_u64(seed);
+    let dist = Uniform::new(0, n);
+    let mut checked_edges = false;
+    loop {
+        let candidates: Vec<Node> = (0..ROOT_CANDIDATES)
+            .map(|_| dist.sample(&mut rng))
+            .collect();
+        // Each worker only knows the edges of its own nodes
+        let local = candidates
+            .iter()
+            .position(|&id| !adjacencies.neighbours(id).is_empty());
+        if let Some(i) = global_min(worker, local) {
+            return candidates[i];
+        }
+        if !checked_edges {
+            let has_edges = Some(()).filter(|_| adjacencies.num_local_nodes() > 0);
+            if global_min(worker, has_edges).is_none() {
+                warn!("The graph has no edges, the root is isolated");
+                return candidates[0];
+            }
+            checked_edges = true;
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::distance::Distance;
+use crate::node::Node;
+use crate::operators::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use timely::communication::Allocate;
+use timely::dataflow::operators::*;
+use timely::order::Product;
+
+/// Where the shortest path between the witness endpoints of the lower bound
+/// to the diameter is saved, in the data directory under the sha of the run.
+/// The path goes in a text file with a line per node, from the first
+/// endpoint to the second, made of the node and its distance from the
+/// first endpoint, separated by a tab.
+#[derive(Clone)]
+pub struct Paths {
+    dir: PathBuf,
+    sha: String,
+    enabled: bool,
+}
+
+/// The nodes of a shortest paths tree owned by a worker, each with its
+/// distance from the root and its parent (none for the root)
+pub type ShortestPathsTree<D> = HashMap<Node, (D, Option<Node>)>;
+
+impl Paths {
+    pub fn new(ddir: &Path, sha: &str, enabled: bool) -> Self {
+        let mut dir = ddir.to_path_buf();
+        dir.push("paths");
+        Self {
+            dir,
+            sha: sha.to_owned(),
+            enabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn save<D: Distance>(&self, path: &[(Node, D)]) {
+        if !self.enabled {
+            return;
+        }
+        std::fs::create_dir_all(&self.dir).expect("problem creating paths directory");
+        let file = self.dir.join(format!("{}.tsv", self.sha));
+        let mut writer = BufWriter::new(File::create(&file).expect("problem creating path file"));
+        for (node, distance) in path.iter() {
+            writeln!(writer, "{}\t{}", node, distance).expect("problem writing path");
+        }
+        writer.flush().expect("problem writing path");
+        info!("Path of {} nodes saved to {:?}", path.len(), file);
+    }
+}
+
+/// Follows the parent pointers of a distributed shortest paths tree from
+/// `target` up to the root, one hop per round, each hop being taken by the
+/// worker owning the node. Returns the path from the root to `target`,
+/// with the distances of its nodes, on the first worker, and an empty
+/// path on the others.
+pub fn trace_path<D: Distance, A: Allocate>(
+    worker: &mut timely::worker::Worker<A>,
+    tree: ShortestPathsTree<D>,
+    target: Node,
+) -> Vec<(Node, D)> {
+    let path = Rc::new(RefCell::new(Vec::new()));
+    let path_ref = Rc::clone(&path);
+
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        let start = if scope.index() == 0 {
+            vec![(0u32, target)]
+        } else {
+            vec![]
+        }
+        .to_stream(scope);
+
+        let hops = scope.iterative::<u32, _, _>(move |inner_scope| {
+            let (handle, cycle) = inner_scope.feedback(Product::new(Default::default(), 1));
+            let hops = start
+                .enter(inner_scope)
+                .concat(&cycle)
+                .exchange(|&(_step, id)| id as u64)
+                .map(move |(step, id)| {
+                    let (distance, parent) = tree.get(&id).expect("node out of the tree");
+                    (step, id, *distance, *parent)
+                });
+            hops.flat_map(|(step, _id, _distance, parent)| parent.map(|p| (step + 1, p)))
+                .connect_loop(handle);
+            hops.map(|(step, id, distance, _parent)| (step, id, distance))
+                .leave()
+        });
+
+        hops.exchange(|_| 0)
+            .inspect(move |&hop| path_ref.borrow_mut().push(hop))
+            .probe()
+    });
+
+    let elapsed = run_to_completion(worker, probe);
+    let mut hops = path.replace(Vec::new());
+    hops.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+    if worker.index() == 0 {
+        info!("Traced a path of {} hops in {:?}", hops.len(), elapsed);
+    }
+    hops.into_iter()
+        .map(|(_step, id, distance)| (id, distance))
+        .collect()
+}
+
+#[test]
+fn test_trace_path() {
+    // The shortest paths tree from 0 of a small weighted graph
+    let weights: HashMap<(Node, Node), u32> = vec![
+        ((0, 1), 2),
+        ((1, 2), 3),
+        ((0, 3), 1),
+        ((3, 4), 4),
+        ((2, 5), 1),
+    ]
+    .into_iter()
+    .collect();
+    let tree: ShortestPathsTree<u32> = vec![
+        (0, (0, None)),
+        (1, (2, Some(0))),
+        (2, (5, Some(1))),
+        (3, (1, Some(0))),
+        (4, (5, Some(3))),
+        (5, (6, Some(2))),
+    ]
+    .into_iter()
+    .collect();
+
+    timely::execute_directly(move |worker| {
+        let path = trace_path(worker, tree.clone(), 5);
+        assert_eq!(path, vec![(0, 0), (1, 2), (2, 5), (5, 6)]);
+        // The path is as long as the distance of the target, edge by edge
+        assert_eq!(path.last().map(|&(_id, d)| d), Some(tree[&5].0));
+        for pair in path.windows(2) {
+            let ((x, dx), (y, dy)) = (pair[0], pair[1]);
+            assert_eq!(weights.get(&(x, y)), Some(&(dy - dx)));
+        }
+
+        assert_eq!(trace_path(worker, tree.clone(), 0), vec![(0, 0)]);
+    });
+}
@@ -0,0 +1,395 @@
+use crate::bounds::DiameterBounds;
+use crate::distance::Distance;
+use crate::distributed_adjacencies::*;
+use crate::node::Node;
+use crate::operators::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::rc::Rc;
+use timely::communication::Allocate;
+use timely::dataflow::channels::pact::{Exchange as ExchangePact, Pipeline};
+use timely::dataflow::operators::*;
+use timely::order::Product;
+
+/// A search from one of the endpoints of a query: the index of the query,
+/// and whether the search starts from its target
+type Search = (u32, bool);
+
+/// What a worker knows about a query at the end of a round: the smallest
+/// distance of the pending nodes of the search from the source and of the
+/// one from the target, and the shortest path found so far
+type Summary<D> = (Option<D>, Option<D>, Option<D>);
+
+/// The searches of all the queries, on the nodes of a worker
+#[derive(Default)]
+struct Searches<D> {
+    /// The tentative distances of the reached nodes, by search
+    distances: HashMap<(Node, Search), D>,
+    /// The nodes whose distance improved since they last relaxed their edges
+    pending: HashMap<Search, HashSet<Node>>,
+    /// The length of the shortest path found so far by each query, among
+    /// the ones through the nodes of this worker
+    shortest: HashMap<u32, D>,
+}
+
+impl<D: Distance> Searches<D> {
+    /// Records a distance proposed to a node by a search, if it improves on
+    /// the known one. A node reached by both the searches of a query is on
+    /// a path between its endpoints.
+    fn propose(&mut self, node: Node, search: Search, distance: D) {
+        let improved = self
+            .distances
+            .get(&(node, search))
+            .map(|&known| distance < known)
+            .unwrap_or(true);
+        if !improved {
+            return;
+        }
+        self.distances.insert((node, search), distance);
+        self.pending
+            .entry(search)
+            .or_insert_with(HashSet::new)
+            .insert(node);
+        let (query, from_target) = search;
+        if let Some(&other) = self.distances.get(&(node, (query, !from_target))) {
+            let length = distance.plus(other);
+            let shortest = self.shortest.entry(query).or_insert(length);
+            *shortest = std::cmp::min(*shortest, length);
+        }
+    }
+
+    fn min_pending(&self, search: Search) -> Option<D> {
+        self.pending
+            .get(&search)?
+            .iter()
+            .map(|&node| self.distances[&(node, search)])
+            .min()
+    }
+
+    /// Removes the pending nodes of the search within the given distance,
+    /// returning them along with their distances
+    fn take_pending(&mut self, search: Search, limit: D) -> Vec<(Node, D)> {
+        let distances = &self.distances;
+        let mut taken = Vec::new();
+        if let Some(pending) = self.pending.get_mut(&search) {
+            pending.retain(|&node| {
+                let distance = distances[&(node, search)];
+                if distance <= limit {
+                    taken.push((node, distance));
+                }
+                distance > limit
+            });
+        }
+        taken
+    }
+
+    fn summaries(&self) -> Vec<(u32, Summary<D>)> {
+        let mut queries: HashSet<u32> = self.pending.keys().map(|&(query, _)| query).collect();
+        queries.extend(self.shortest.keys());
+        queries
+            .into_iter()
+            .map(|query| {
+                let summary = (
+                    self.min_pending((query, false)),
+                    self.min_pending((query, true)),
+                    self.shortest.get(&query).copied(),
+                );
+                (query, summary)
+            })
+            .collect()
+    }
+
+    /// Forgets the queries that have been answered
+    fn retain_queries(&mut self, active: &HashSet<u32>) {
+        self.distances
+            .retain(|(_node, (query, _)), _| active.contains(query));
+        self.pending.retain(|(query, _), _| active.contains(query));
+        self.shortest.retain(|query, _| active.contains(query));
+    }
+}
+
+fn min_known<D: Ord>(a: Option<D>, b: Option<D>) -> Option<D> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn merge_summaries<D: Distance>(s1: Summary<D>, s2: Summary<D>) -> Summary<D> {
+    (
+        min_known(s1.0, s2.0),
+        min_known(s1.1, s2.1),
+        min_known(s1.2, s2.2),
+    )
+}
+
+/// Whether the shortest path found by a query is a shortest one, given the
+/// smallest distances of the pending nodes of its two searches. A shorter
+/// path would go through a pending node of each search, or would have been
+/// found already. A search with no pending node has visited the whole
+/// component of its endpoint, reaching the other endpoint if it is there.
+fn is_settled<D: Distance>((from_source, from_target, shortest): Summary<D>) -> bool {
+    match (from_source, from_target) {
+        (Some(s), Some(t)) => shortest.map(|l| l <= s.plus(t)).unwrap_or(false),
+        _ => true,
+    }
+}
+
+/// Reads the queries from a text file with a pair of nodes per line,
+/// separated by whitespace
+pub fn read_queries(path: &Path) -> Vec<(Node, Node)> {
+    let file = File::open(path).expect("problem opening the queries file");
+    BufReader::new(file)
+        .lines()
+        .map(|line| line.expect("problem reading the queries file"))
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse_query(&line).expect("malformed query, expected two nodes"))
+        .collect()
+}
+
+fn parse_query(line: &str) -> Option<(Node, Node)> {
+    let mut tokens = line.split_whitespace().map(|token| token.parse::<Node>());
+    match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some(Ok(source)), Some(Ok(target)), None) => Some((source, target)),
+        _ => None,
+    }
+}
+
+/// Answers the given distance queries, known to the first worker, with a
+/// bidirectional search from the endpoints of each. All the searches run
+/// together, in rounds: in each round the pending nodes of a search within
+/// `delta` of its closest pending node relax their edges, so that a zero
+/// `delta` gives a bidirectional BFS on unweighted graphs, and a query is
+/// answered as soon as no shorter path can be found.
+///
+/// Returns, on the first worker, the distance of each query (none for
+/// disconnected endpoints) in the order of the queries, along with the
+/// farthest pair among them as a lower bound to the diameter (zero if
+/// none is connected).
+pub fn point_to_point<D: Distance, A: Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    delta: u32,
+    queries: &[(Node, Node)],
+) -> (
+    Option<DiameterBounds<D>>,
+    Vec<((Node, Node), Option<D>)>,
+    std::time::Duration,
+) {
+    let delta = D::from_u32(delta);
+    let endpoints: Vec<(Node, Search, D)> = queries
+        .iter()
+        .enumerate()
+        .flat_map(|(query, &(source, target))| {
+            let query = query as u32;
+            vec![
+                (source, (query, false), D::zero()),
+                (target, (query, true), D::zero()),
+            ]
+        })
+        .collect();
+
+    let searches = Rc::new(RefCell::new(Searches::<D>::default()));
+    let relax_searches = Rc::clone(&searches);
+    let adjacencies = DistributedAdjacencies::clone(adjacencies);
+    let answers = Rc::new(RefCell::new(Vec::new()));
+    let answers_ref = Rc::clone(&answers);
+
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        let endpoints = endpoints.to_stream(scope);
+
+        let answers = scope.iterative::<u32, _, _>(move |inner_scope| {
+            // The queries still to be answered, with the distance within
+            // which the pending nodes of each of their searches relax
+            let (handle, limits) =
+                inner_scope.feedback::<(u32, D, D)>(Product::new(Default::default(), 1));
+
+            let mut limits_stash = HashMap::new();
+            let mut num_active = usize::max_value();
+            let proposals = limits.unary_notify(
+                Pipeline,
+                "relax",
+                None,
+                move |input, output, notificator| {
+                    notificator.for_each(|t, _, _| {
+                        let limits: Vec<(u32, D, D)> =
+                            limits_stash.remove(t.time()).unwrap_or_default();
+                        let mut searches = relax_searches.borrow_mut();
+                        if limits.len() < num_active {
+                            let active = limits.iter().map(|&(query, _, _)| query).collect();
+                            searches.retain_queries(&active);
+                            num_active = limits.len();
+                        }
+                        let mut session = output.session(&t);
+                        for (query, from_source, from_target) in limits {
+                            for &(search, limit) in
+                                [((query, false), from_source), ((query, true), from_target)].iter()
+                            {
+                                for (node, distance) in searches.take_pending(search, limit) {
+                                    for &(neighbour, weight) in adjacencies.neighbours(node) {
+                                        session.give((
+                                            neighbour,
+                                            search,
+                                            distance.add_weight(weight),
+                                        ));
+                                    }
+                                }
+                            }
+                        }
+                    });
+
+                    input.for_each(|t, data| {
+                        limits_stash
+                            .entry(t.time().clone())
+                            .or_insert_with(Vec::new)
+                            .extend(data.replace(Vec::new()));
+                        notificator.notify_at(t.retain());
+                    });
+                },
+            );
+
+            // The nodes apply the proposals they got, then summarize the
+            // queries. The limits make sure that this happens in every round,
+            // even when no node relaxed its edges.
+            let mut proposals_stash = HashMap::new();
+            let summaries = proposals
+                .concat(&endpoints.enter(inner_scope))
+                .binary_notify(
+                    &limits,
+                    ExchangePact::new(|(id, _search, _distance)| *id as u64),
+                    Pipeline,
+                    "apply",
+                    None,
+                    move |proposal_input, limits_input, output, notificator| {
+                        notificator.for_each(|t, _, _| {
+                            let proposals: Vec<(Node, Search, D)> =
+                                proposals_stash.remove(t.time()).unwrap_or_default();
+                            let mut searches = searches.borrow_mut();
+                            for (node, search, distance) in proposals {
+                                searches.propose(node, search, distance);
+                            }
+                            output
+                                .session(&t)
+                                .give_iterator(searches.summaries().into_iter());
+                        });
+
+                        proposal_input.for_each(|t, data| {
+                            proposals_stash
+                                .entry(t.time().clone())
+                                .or_insert_with(Vec::new)
+                                .extend(data.replace(Vec::new()));
+                            notificator.notify_at(t.retain());
+                        });
+
+                        limits_input.for_each(|t, _data| {
+                            notificator.notify_at(t.retain());
+                        });
+                    },
+                );
+
+            // Each query is decided by the worker it is sent to, which
+            // either answers it or sets the limits of its next round
+            let mut summaries_stash = HashMap::new();
+            let decisions = summaries.unary_notify(
+                ExchangePact::new(|(query, _summary)| *query as u64),
+                "decide",
+                None,
+                move |input, output, notificator| {
+                    notificator.for_each(|t, _, _| {
+                        let summaries: HashMap<u32, Summary<D>> =
+                            summaries_stash.remove(t.time()).unwrap_or_default();
+                        let mut session = output.session(&t);
+                        for (query, summary) in summaries {
+                            if is_settled(summary) {
+                                session.give((query, summary.2, None));
+                            } else {
+                                let from_source = summary.0.expect("missing pending node");
+                                let from_target = summary.1.expect("missing pending node");
+                                let limits = (from_source.plus(delta), from_target.plus(delta));
+                                session.give((query, None, Some(limits)));
+                            }
+                        }
+                    });
+
+                    input.for_each(|t, data| {
+                        let stash = summaries_stash
+                            .entry(t.time().clone())
+                            .or_insert_with(HashMap::new);
+                        for (query, summary) in data.replace(Vec::new()) {
+                            let merged = match stash.remove(&query) {
+                                Some(known) => merge_summaries(known, summary),
+                                None => summary,
+                            };
+                            stash.insert(query, merged);
+                        }
+                        notificator.notify_at(t.retain());
+                    });
+                },
+            );
+
+            decisions
+                .flat_map(|(query, _distance, limits)| {
+                    limits.map(|(from_source, from_target)| (query, from_source, from_target))
+                })
+                .broadcast()
+                .connect_loop(handle);
+
+            decisions
+                .filter(|(_query, _distance, limits)| limits.is_none())
+                .map(|(query, distance, _limits)| (query, distance))
+                .leave()
+        });
+
+        answers
+            .exchange(|_| 0)
+            .inspect(move |&answer| answers_ref.borrow_mut().push(answer))
+            .probe()
+    });
+
+    let elapsed = run_to_completion(worker, probe);
+
+    let mut answers = answers.replace(Vec::new());
+    answers.sort_unstable_by_key(|&(query, _distance)| query);
+    let answers: Vec<((Node, Node), Option<D>)> = answers
+        .into_iter()
+        .map(|(query, distance)| (queries[query as usize], distance))
+        .collect();
+    if worker.index() == 0 {
+        info!("Answered {} queries in {:?}", answers.len(), elapsed);
+    }
+
+    let bounds = answers
+        .iter()
+        .filter_map(|&(pair, distance)| distance.map(|d| (d, pair)))
+        .max()
+        .map(|(distance, pair)| DiameterBounds {
+            witness_pair: Some(pair),
+            ..DiameterBounds::lower_only(distance)
+        })
+        .unwrap_or_else(|| DiameterBounds::lower_only(D::zero()));
+
+    (Some(bounds), answers, elapsed)
+}
+
+#[test]
+fn test_parse_query() {
+    assert_eq!(parse_query("3 14"), Some((3, 14)));
+    assert_eq!(parse_query("  3\t14 "), Some((3, 14)));
+    assert_eq!(parse_query("3"), None);
+    assert_eq!(parse_query("3 14 15"), None);
+    assert_eq!(parse_query("3 x"), None);
+}
+
+#[test]
+fn test_is_settled() {
+    // The path of length 10 may still be beaten by one through the pending nodes
+    assert!(!is_settled((Some(4u32), Some(5), Some(10))));
+    assert!(is_settled((Some(5u32), Some(5), Some(10))));
+    assert!(!is_settled((Some(5u32), Some(5), None)));
+    // An exhausted search settles the query, even with no path
+    assert!(is_settled((None, Some(5u32), Some(10))));
+    assert!(is_settled((Some(5u32), None, None)));
+}
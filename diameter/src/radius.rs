@@ -0,0 +1,282 @@
+use crate::bfs::bfs_from;
+use crate::bounds::DiameterBounds;
+use crate::checkpoint::Checkpoints;
+use crate::delta_stepping::delta_stepping_from;
+use crate::distance::Distance;
+use crate::distributed_adjacencies::DistributedAdjacencies;
+use crate::eccentricities::*;
+use crate::node::Node;
+use crate::operators::*;
+use crate::rand_cluster::clustering_eccentricities;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use timely::communication::Allocate;
+use timely::dataflow::operators::*;
+use timely::worker::Worker;
+
+/// How many candidate centers are reported at most
+const MAX_REPORTED_CANDIDATES: usize = 1000;
+
+/// Bounds to the radius of the graph, that is the smallest eccentricity of
+/// its nodes, with the candidate centers: the nodes whose lower bound does
+/// not exceed the upper bound to the radius.
+#[derive(Debug, Clone)]
+pub struct RadiusBounds {
+    pub lower: u64,
+    pub upper: u64,
+    /// A node with eccentricity at most `upper`
+    pub center: Node,
+    pub num_candidates: u64,
+    /// The candidates with the smallest lower bounds, along with them
+    pub candidates: Vec<(u64, Node)>,
+}
+
+/// Narrows down the radius and the centers of the graph. The clustering with
+/// the given radius gives upper bounds to the eccentricities of all the nodes,
+/// then `k` single source shortest paths computations refine them by the
+/// triangle inequality. After a random root, the sources alternate between
+/// the farthest node from the previous one, which raises the lower bounds,
+/// and the candidate with the smallest lower bound, whose eccentricity
+/// becomes exact.
+///
+/// As in `sweep`, the computations are BFSs if all the edges have the same
+/// weight, and delta-stepping with the given delta otherwise.
+pub fn radius<D: Distance, A: Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut Worker<A>,
+    k: u32,
+    cluster_radius: u32,
+    n: Node,
+    (min_weight, max_weight): (u32, u32),
+    delta: u32,
+    seed: u64,
+    checkpoints: &Checkpoints,
+    eccentricities: &Eccentricities,
+) -> (Option<DiameterBounds<u64>>, Option<RadiusBounds>, Duration) {
+    let (diameter, mut node_bounds, mut elapsed) = clustering_eccentricities::<D, _>(
+        DistributedAdjacencies::clone(&adjacencies),
+        worker,
+        cluster_radius,
+        n,
+        seed,
+        &checkpoints.phase("clustering"),
+    );
+    let mut diameter = diameter.map(|bounds| bounds.to_u64());
+
+    let mut sources = Vec::new();
+    let mut farthest: Option<Node> = None;
+    for i in 0..k {
+        let far = farthest.filter(|far| !sources.contains(far));
+        let source = match (i, far) {
+            (0, _) => random_root(&adjacencies, worker, n, seed),
+            (i, Some(far)) if i % 2 == 1 => far,
+            _ => {
+                let candidate = node_bounds
+                    .iter()
+                    .filter(|(id, _bounds)| !sources.contains(id))
+                    .map(|(id, (lower, _upper))| (lower, id))
+                    .min();
+                match global_min(worker, candidate) {
+                    Some((_lower, candidate)) => candidate,
+                    None => {
+                        info!("All the nodes have been sources already");
+                        break;
+                    }
+                }
+            }
+        };
+
+        let (source_farthest, distances, source_elapsed) = if min_weight == max_weight {
+            let (farthest, distances, elapsed) = bfs_from(&adjacencies, worker, source, true);
+            let scale = |hops: u32| hops as u64 * max_weight as u64;
+            let farthest = farthest.map(|(hops, far)| (scale(hops), far));
+            let distances = distances
+                .into_iter()
+                .map(|(id, (hops, _parent))| (id, scale(hops)))
+                .collect::<Vec<_>>();
+            (farthest, distances, elapsed)
+        } else {
+            let checkpoints = checkpoints.phase(&format!("source-{}", i));
+            let (farthest, distances, elapsed) = delta_stepping_from::<D, _>(
+                &adjacencies,
+                worker,
+                delta,
+                source,
+                &checkpoints,
+                true,
+            );
+            let farthest = farthest.map(|(eccentricity, far)| (eccentricity.to_u64(), far));
+            let distances = distances
+                .into_iter()
+                .map(|(id, (distance, _parent))| (id, distance.to_u64()))
+                .collect::<Vec<_>>();
+            (farthest, distances, elapsed)
+        };
+        elapsed += source_elapsed;
+        let (eccentricity, far) = source_farthest.expect("missing farthest node");
+        info!(
+            "Source {}: eccentricity {} from {}, reaching {} ({:?})",
+            i, eccentricity, source, far, source_elapsed
+        );
+
+        for (id, distance) in distances {
+            node_bounds.refine_from_source(id, distance, eccentricity);
+        }
+        let source_bounds = DiameterBounds::from_sweep(eccentricity, source, far);
+        diameter = Some(match diameter {
+            Some(bounds) => bounds.intersect(source_bounds),
+            None => source_bounds,
+        });
+        sources.push(source);
+        farthest = Some(far);
+    }
+
+    let lower = global_min(
+        worker,
+        node_bounds.iter().map(|(_id, (lower, _upper))| lower).min(),
+    );
+    let upper = global_min(
+        worker,
+        node_bounds
+            .iter()
+            .map(|(id, (_lower, upper))| (upper, id))
+            .min(),
+    );
+    let candidates = match upper {
+        Some((upper, _center)) => node_bounds
+            .iter()
+            .filter(|(_id, (lower, _upper))| *lower <= upper)
+            .map(|(id, (lower, _upper))| (lower, id))
+            .collect(),
+        None => Vec::new(),
+    };
+    let gathered = gather_candidates(worker, candidates);
+    eccentricities.save(&node_bounds);
+
+    // The diameter is at most twice the radius
+    if let Some((upper, _center)) = upper {
+        let radius_bounds = DiameterBounds {
+            lower: 0,
+            upper: Some(2 * upper),
+            witness_pair: None,
+        };
+        diameter = diameter.map(|bounds| bounds.intersect(radius_bounds));
+    }
+
+    let radius = match (lower, upper, gathered) {
+        (Some(lower), Some((upper, center)), Some((num_candidates, candidates))) => {
+            Some(RadiusBounds {
+                lower,
+                upper,
+                center,
+                num_candidates,
+                candidates,
+            })
+        }
+        _ => None,
+    };
+
+    (diameter, radius, elapsed)
+}
+
+/// Gathers on the first worker the number of candidates of all the workers,
+/// along with the ones with the smallest lower bounds
+fn gather_candidates<A: Allocate>(
+    worker: &mut Worker<A>,
+    mut candidates: Vec<(u64, Node)>,
+) -> Option<(u64, Vec<(u64, Node)>)> {
+    let count = candidates.len() as u64;
+    candidates.sort_unstable();
+    candidates.truncate(MAX_REPORTED_CANDIDATES);
+
+    let result = Rc::new(RefCell::new(None));
+    let result_ref = Rc::clone(&result);
+    let probe = worker.dataflow::<(), _, _>(move |scope| {
+        vec![(count, candidates)]
+            .to_stream(scope)
+            .exchange(|_| 0)
+            .accumulate((0, Vec::new()), |(count, candidates), data| {
+                for (local_count, local_candidates) in data.iter() {
+                    *count += *local_count;
+                    candidates.extend(local_candidates.iter().copied());
+                }
+                candidates.sort_unstable();
+                candidates.truncate(MAX_REPORTED_CANDIDATES);
+            })
+            .inspect(move |gathered| {
+                result_ref.borrow_mut().replace(gathered.clone());
+            })
+            .probe()
+    });
+    run_to_completion(worker, probe);
+    result.replace(None)
+}
+
+#[test]
+fn test_radius_bounds_and_candidates() {
+    use crate::sequential::{dijkstra, init_neighbourhoods};
+
+    // A weighted cycle with chords, and a path hanging from it
+    let mut edges: Vec<((Node, Node), u32)> = (0..20)
+        .map(|i: u32| ((i as Node, (i as Node + 1) % 20), 1 + i % 3))
+        .collect();
+    edges.push(((0, 10), 4));
+    edges.push(((5, 15), 7));
+    edges.push(((3, 20), 5));
+    edges.push(((20, 21), 2));
+    let n = 22;
+
+    let neighbourhoods = init_neighbourhoods(edges.clone(), n);
+    let eccentricities: Vec<u64> = (0..n)
+        .map(|x| {
+            dijkstra(&neighbourhoods, x)
+                .0
+                .iter()
+                .flatten()
+                .max()
+                .copied()
+                .unwrap() as u64
+        })
+        .collect();
+    let exact_radius = *eccentricities.iter().min().unwrap();
+    let diameter = *eccentricities.iter().max().unwrap();
+
+    timely::execute_directly(move |worker| {
+        let checkpoints = Checkpoints::new(&std::env::temp_dir(), "none", 0, None, false);
+        let no_eccentricities = Eccentricities::new(&std::env::temp_dir(), "none", 0, false);
+        for &k in &[1, 3, 6] {
+            let adjacencies = DistributedAdjacencies::from_edge_list(0, 1, n, &edges);
+            let (bounds, radius_bounds, _) = radius::<u32, _>(
+                adjacencies,
+                worker,
+                k,
+                3,
+                n,
+                (1, 5),
+                2,
+                42,
+                &checkpoints,
+                &no_eccentricities,
+            );
+            let bounds = bounds.expect("missing diameter bounds");
+            assert!(bounds.lower <= diameter);
+            assert!(bounds.upper.expect("missing upper bound") >= diameter);
+
+            let radius_bounds = radius_bounds.expect("missing radius bounds");
+            assert!(radius_bounds.lower <= exact_radius && exact_radius <= radius_bounds.upper);
+            assert!(eccentricities[radius_bounds.center as usize] <= radius_bounds.upper);
+            // No candidate is ruled out wrongly, and all the centers are candidates
+            assert_eq!(
+                radius_bounds.num_candidates,
+                radius_bounds.candidates.len() as u64
+            );
+            for &(lower, id) in radius_bounds.candidates.iter() {
+                assert!(lower <= eccentricities[id as usize]);
+            }
+            for id in (0..n).filter(|&id| eccentricities[id as usize] == exact_radius) {
+                assert!(radius_bounds.candidates.iter().any(|&(_, c)| c == id));
+            }
+        }
+    });
+}
@@ -1,9 +1,16 @@
+use crate::bounds::DiameterBounds;
+use crate::checkpoint::*;
+use crate::distance::{Distance, Weight};
 use crate::distributed_adjacencies::*;
 use crate::distributed_graph::*;
+use crate::eccentricities::*;
+use crate::node::Node;
 use crate::operators::*;
 use crate::sequential::*;
+use crate::vertex_program::*;
 use rand::Rng;
 use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
@@ -21,13 +28,13 @@ use timely::order::Product;
 use timely::progress::Timestamp;
 
 #[derive(Debug, Clone, Copy, Abomonation, Hash, Ord, PartialOrd, Eq, PartialEq)]
-struct Message {
-    distance: u32,
-    root: u32,
+struct Message<D> {
+    distance: D,
+    root: Node,
     generation: u32,
 }
 
-impl Message {
+impl<D: Distance> Message<D> {
     fn merge(msg1: &Self, msg2: &Self) -> Self {
         if msg1.generation < msg2.generation {
             // The newest wins
@@ -46,28 +53,31 @@ impl Message {
     }
 }
 
-#[derive(Debug, Clone, Abomonation, Hash, Ord, PartialOrd, Eq, PartialEq)]
-enum NodeState {
+#[derive(
+    Debug, Clone, Abomonation, Hash, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize,
+)]
+enum NodeState<D> {
     Uncovered,
     Covered {
-        root: u32,
-        distance: u32,
+        root: Node,
+        distance: D,
         generation: u32,
         updated: bool,
     },
     Frozen {
-        root: u32,
-        distance: u32,
+        root: Node,
+        distance: D,
+        generation: u32,
     },
 }
 
-impl Default for NodeState {
+impl<D> Default for NodeState<D> {
     fn default() -> Self {
         Self::Uncovered
     }
 }
 
-impl NodeState {
+impl<D: Distance> NodeState<D> {
     fn reset(&self) -> Self {
         Self::default()
     }
@@ -98,19 +108,31 @@ impl NodeState {
         }
     }
 
-    fn can_send(&self, radius: u32, round: u32) -> bool {
+    /// Whether the node is frozen in a cluster whose center was sampled
+    /// before the given generation
+    fn frozen_before(&self, generation: u32) -> bool {
+        match *self {
+            Self::Frozen {
+                generation: frozen_generation,
+                ..
+            } => frozen_generation < generation,
+            _ => false,
+        }
+    }
+
+    fn can_send(&self, radius: D, round: u32) -> bool {
         match *self {
             Self::Covered {
                 root: _,
                 distance,
                 generation,
                 updated,
-            } => updated && distance <= radius * (1 + round - generation),
+            } => updated && distance <= radius.times(1 + round - generation),
             _ => false,
         }
     }
 
-    fn reactivate_fringe(&self, radius: u32, round: u32) -> Self {
+    fn reactivate_fringe(&self, radius: D, round: u32) -> Self {
         match *self {
             Self::Covered {
                 root,
@@ -118,7 +140,7 @@ impl NodeState {
                 generation,
                 updated: _,
             } => {
-                if distance >= radius * (round - generation) {
+                if distance >= radius.times(round - generation) {
                     Self::Covered {
                         root,
                         distance,
@@ -134,7 +156,7 @@ impl NodeState {
         }
     }
 
-    fn freeze_if_done(&self, radius: u32, round: u32) -> Self {
+    fn freeze_if_done(&self, radius: D, round: u32) -> Self {
         match *self {
             Self::Uncovered => Self::Uncovered,
             Self::Frozen { .. } => self.clone(),
@@ -144,8 +166,12 @@ impl NodeState {
                 generation,
                 updated: _,
             } => {
-                if distance <= radius * (1 + round - generation) {
-                    Self::Frozen { root, distance }
+                if distance <= radius.times(1 + round - generation) {
+                    Self::Frozen {
+                        root,
+                        distance,
+                        generation,
+                    }
                 } else {
                     self.clone()
                 }
@@ -153,7 +179,7 @@ impl NodeState {
         }
     }
 
-    fn propagate(&self, weight: u32, _radius: u32) -> Option<Message> {
+    fn propagate<W: Weight>(&self, weight: W, _radius: D) -> Option<Message<D>> {
         match *self {
             Self::Covered {
                 root,
@@ -164,7 +190,7 @@ impl NodeState {
                 assert!(updated, "no reason to send from non-updated nodes");
                 Some(Message {
                     root,
-                    distance: distance + weight,
+                    distance: distance.add_weight(weight),
                     generation,
                 })
             }
@@ -173,7 +199,7 @@ impl NodeState {
         }
     }
 
-    fn distance(&self) -> u32 {
+    fn distance(&self) -> D {
         match self {
             Self::Covered {
                 root: _,
@@ -181,12 +207,12 @@ impl NodeState {
                 generation: _,
                 updated: _,
             } => *distance,
-            Self::Frozen { root: _, distance } => *distance,
+            Self::Frozen { distance, .. } => *distance,
             Self::Uncovered => panic!("Cannot get distance from uncovered node"),
         }
     }
 
-    fn root(&self) -> u32 {
+    fn root(&self) -> Node {
         match self {
             Self::Covered {
                 root,
@@ -194,20 +220,20 @@ impl NodeState {
                 generation: _,
                 updated: _,
             } => *root,
-            Self::Frozen { root, distance: _ } => *root,
+            Self::Frozen { root, .. } => *root,
             Self::Uncovered => panic!("Cannot get distance from uncovered node"),
         }
     }
 
-    fn is_center(&self, id: u32) -> bool {
+    fn is_center(&self, id: Node) -> bool {
         self.root() == id
     }
 
-    fn as_center(&self, id: u32, generation: u32) -> Self {
+    fn as_center(&self, id: Node, generation: u32) -> Self {
         match &self {
             Self::Uncovered => Self::Covered {
                 root: id,
-                distance: 0,
+                distance: D::zero(),
                 generation,
                 updated: true,
             },
@@ -233,7 +259,7 @@ impl NodeState {
         }
     }
 
-    fn updated(&self, message: Message) -> Self {
+    fn updated(&self, message: Message<D>) -> Self {
         match *self {
             Self::Frozen { .. } => self.clone(),
             Self::Uncovered => Self::Covered {
@@ -279,12 +305,13 @@ impl<T: Timestamp> GetGeneration for Product<T, u32> {
         self.inner
     }
 }
-fn sample_centers<G: Scope, R: Rng + 'static>(
-    nodes: &Stream<G, (u32, NodeState)>,
+fn sample_centers<G: Scope, D: Distance, R: Rng + 'static>(
+    nodes: &Stream<G, Node>,
+    states: Rc<RefCell<LocalStates<NodeState<D>>>>,
     base: f64,
-    n: u32,
+    n: Node,
     rand: Rc<RefCell<R>>,
-) -> Stream<G, (u32, NodeState)>
+) -> Stream<G, Node>
 where
     G::Timestamp: GetGeneration,
 {
@@ -309,89 +336,227 @@ where
             notificator.for_each(|t, _, _| {
                 if let Some(mut nodes) = stash.remove(&t) {
                     let generation = t.time().get_generation();
-                    nodes.sort_by_key(|pair| pair.0);
+                    nodes.sort();
                     // l1.log((CountEvent::Active(generation), nodes.len() as u64));
-                    let mut out = output.session(&t);
-                    let mut cnt = 0;
-                    let cnt_uncovered = nodes.iter().filter(|p| p.1.is_uncovered()).count();
+                    let mut states = states.borrow_mut();
                     let p = base.powi(generation as i32) / n as f64;
-                    if p <= 1.0 {
-                        // info!("Probability is less than one (n is {}, p is {})", n, p);
-                        for (id, state) in nodes.into_iter() {
-                            if state.is_uncovered() && rand.borrow_mut().gen_bool(p) {
-                                out.give((id, state.as_center(id, generation)));
-                                cnt += 1;
-                            } else {
-                                out.give((id, state));
-                            }
+                    // Selecting all uncovered nodes as centers once p is larger than one
+                    for &id in nodes.iter() {
+                        let state = states.get(id).expect("missing state");
+                        if state.is_uncovered() && (p > 1.0 || rand.borrow_mut().gen_bool(p)) {
+                            let state = state.as_center(id, generation);
+                            states.insert(id, state);
                         }
-                    } else {
-                        // info!("Selecting all uncovered nodes as centers (n is {})", n);
-                        cnt = cnt_uncovered;
-                        out.give_iterator(nodes.into_iter().map(|(id, state)| {
-                            if state.is_uncovered() {
-                                // cnt_uncovered += 1;
-                                (id, state.as_center(id, generation))
-                            } else {
-                                (id, state)
-                            }
-                        }));
                     }
-                    // info!("Uncovered {}, sampled as centers {}", cnt_uncovered, cnt);
-                    // l1.log((CountEvent::Centers(generation), cnt as u64));
-                    // l1.log((CountEvent::Uncovered(generation), cnt_uncovered as u64));
+                    output.session(&t).give_iterator(nodes.into_iter());
                 }
             });
         },
     )
 }
 
-fn expand_clusters<G>(
-    adjacencies: &DistributedAdjacencies,
-    nodes: &Stream<G, (u32, NodeState)>,
-    radius: u32,
-) -> Stream<G, (u32, NodeState)>
+/// Grows the clusters by `radius` from their current boundary
+#[derive(Clone)]
+struct ClusterExpansion<D> {
+    radius: D,
+}
+
+impl<T, D, W> VertexProgram<Product<T, u32>, W> for ClusterExpansion<D>
+where
+    T: Timestamp + GetGeneration,
+    D: Distance,
+    W: Weight,
+{
+    type State = NodeState<D>;
+    type Message = Message<D>;
+
+    fn with_default(&self) -> bool {
+        false
+    }
+
+    fn should_send(&self, time: &Product<T, u32>, state: &NodeState<D>) -> bool {
+        state.can_send(self.radius, time.outer.get_generation())
+    }
+
+    fn message(
+        &self,
+        _time: &Product<T, u32>,
+        _id: Node,
+        state: &NodeState<D>,
+        weight: W,
+    ) -> Option<Message<D>> {
+        state.propagate(weight, self.radius)
+    }
+
+    fn combine(msg1: &Message<D>, msg2: &Message<D>) -> Message<D> {
+        Message::merge(msg1, msg2)
+    }
+
+    fn apply(
+        &self,
+        _time: &Product<T, u32>,
+        state: &NodeState<D>,
+        message: Option<&Message<D>>,
+    ) -> NodeState<D> {
+        match message {
+            Some(message) => state.updated(*message),
+            None => state.deactivate(), // deactivate nodes with no messages
+        }
+    }
+
+    // Circulate while someone has something to say
+    fn is_active(&self, time: &Product<T, u32>, state: &NodeState<D>) -> bool {
+        state.can_send(self.radius, time.outer.get_generation())
+    }
+}
+
+/// Expands the clusters of the given nodes, which are all the ones not frozen
+/// yet, and returns the ones that are still not frozen afterwards, along with
+/// the ones that just froze
+fn expand_clusters<G, D, W>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, Node>,
+    states: Rc<RefCell<LocalStates<NodeState<D>>>>,
+    radius: D,
+) -> Stream<G, Node>
 where
     G: Scope,
+    D: Distance,
+    W: Weight,
     G::Timestamp: GetGeneration,
 {
     // let l1 = nodes.scope().count_logger().expect("missing logger");
 
-    nodes
-        .map_timed(move |t, (id, state)| (id, state.reactivate_fringe(radius, t.get_generation())))
-        .scope()
-        .iterative::<u32, _, _>(move |subscope| {
-            let nodes = nodes.enter(subscope);
-
-            let (handle, cycle) = subscope.feedback(Product::new(Default::default(), 1));
-
-            let (output, further) = adjacencies
-                .send(
-                    &nodes.concat(&cycle),
-                    false,
-                    move |t, state| state.can_send(radius, t.outer.get_generation()),
-                    move |t, state, weight| state.propagate(weight, radius),
-                    |d1, d2| Message::merge(d1, d2), // aggregate messages
-                    |state, message| state.updated(*message),
-                    |state| state.deactivate(), // deactivate nodes with no messages
-                )
-                .branch_all(
-                    "can_send",
-                    move |t, pair| pair.1.can_send(radius, t.outer.get_generation()),
-                    0,
-                ); // Circulate while someone has something to say
-
-            further.connect_loop(handle);
-
-            output.leave()
-        })
-        .map_timed(move |t, (id, state)| (id, state.freeze_if_done(radius, t.get_generation())))
+    let fringe_states = Rc::clone(&states);
+    let nodes = nodes.map_timed(move |t, id| {
+        let mut states = fringe_states.borrow_mut();
+        let state = states
+            .get(id)
+            .expect("missing state")
+            .reactivate_fringe(radius, t.get_generation());
+        states.insert(id, state);
+        id
+    });
+
+    let frozen_states = Rc::clone(&states);
+    run_until_quiescent_in(adjacencies, &nodes, states, ClusterExpansion { radius }).unary(
+        Pipeline,
+        "freeze",
+        move |_, _| {
+            move |input, output| {
+                input.for_each(|t, data| {
+                    let data = data.replace(Vec::new());
+                    let mut session = output.session(&t);
+                    let mut states = frozen_states.borrow_mut();
+                    for id in data.into_iter() {
+                        let state = states.get(id).expect("missing state");
+                        // Nodes frozen in earlier generations are touched
+                        // by the expansion, but stay as they are
+                        if !state.is_frozen() {
+                            let state = state.freeze_if_done(radius, t.time().get_generation());
+                            states.insert(id, state);
+                            session.give(id);
+                        }
+                    }
+                })
+            }
+        },
+    )
 }
 
-fn remap_edges<G: Scope>(
-    adjacencies: &DistributedAdjacencies,
-    clustering: &Stream<G, (u32, NodeState)>,
-) -> Stream<G, ((u32, u32), u32)> {
+/// The state of a node in the exponential start time clustering of Miller,
+/// Peng and Xu: the center it currently belongs to, the start time of the
+/// center, and the distance from it. A node goes to the center that reaches
+/// it first, that is the one with the smallest start time plus distance.
+#[derive(Debug, Clone, Default, Abomonation, Serialize, Deserialize)]
+struct ShiftState<D> {
+    start: D,
+    root: Node,
+    distance: D,
+    updated: bool,
+    round: u32,
+}
+
+impl<D: Distance> ShiftState<D> {
+    fn arrival(&self) -> (D, Node) {
+        (self.start.plus(self.distance), self.root)
+    }
+}
+
+/// Propagates the earliest arrivals of the centers, with messages made of
+/// the start time of the center, the center, and the distance from it
+#[derive(Clone)]
+struct ShiftExpansion<D> {
+    _distance: std::marker::PhantomData<D>,
+}
+
+impl<T: Timestamp, D: Distance> VertexProgram<Product<T, u32>> for ShiftExpansion<D> {
+    type State = ShiftState<D>;
+    type Message = (D, Node, D);
+
+    fn with_default(&self) -> bool {
+        false
+    }
+
+    fn should_send(&self, _time: &Product<T, u32>, state: &ShiftState<D>) -> bool {
+        state.updated
+    }
+
+    fn message(
+        &self,
+        _time: &Product<T, u32>,
+        _id: Node,
+        state: &ShiftState<D>,
+        weight: u32,
+    ) -> Option<(D, Node, D)> {
+        Some((state.start, state.root, state.distance.add_weight(weight)))
+    }
+
+    fn combine(msg1: &(D, Node, D), msg2: &(D, Node, D)) -> (D, Node, D) {
+        let arrival = |(start, root, distance): &(D, Node, D)| (start.plus(*distance), *root);
+        if arrival(msg1) <= arrival(msg2) {
+            *msg1
+        } else {
+            *msg2
+        }
+    }
+
+    fn apply(
+        &self,
+        time: &Product<T, u32>,
+        state: &ShiftState<D>,
+        message: Option<&(D, Node, D)>,
+    ) -> ShiftState<D> {
+        match message {
+            Some(&(start, root, distance)) if (start.plus(distance), root) < state.arrival() => {
+                ShiftState {
+                    start,
+                    root,
+                    distance,
+                    updated: true,
+                    round: time.inner,
+                }
+            }
+            _ => ShiftState {
+                updated: false,
+                ..state.clone()
+            },
+        }
+    }
+
+    fn is_active(&self, _time: &Product<T, u32>, state: &ShiftState<D>) -> bool {
+        state.updated
+    }
+}
+
+/// The edges of the auxiliary graph of the clustering, between the centers of
+/// the clusters, with the length of the shortest path through an edge between
+/// them. The lengths go up to about twice the largest distance from a center,
+/// so they are 64 bits wide, as the values derived from them.
+fn remap_edges<G: Scope, D: Distance, W: Weight>(
+    adjacencies: &DistributedAdjacencies<W>,
+    clustering: &Stream<G, (Node, NodeState<D>)>,
+) -> Stream<G, ((Node, Node), u64)> {
     use std::collections::hash_map::DefaultHasher;
 
     // We build the self loops to cover the case in which
@@ -408,14 +573,15 @@ fn remap_edges<G: Scope>(
 
     adjacencies
         .triplets(&clustering, |((_u, state_u), (_v, state_v), w)| {
-            let d_u = state_u.distance();
-            let d_v = state_v.distance();
+            let d_u = state_u.distance().to_u64();
+            let d_v = state_v.distance().to_u64();
+            let w: u64 = w.into();
             let c_u = state_u.root();
             let c_v = state_v.root();
             let out_edge = if c_u < c_v {
-                Some(((c_u, c_v), w + d_u + d_v))
+                Some(((c_u, c_v), d_u + d_v + w))
             } else if c_u > c_v {
-                Some(((c_v, c_u), w + d_u + d_v))
+                Some(((c_v, c_u), d_u + d_v + w))
             } else {
                 None
             };
@@ -423,12 +589,12 @@ fn remap_edges<G: Scope>(
         })
         .concat(&self_loops)
         .aggregate(
-            |_key, val, min_weight: &mut Option<u32>| {
+            |_key, val, min_weight: &mut Option<u64>| {
                 *min_weight = min_weight.map(|min| std::cmp::min(min, val)).or(Some(val));
             },
-            // we must use Option<u32> because the state is initialized according to Default,
-            // which for u32 is 0, that doesn't play well with the minimum we want to compute
-            |key, min_weight: Option<u32>| {
+            // we must use Option<D> because the state is initialized according to Default,
+            // which for numbers is 0, that doesn't play well with the minimum we want to compute
+            |key, min_weight: Option<u64>| {
                 (key, min_weight.expect("no weights received for this node"))
             },
             move |key| {
@@ -439,15 +605,41 @@ fn remap_edges<G: Scope>(
         )
 }
 
-fn build_clustering<G: Scope, R: Rng + 'static>(
-    adjacencies: &DistributedAdjacencies,
-    nodes: &Stream<G, (u32, NodeState)>,
-    radius: u32,
+/// The initial node states and the frozen ones, which are both
+/// loaded from the checkpoint when resuming
+fn initial_states<G: Scope, D: Distance, W: Weight>(
+    adjacencies: &DistributedAdjacencies<W>,
+    scope: &mut G,
+    checkpoints: &Checkpoints,
+    resume: Option<u32>,
+) -> (
+    Stream<G, (Node, NodeState<D>)>,
+    Stream<G, (Node, NodeState<D>)>,
+) {
+    match resume {
+        Some(round) => {
+            let (nodes, frozen) = checkpoints.load::<NodeState<D>>(round);
+            (nodes.to_stream(scope), frozen.to_stream(scope))
+        }
+        None => (
+            adjacencies.nodes::<_, NodeState<D>>(scope),
+            Vec::new().to_stream(scope),
+        ),
+    }
+}
+
+fn build_clustering<G: Scope, D: Distance, W: Weight, R: Rng + 'static>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, (Node, NodeState<D>)>,
+    frozen: &Stream<G, (Node, NodeState<D>)>,
+    radius: D,
     base: f64,
-    n: u32,
+    n: Node,
     rand: Rc<RefCell<R>>,
+    checkpoints: &Checkpoints,
+    start: u32,
 ) -> (
-    Rc<RefCell<Option<Vec<(u32, NodeState)>>>>,
+    Rc<RefCell<Option<Vec<(Node, NodeState<D>)>>>>,
     Rc<RefCell<Option<u32>>>,
     ProbeHandle<G::Timestamp>,
 ) {
@@ -456,52 +648,85 @@ fn build_clustering<G: Scope, R: Rng + 'static>(
     let local_states = Rc::new(RefCell::new(None));
     let local_states_ref = Rc::clone(&local_states);
 
+    // The states stay on their worker across the generations, and only the
+    // nodes that are not frozen yet go around the loop
+    let states = Rc::new(RefCell::new(adjacencies.local_states::<NodeState<D>>()));
+    let load = |stream: &Stream<G, (Node, NodeState<D>)>| {
+        let states = Rc::clone(&states);
+        stream.map(move |(id, state)| {
+            states.borrow_mut().insert(id, state);
+            id
+        })
+    };
+    let nodes = load(nodes);
+    let frozen = load(frozen);
+    let loop_states = Rc::clone(&states);
+
     let (centers_count, probe) = nodes
         .scope()
         .iterative::<u32, _, _>(|inner_scope| {
-            let nodes = nodes.enter(&inner_scope);
+            let nodes = nodes.enter_at(&inner_scope, move |_| start);
 
             let summary = Product::new(Default::default(), 1);
             let (handle, cycle) = inner_scope.feedback(summary);
 
-            let (stable, further) = expand_clusters(
-                &adjacencies,
-                &sample_centers(&nodes.concat(&cycle), base, n, rand),
-                radius,
-            )
-            .branch(move |_t, (_id, state)| !state.is_frozen());
+            let sampled = sample_centers(
+                &nodes.concat(&cycle),
+                Rc::clone(&loop_states),
+                base,
+                n,
+                rand,
+            );
+            let frozen_states = Rc::clone(&loop_states);
+            let (stable, further) =
+                expand_clusters(&adjacencies, &sampled, Rc::clone(&loop_states), radius).branch(
+                    move |_t, id| {
+                        !frozen_states
+                            .borrow()
+                            .get(*id)
+                            .expect("missing state")
+                            .is_frozen()
+                    },
+                );
 
-            further.connect_loop(handle);
+            further
+                .checkpoint(&stable, &loop_states, checkpoints)
+                .connect_loop(handle);
 
             stable.leave()
         })
-        // Turn uncovered nodes into singleton clusters
-        .map(|(id, state)| {
-            if state.is_uncovered() {
-                (id, state.as_center(id, std::u32::MAX))
-            } else {
-                (id, state)
-            }
-        })
-        // Count how many centers we have locally, and at the same time collect all the states of this worker
-        .unary(Pipeline, "collect_and_count", move |_, _| {
-            move |input, output| {
-                input.for_each(|t, data| {
-                    let data = data.replace(Vec::new());
+        .concat(&frozen)
+        .concat(&nodes)
+        // Once all the nodes are frozen, count how many centers we have
+        // locally, and at the same time collect all the states of this worker
+        .unary_notify(
+            Pipeline,
+            "collect_and_count",
+            None,
+            move |input, output, notificator| {
+                input.for_each(|t, _data| {
+                    notificator.notify_at(t.retain());
+                });
+                notificator.for_each(|t, _, _| {
                     let mut count = 0u32;
-                    for pair in data.into_iter() {
-                        if pair.1.is_center(pair.0) {
+                    let mut local_states = local_states.borrow_mut();
+                    let local_states = local_states.get_or_insert_with(Vec::new);
+                    for (id, state) in states.borrow().iter() {
+                        // Turn uncovered nodes into singleton clusters
+                        let state = if state.is_uncovered() {
+                            state.as_center(id, std::u32::MAX)
+                        } else {
+                            state.clone()
+                        };
+                        if state.is_center(id) {
                             count += 1;
                         }
-                        local_states
-                            .borrow_mut()
-                            .get_or_insert_with(Vec::new)
-                            .push(pair);
+                        local_states.push((id, state));
                     }
                     output.session(&t).give(count);
                 });
-            }
-        })
+            },
+        )
         .exchange(|_| 0)
         .accumulate(0, |sum, data| {
             for &x in data.iter() {
@@ -514,151 +739,685 @@ fn build_clustering<G: Scope, R: Rng + 'static>(
     (local_states_ref, centers_count, probe)
 }
 
-pub fn rand_cluster<A: timely::communication::Allocate>(
+pub fn rand_cluster<D: Distance, A: timely::communication::Allocate>(
     adjacencies: DistributedAdjacencies,
     worker: &mut timely::worker::Worker<A>,
     radius: u32,
     base: f64,
-    n: u32,
+    n: Node,
     seed: u64,
     final_approx_probe: &mut Option<Duration>,
-) -> (Option<u32>, std::time::Duration) {
+    checkpoints: &Checkpoints,
+    eccentricities: &Eccentricities,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
     use rand_xoshiro::Xoroshiro128StarStar;
 
+    let resume = checkpoints.resume_point(worker);
     let (local_states, num_centers, probe) = worker.dataflow::<(), _, _>(|scope| {
-        let nodes = adjacencies.nodes::<_, NodeState>(scope);
-
         let mut rand = Xoroshiro128StarStar::seed_from_u64(seed);
-        for _ in 0..nodes.scope().index() {
+        for _ in 0..scope.index() {
             rand.jump();
         }
         let rand = Rc::new(RefCell::new(rand));
 
-        let nodes = adjacencies.nodes::<_, NodeState>(scope);
+        let (nodes, frozen) = initial_states(&adjacencies, scope, checkpoints, resume);
 
-        build_clustering(&adjacencies, &nodes, radius, base, n, rand)
+        build_clustering(
+            &adjacencies,
+            &nodes,
+            &frozen,
+            D::from_u32(radius),
+            base,
+            n,
+            rand,
+            checkpoints,
+            resume.map(|round| round + 1).unwrap_or(0),
+        )
     });
 
     let elapsed_clustering = run_to_completion(worker, probe);
     info!("Clustering completed in {:?}", elapsed_clustering);
 
-    let (diameter_box, probe) = worker.dataflow::<(), _, _>(move |scope| {
-        let local_states = local_states.borrow_mut().take().into_iter().flatten();
-        let clustering = local_states.to_stream(scope);
-        collect_and_approximate(adjacencies, &clustering).collect_single()
-    });
+    let local_states = local_states.borrow_mut().take().unwrap_or_else(Vec::new);
+    let (diameter, elapsed_approximation) = approximate(
+        adjacencies,
+        worker,
+        local_states,
+        final_approx_probe,
+        eccentricities,
+    );
 
-    let elapsed_approximation = run_to_completion(worker, probe);
-    final_approx_probe.replace(elapsed_approximation.clone());
-    let diameter = diameter_box.borrow_mut().take();
+    (diameter, elapsed_clustering + elapsed_approximation)
+}
+
+/// How `rand_cluster_guess` picks the radius of the next attempt, when the
+/// clustering with the current one has too many clusters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadiusSearch {
+    /// Multiply the radius by the step
+    Geometric,
+    /// Predict the radius that gives `memory` clusters from the previous
+    /// attempts, then refine it once the clustering fits. With `reuse`, the
+    /// clusters of the first generations of the previous attempt are kept
+    Interpolated { reuse: bool },
+}
+
+/// Predicts the radius giving `memory` clusters, assuming that their number
+/// decreases as a power of the radius, fitted on the last two attempts.
+/// The prediction is larger than the last radius, by at most the square of
+/// the step, and falls back to the geometric step with a single attempt.
+fn predict_radius(history: &[(u32, Duration, u32)], memory: u32, step: u32) -> u32 {
+    let (radius, _, centers) = *history.last().expect("no attempts to predict from");
+    let max_radius = radius.saturating_mul(step.saturating_mul(step));
+    let predicted = if history.len() < 2 {
+        radius.saturating_mul(step)
+    } else {
+        let (prev_radius, _, prev_centers) = history[history.len() - 2];
+        let exponent = (prev_centers.max(1) as f64 / centers.max(1) as f64).ln()
+            / (radius as f64 / prev_radius as f64).ln();
+        if exponent.is_finite() && exponent > 0.0 {
+            let ratio = centers as f64 / memory.max(1) as f64;
+            let predicted = radius as f64 * ratio.powf(1.0 / exponent);
+            predicted.min(max_radius as f64).round() as u32
+        } else {
+            radius.saturating_mul(step)
+        }
+    };
+    std::cmp::max(radius + 1, std::cmp::min(predicted, max_radius))
+}
+
+/// Clusters the graph with the given radius, returning the local states, the
+/// number of clusters, and the time it took. When not resuming, the states
+/// of a previous attempt can be given, along with a generation: the nodes
+/// frozen before it keep their clusters, and the others are clustered again
+/// starting from that generation.
+fn cluster_with_radius<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    radius: u32,
+    n: Node,
+    seed: u64,
+    reused: Option<(&[(Node, NodeState<D>)], u32)>,
+    checkpoints: &Checkpoints,
+) -> (Vec<(Node, NodeState<D>)>, u32, Duration) {
+    use rand_xoshiro::Xoroshiro128StarStar;
+
+    info!("Do clustering with radius {}", radius);
+    let resume = checkpoints.resume_point(worker);
+    let (local_states, num_centers, probe) = worker.dataflow::<(), _, _>(|scope| {
+        let mut rand = Xoroshiro128StarStar::seed_from_u64(seed);
+        for _ in 0..scope.index() {
+            rand.jump();
+        }
+        let rand = Rc::new(RefCell::new(rand));
+
+        let (nodes, frozen, start) = match (resume, reused) {
+            (None, Some((states, generation))) => {
+                let (frozen, nodes): (Vec<_>, Vec<_>) = states
+                    .iter()
+                    .cloned()
+                    .partition(|(_id, state)| state.frozen_before(generation));
+                info!("Reusing {} frozen nodes", frozen.len());
+                let nodes = nodes.into_iter().map(|(id, state)| (id, state.reset()));
+                (nodes.to_stream(scope), frozen.to_stream(scope), generation)
+            }
+            _ => {
+                let (nodes, frozen) = initial_states(adjacencies, scope, checkpoints, resume);
+                (nodes, frozen, resume.map(|round| round + 1).unwrap_or(0))
+            }
+        };
+
+        build_clustering(
+            adjacencies,
+            &nodes,
+            &frozen,
+            D::from_u32(radius),
+            2.0,
+            n,
+            rand,
+            checkpoints,
+            start,
+        )
+    });
+    let elapsed_clustering = run_to_completion(worker, probe);
+    let num_centers = num_centers.borrow().expect("missing centers count");
     info!(
-        "Final diameter approximation in {:?}",
-        elapsed_approximation
+        "Clustering completed in {:?}, with {} clusters, radius {}",
+        elapsed_clustering, num_centers, radius
     );
+    let local_states = local_states.borrow_mut().take().unwrap_or_else(Vec::new);
 
-    (diameter, elapsed_clustering + elapsed_approximation)
+    (local_states, num_centers, elapsed_clustering)
 }
 
-pub fn rand_cluster_guess<A: timely::communication::Allocate>(
+pub fn rand_cluster_guess<D: Distance, A: timely::communication::Allocate>(
     adjacencies: DistributedAdjacencies,
     worker: &mut timely::worker::Worker<A>,
     memory: u32,
     init: u32,
     step: u32,
-    n: u32,
+    search: RadiusSearch,
+    n: Node,
     seed: u64,
     final_approx_probe: &mut Option<Duration>,
     iteration_information: &mut Vec<(u32, Duration, u32)>,
-) -> (Option<u32>, std::time::Duration) {
-    use rand_xoshiro::Xoroshiro128StarStar;
-
+    checkpoints: &Checkpoints,
+    eccentricities: &Eccentricities,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
     // Do iterative guessing
     let mut guess_radius = init;
-    let final_local_states = Rc::new(RefCell::new(None));
-    loop {
-        info!("Do clustering with radius {}", guess_radius);
-        let (local_states, num_centers, probe) = worker.dataflow::<(), _, _>(|scope| {
-            let nodes = adjacencies.nodes::<_, NodeState>(scope);
-
-            let mut rand = Xoroshiro128StarStar::seed_from_u64(seed);
-            for _ in 0..nodes.scope().index() {
-                rand.jump();
+    let radius_checkpoints = |radius: u32| checkpoints.phase(&format!("radius-{}", radius));
+    if search == RadiusSearch::Geometric && checkpoints.is_resuming() {
+        // Guesses smaller than the last checkpointed one already turned out too small
+        while radius_checkpoints(guess_radius * step)
+            .resume_point(worker)
+            .is_some()
+        {
+            info!("Skipping clustering with radius {}", guess_radius);
+            guess_radius *= step;
+        }
+    }
+    // Centers are sampled with probability doubling at each generation, so
+    // those of the first generations are expected to be less than half the
+    // memory, leaving room for the clusters built on top of them
+    let reused_generation = match search {
+        RadiusSearch::Interpolated { reuse: true } => 31 - (memory / 2).max(1).leading_zeros(),
+        _ => 0,
+    };
+    // The radius and the states of the last attempt with too many clusters
+    let mut failed: Option<(u32, Vec<(Node, NodeState<D>)>)> = None;
+    let (num_centers, mut final_local_states) = loop {
+        let reused = failed
+            .as_ref()
+            .filter(|_| reused_generation > 0)
+            .map(|(_radius, states)| (&states[..], reused_generation));
+        let (local_states, num_centers, elapsed_clustering) = cluster_with_radius(
+            &adjacencies,
+            worker,
+            guess_radius,
+            n,
+            seed,
+            reused,
+            &radius_checkpoints(guess_radius),
+        );
+        iteration_information.push((guess_radius, elapsed_clustering, num_centers));
+
+        if num_centers <= memory {
+            info!("Small enough clustering built");
+            break (num_centers, local_states);
+        }
+
+        // Keep the states only if the next attempts can reuse them
+        let local_states = if reused_generation > 0 {
+            local_states
+        } else {
+            Vec::new()
+        };
+        failed = Some((guess_radius, local_states));
+        guess_radius = match search {
+            RadiusSearch::Geometric => guess_radius * step,
+            RadiusSearch::Interpolated { .. } => {
+                predict_radius(iteration_information, memory, step)
             }
-            let rand = Rc::new(RefCell::new(rand));
+        };
+    };
 
-            let nodes = adjacencies.nodes::<_, NodeState>(scope);
+    // With much fewer clusters than allowed, a radius between the last two
+    // attempts might still fit, giving a better approximation
+    if let (RadiusSearch::Interpolated { .. }, Some((failed_radius, failed_states))) =
+        (search, &failed)
+    {
+        let radius = (*failed_radius as f64 * guess_radius as f64).sqrt() as u32;
+        if num_centers < memory / 2 && radius > *failed_radius && radius < guess_radius {
+            let reused =
+                Some((&failed_states[..], reused_generation)).filter(|_| reused_generation > 0);
+            let (local_states, num_centers, elapsed_clustering) = cluster_with_radius(
+                &adjacencies,
+                worker,
+                radius,
+                n,
+                seed,
+                reused,
+                &radius_checkpoints(radius),
+            );
+            iteration_information.push((radius, elapsed_clustering, num_centers));
+            if num_centers <= memory {
+                info!("Refined clustering with radius {} fits as well", radius);
+                final_local_states = local_states;
+            }
+        }
+    }
 
-            build_clustering(&adjacencies, &nodes, guess_radius, 2.0, n, rand)
+    let (diameter, elapsed_approximation) = approximate(
+        adjacencies,
+        worker,
+        final_local_states,
+        final_approx_probe,
+        eccentricities,
+    );
+
+    let elapsed = elapsed_approximation + iteration_information.iter().map(|trip| trip.1).sum();
+
+    (diameter, elapsed)
+}
+
+/// Like `rand_cluster_guess`, but instead of clustering the graph again with
+/// a larger radius when there are too many clusters, it clusters their
+/// auxiliary graph, distributed among the workers like the input graph.
+/// Only the auxiliary graph of the last level, with at most `memory` nodes,
+/// is collected on a single worker.
+pub fn rand_cluster_recursive<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    memory: u32,
+    init: u32,
+    step: u32,
+    n: Node,
+    seed: u64,
+    final_approx_probe: &mut Option<Duration>,
+    iteration_information: &mut Vec<(u32, Duration, u32)>,
+    checkpoints: &Checkpoints,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
+    let (diameter, elapsed_levels) = cluster_levels::<D, _, _>(
+        adjacencies,
+        worker,
+        0,
+        n,
+        HashMap::new(),
+        init,
+        (memory, step, seed),
+        final_approx_probe,
+        iteration_information,
+        checkpoints,
+    );
+
+    let elapsed = elapsed_levels + iteration_information.iter().map(|trip| trip.1).sum();
+
+    (diameter, elapsed)
+}
+
+/// Clusters the graph of the given level, with the number of its nodes and
+/// the radii of the clusters of the previous levels they stand for, then
+/// either approximates the diameter with its clustering, if it is small
+/// enough, or goes on with the next level on its auxiliary graph. Returns
+/// the time spent out of the clusterings, which are in `iteration_information`.
+/// The auxiliary graphs, and the distances in them, are 64 bits wide.
+fn cluster_levels<D: Distance, W: Weight, A: timely::communication::Allocate>(
+    level_adjacencies: DistributedAdjacencies<W>,
+    worker: &mut timely::worker::Worker<A>,
+    level: u32,
+    level_n: Node,
+    level_radii: HashMap<Node, u64>,
+    radius: u32,
+    (memory, step, seed): (u32, u32, u64),
+    final_approx_probe: &mut Option<Duration>,
+    iteration_information: &mut Vec<(u32, Duration, u32)>,
+    checkpoints: &Checkpoints,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
+    use rand_xoshiro::Xoroshiro128StarStar;
+
+    info!(
+        "Do clustering of level {} ({} nodes) with radius {}",
+        level, level_n, radius
+    );
+    let level_checkpoints = checkpoints.phase(&format!("level-{}", level));
+    let resume = level_checkpoints.resume_point(worker);
+    let (local_states, num_centers, probe) = worker.dataflow::<(), _, _>(|scope| {
+        let mut rand = Xoroshiro128StarStar::seed_from_u64(seed);
+        for _ in 0..scope.index() {
+            rand.jump();
+        }
+        let rand = Rc::new(RefCell::new(rand));
+
+        let (nodes, frozen) =
+            initial_states::<_, D, _>(&level_adjacencies, scope, &level_checkpoints, resume);
+
+        build_clustering(
+            &level_adjacencies,
+            &nodes,
+            &frozen,
+            D::from_u32(radius),
+            2.0,
+            level_n,
+            rand,
+            &level_checkpoints,
+            resume.map(|round| round + 1).unwrap_or(0),
+        )
+    });
+    let elapsed_clustering = run_to_completion(worker, probe);
+    let num_centers = num_centers.borrow().expect("missing centers count");
+    info!(
+        "Clustering of level {} completed in {:?}, with {} clusters",
+        level, elapsed_clustering, num_centers
+    );
+    iteration_information.push((radius, elapsed_clustering, num_centers));
+    let local_states = local_states.borrow_mut().take().unwrap_or_else(Vec::new);
+
+    // With as many clusters as nodes, and a radius that cannot grow any
+    // further, the next level would cluster the same graph in the same way
+    let next_radius = radius.saturating_mul(step);
+    let stuck = num_centers as Node >= level_n && next_radius <= radius;
+    if stuck {
+        warn!(
+            "Clustering of level {} does not shrink the graph, collecting its {} clusters",
+            level, num_centers
+        );
+    }
+
+    if num_centers <= memory || stuck {
+        info!("Small enough clustering built");
+        let (diameter_box, probe) = worker.dataflow::<(), _, _>(move |scope| {
+            let clustering = local_states.to_stream(scope);
+            collect_and_approximate(level_adjacencies, &clustering, level_radii)
+                .map(|(bounds, _reach)| bounds)
+                .collect_single()
         });
-        let elapsed_clustering = run_to_completion(worker, probe);
+
+        let elapsed_approximation = run_to_completion(worker, probe);
+        final_approx_probe.replace(elapsed_approximation.clone());
+        let diameter = diameter_box.borrow_mut().take();
         info!(
-            "Clustering completed in {:?}, with {:?} clusters, radius {}",
-            elapsed_clustering,
-            num_centers.borrow(),
-            guess_radius
+            "Final diameter approximation in {:?}",
+            elapsed_approximation
         );
-        iteration_information.push((
-            guess_radius,
-            elapsed_clustering,
-            num_centers.borrow().expect("missing centers count"),
-        ));
+        return (diameter, elapsed_approximation);
+    }
 
-        if num_centers.borrow().expect("missing centers count") <= memory {
-            info!("Small enough clustering built");
-            let states = local_states
-                .borrow_mut()
-                .take()
-                .expect("missing local states");
-            final_local_states.borrow_mut().replace(states);
-            break;
-        }
+    let (auxiliary_adjacencies, auxiliary_radii, probe) = worker.dataflow::<(), _, _>(|scope| {
+        let clustering = local_states.to_stream(scope);
+        distribute_auxiliary(&level_adjacencies, &clustering, level_radii)
+    });
+    let elapsed_auxiliary = run_to_completion(worker, probe);
+
+    let auxiliary_adjacencies = level_adjacencies.with_adjacencies(
+        auxiliary_adjacencies
+            .borrow_mut()
+            .take()
+            .unwrap_or_else(HashMap::new),
+    );
+    let auxiliary_radii = auxiliary_radii
+        .borrow_mut()
+        .take()
+        .unwrap_or_else(HashMap::new);
+    let (diameter, elapsed_levels) = cluster_levels::<u64, _, _>(
+        auxiliary_adjacencies,
+        worker,
+        level + 1,
+        num_centers as Node,
+        auxiliary_radii,
+        next_radius,
+        (memory, step, seed),
+        final_approx_probe,
+        iteration_information,
+        checkpoints,
+    );
+
+    (diameter, elapsed_auxiliary + elapsed_levels)
+}
+
+/// Clusters the graph with exponentially distributed start times (Miller,
+/// Peng and Xu): each node starts a cluster at a time that is shifted back by
+/// an exponential random variable with rate `beta`, unless it is reached
+/// earlier by another cluster. All the clusters grow at the same speed, in
+/// a single shortest paths computation, without guessing the radius.
+pub fn mpx<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    beta: f64,
+    n: Node,
+    seed: u64,
+    final_approx_probe: &mut Option<Duration>,
+    eccentricities: &Eccentricities,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
+    let (shift_states, elapsed_clustering) =
+        shift_clustering::<D, _>(&adjacencies, worker, beta, n, seed);
+    let local_states = shift_states
+        .into_iter()
+        .map(|(id, state)| {
+            let frozen = NodeState::Frozen {
+                root: state.root,
+                distance: state.distance,
+                generation: 0,
+            };
+            (id, frozen)
+        })
+        .collect();
+
+    let (diameter, elapsed_approximation) = approximate(
+        adjacencies,
+        worker,
+        local_states,
+        final_approx_probe,
+        eccentricities,
+    );
+
+    (diameter, elapsed_clustering + elapsed_approximation)
+}
+
+/// With probability at least 1 - 1/n no shift is larger than this, and the
+/// few larger ones are capped, so that start times are positive
+fn shift_cap(beta: f64, n: Node) -> f64 {
+    (2.0 * (n as f64).ln() / beta).ceil()
+}
+
+/// The start time of the cluster of the given node, shifted back from the cap
+/// by an exponential random variable with rate `beta`. Seeding by node makes
+/// the shifts independent of the order of the nodes
+fn shift_start(id: Node, beta: f64, cap: f64, seed: u64) -> u32 {
+    use rand_xoshiro::Xoroshiro128StarStar;
+
+    let mut rand = Xoroshiro128StarStar::seed_from_u64(seed.wrapping_add(id as u64));
+    let shift = -(1.0 - rand.gen::<f64>()).ln() / beta;
+    (cap - shift.min(cap)).floor() as u32
+}
+
+/// The clustering of `mpx`, with the states of the nodes of this worker
+fn shift_clustering<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    beta: f64,
+    n: Node,
+    seed: u64,
+) -> (Vec<(Node, ShiftState<D>)>, Duration) {
+    let cap = shift_cap(beta, n);
+    info!("Shifts are capped at {}", cap);
 
-        guess_radius *= step;
+    let local_states = Rc::new(RefCell::new(Vec::new()));
+    let local_states_ref = Rc::clone(&local_states);
+    let (stats, probe) = worker.dataflow::<(), _, _>(|scope| {
+        let nodes = adjacencies.nodes::<_, ()>(scope).map(move |(id, ())| {
+            let state = ShiftState {
+                start: D::from_u32(shift_start(id, beta, cap, seed)),
+                root: id,
+                distance: D::zero(),
+                updated: true,
+                round: 0,
+            };
+            (id, state)
+        });
+
+        let program = ShiftExpansion {
+            _distance: std::marker::PhantomData,
+        };
+        run_until_quiescent(adjacencies, &nodes, program)
+            .unary(Pipeline, "collect_and_count", move |_, _| {
+                move |input, output| {
+                    input.for_each(|t, data| {
+                        let data = data.replace(Vec::new());
+                        let mut centers = 0u32;
+                        let mut rounds = 0u32;
+                        let mut local_states = local_states.borrow_mut();
+                        for (id, state) in data.into_iter() {
+                            if state.root == id {
+                                centers += 1;
+                            }
+                            rounds = std::cmp::max(rounds, state.round + 1);
+                            local_states.push((id, state));
+                        }
+                        output.session(&t).give((centers, rounds));
+                    });
+                }
+            })
+            .exchange(|_| 0)
+            .accumulate((0, 0), |(centers, rounds), data| {
+                for &(c, r) in data.iter() {
+                    *centers += c;
+                    *rounds = std::cmp::max(*rounds, r);
+                }
+            })
+            .collect_single()
+    });
+
+    let elapsed_clustering = run_to_completion(worker, probe);
+    if let Some((centers, rounds)) = stats.borrow_mut().take() {
+        info!(
+            "Clustering completed in {:?} and {} rounds, with {} clusters",
+            elapsed_clustering, rounds, centers
+        );
     }
 
-    let (diameter_box, probe) = worker.dataflow::<(), _, _>(move |scope| {
-        let local_states = final_local_states.borrow_mut().take().into_iter().flatten();
+    (local_states_ref.replace(Vec::new()), elapsed_clustering)
+}
+
+/// Approximates the diameter with the auxiliary graph of the given clustering,
+/// of the input graph, saving the bounds to the eccentricities of the nodes
+/// of this worker it implies, if asked to.
+fn approximate<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    local_states: Vec<(Node, NodeState<D>)>,
+    final_approx_probe: &mut Option<Duration>,
+    eccentricities: &Eccentricities,
+) -> (Option<DiameterBounds<u64>>, std::time::Duration) {
+    let (diameter, node_bounds, elapsed) = approximate_with_eccentricities(
+        adjacencies,
+        worker,
+        local_states,
+        final_approx_probe,
+        eccentricities.is_enabled(),
+    );
+    eccentricities.save(&node_bounds);
+
+    (diameter, elapsed)
+}
+
+/// Like `approximate`, returning the bounds to the eccentricities of the
+/// nodes of this worker, if asked to: a node reaches any other through its
+/// center, which in turn is within `reach` of any node. The clustering gives
+/// no lower bounds.
+fn approximate_with_eccentricities<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    local_states: Vec<(Node, NodeState<D>)>,
+    final_approx_probe: &mut Option<Duration>,
+    keep_eccentricities: bool,
+) -> (
+    Option<DiameterBounds<u64>>,
+    EccentricityBounds,
+    std::time::Duration,
+) {
+    let distances: Vec<(Node, D)> = if keep_eccentricities {
+        local_states
+            .iter()
+            .map(|(id, state)| (*id, state.distance()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let (result_box, probe) = worker.dataflow::<(), _, _>(move |scope| {
         let clustering = local_states.to_stream(scope);
-        collect_and_approximate(adjacencies, &clustering).collect_single()
+        collect_and_approximate(adjacencies, &clustering, HashMap::new())
+            .broadcast()
+            .collect_single()
     });
 
     let elapsed_approximation = run_to_completion(worker, probe);
     final_approx_probe.replace(elapsed_approximation.clone());
-    let diameter = diameter_box.borrow_mut().take();
+    let result = result_box.borrow_mut().take();
     info!(
         "Final diameter approximation in {:?}",
         elapsed_approximation
     );
 
-    let elapsed = elapsed_approximation + iteration_information.iter().map(|trip| trip.1).sum();
+    let mut node_bounds = EccentricityBounds::new();
+    if let Some((_bounds, reach)) = result {
+        for (id, distance) in distances {
+            node_bounds.refine(id, 0, distance.to_u64() + reach);
+        }
+    }
 
-    (diameter, elapsed)
+    (
+        result.map(|(bounds, _reach)| bounds),
+        node_bounds,
+        elapsed_approximation,
+    )
 }
 
-fn collect_and_approximate<G: Scope>(
+/// Clusters the graph with the given radius, returning the bounds to the
+/// diameter along with upper bounds to the eccentricities of the nodes of
+/// this worker
+pub fn clustering_eccentricities<D: Distance, A: timely::communication::Allocate>(
     adjacencies: DistributedAdjacencies,
-    clustering: &Stream<G, (u32, NodeState)>,
-) -> Stream<G, u32> {
-    // let l_radius = nodes.scope().count_logger().expect("missing logger");
-    let auxiliary_graph = remap_edges(&adjacencies, &clustering);
-    let clusters_radii = clustering
-        .map(|(_id, state)| (state.root(), state.distance()))
+    worker: &mut timely::worker::Worker<A>,
+    radius: u32,
+    n: Node,
+    seed: u64,
+    checkpoints: &Checkpoints,
+) -> (
+    Option<DiameterBounds<u64>>,
+    EccentricityBounds,
+    std::time::Duration,
+) {
+    let (local_states, _num_centers, elapsed_clustering) =
+        cluster_with_radius::<D, _>(&adjacencies, worker, radius, n, seed, None, checkpoints);
+    let (diameter, node_bounds, elapsed_approximation) =
+        approximate_with_eccentricities(adjacencies, worker, local_states, &mut None, true);
+
+    (
+        diameter,
+        node_bounds,
+        elapsed_clustering + elapsed_approximation,
+    )
+}
+
+/// The radius of each cluster, keyed by its center. The clustered nodes may stand
+/// for clusters themselves, whose radii are in `radii` (missing ones are zero):
+/// the radius of a cluster then accounts for the ones of its nodes.
+fn clusters_radii<G: Scope, D: Distance>(
+    clustering: &Stream<G, (Node, NodeState<D>)>,
+    radii: HashMap<Node, u64>,
+) -> Stream<G, (Node, u64)> {
+    clustering
+        .map(move |(id, state)| {
+            let radius = radii.get(&id).copied().unwrap_or(0);
+            (state.root(), state.distance().to_u64() + radius)
+        })
         .aggregate(
             |_center, distance, agg| {
                 *agg = std::cmp::max(*agg, distance);
             },
-            |center, agg: u32| (center, agg),
+            |center, agg: u64| (center, agg),
             |key| *key as u64,
         )
-        .inspect_batch(move |_t, data| {
-            let mut hist = HashMap::new();
-            data.iter().for_each(|(_center, radius)| {
-                hist.entry(radius).and_modify(|c| *c += 1).or_insert(1);
-            });
-            // for (radius, count) in hist.drain() {
-            //     l_radius.log((CountEvent::RadiusHist(0, *radius), count as u64));
-            // }
+}
+
+fn collect_and_approximate<G: Scope, D: Distance, W: Weight>(
+    adjacencies: DistributedAdjacencies<W>,
+    clustering: &Stream<G, (Node, NodeState<D>)>,
+    radii: HashMap<Node, u64>,
+) -> Stream<G, (DiameterBounds<u64>, u64)> {
+    // let l_radius = nodes.scope().count_logger().expect("missing logger");
+    let auxiliary_graph = remap_edges(&adjacencies, &clustering);
+    let clusters_radii = clusters_radii(clustering, radii).inspect_batch(move |_t, data| {
+        let mut hist = HashMap::new();
+        data.iter().for_each(|(_center, radius)| {
+            hist.entry(radius).and_modify(|c| *c += 1).or_insert(1);
         });
+        // for (radius, count) in hist.drain() {
+        //     l_radius.log((CountEvent::RadiusHist(0, *radius), count as u64));
+        // }
+    });
 
     // Collect the auxiliary graph and compute the diameter on it
     let mut stash_auxiliary = HashMap::new();
@@ -678,12 +1437,12 @@ fn collect_and_approximate<G: Scope>(
                     .entry(t.time().clone())
                     .or_insert_with(HashMap::new);
                 for ((u, v), w) in data.into_iter() {
-                    let u1: u32 = *remapping.entry(u).or_insert_with(|| {
+                    let u1: Node = *remapping.entry(u).or_insert_with(|| {
                         let x = node_count;
                         node_count += 1;
                         x
                     });
-                    let v1: u32 = *remapping.entry(v).or_insert_with(|| {
+                    let v1: Node = *remapping.entry(v).or_insert_with(|| {
                         let x = node_count;
                         node_count += 1;
                         x
@@ -696,7 +1455,7 @@ fn collect_and_approximate<G: Scope>(
                 let data = data.replace(Vec::new());
                 stash_radii
                     .entry(t.time().clone())
-                    .or_insert_with(HashMap::<u32, u32>::new)
+                    .or_insert_with(HashMap::<Node, u64>::new)
                     .extend(data.into_iter().map(|(root, radius)| {
                         (
                             *remapping.entry(root).or_insert_with(|| {
@@ -725,38 +1484,252 @@ fn collect_and_approximate<G: Scope>(
                         n,
                         edges.len()
                     );
-                    if n == 1 {
+                    // Any two nodes are connected by a path going through their
+                    // centers, and an edge of the auxiliary graph is never shorter
+                    // than the distance between its endpoints. So the diameter of
+                    // the auxiliary graph plus twice the largest radius is an upper
+                    // bound. The radii are lengths of paths that need not be shortest,
+                    // so the clustering gives no lower bound.
+                    let auxiliary_diameter = if n == 1 {
                         info!("Auxiliary graph with a single node");
-                        // In this case the max radius is a two approximation, so we have to
-                        // double it to make an upper bound estimate like in the other cases
-                        output.session(&t).give(2 * max_radius);
+                        0
                     } else {
                         let start = std::time::Instant::now();
-                        let diameter = approx_diameter(edges, n as u32)
-                            .0
-                            .into_iter()
-                            .map(|(approx, (u, v))| {
-                                let res = approx + radii[&(u as u32)] + radii[&(v as u32)];
-                                res
-                            })
-                            .max()
-                            .expect("called max on an empty collection");
+                        let bounds = ifub_diameter(edges, n as Node).0;
                         let end = std::time::Instant::now();
                         let elapsed = end - start;
                         info!(
-                            "Diameter approximation on auxiliary graph took {:?}",
-                            elapsed
+                            "Diameter of the auxiliary graph ({}) took {:?}",
+                            bounds.lower, elapsed
                         );
-                        if max_radius > diameter {
-                            info!("Outputting max radius: {}!", max_radius);
-                            output.session(&t).give(max_radius);
-                        } else {
-                            info!("Outputting combined diameter: {}", diameter);
-                            output.session(&t).give(diameter);
-                        }
-                    }
+                        bounds.lower
+                    };
+                    let bounds = DiameterBounds {
+                        lower: 0,
+                        upper: Some(auxiliary_diameter + 2 * max_radius),
+                        witness_pair: None,
+                    };
+                    info!("Outputting diameter bounds: {:?}", bounds);
+                    // Any node is within this distance from any center
+                    let reach = auxiliary_diameter + max_radius;
+                    output.session(&t).give((bounds, reach));
                 }
             });
         },
     )
 }
+
+/// Distributes the auxiliary graph of the clustering among the workers, with
+/// the edges of each center on the worker of the center, and computes the
+/// radii of the clusters, which its nodes stand for. The weights are sums of
+/// distances, so they are 64 bits wide whatever the distances of the graph.
+fn distribute_auxiliary<G: Scope, D: Distance, W: Weight>(
+    adjacencies: &DistributedAdjacencies<W>,
+    clustering: &Stream<G, (Node, NodeState<D>)>,
+    radii: HashMap<Node, u64>,
+) -> (
+    Rc<RefCell<Option<HashMap<Node, Vec<(Node, u64)>>>>>,
+    Rc<RefCell<Option<HashMap<Node, u64>>>>,
+    ProbeHandle<G::Timestamp>,
+) {
+    let auxiliary_adjacencies = Rc::new(RefCell::new(None));
+    let auxiliary_adjacencies_ref = Rc::clone(&auxiliary_adjacencies);
+    let auxiliary_radii = Rc::new(RefCell::new(None));
+    let auxiliary_radii_ref = Rc::clone(&auxiliary_radii);
+
+    // The self loops of the centers are kept as nodes without edges, so
+    // that the centers of isolated clusters are nodes of the auxiliary graph
+    let edges = remap_edges(adjacencies, clustering)
+        .flat_map(|((u, v), w)| {
+            if u == v {
+                vec![(u, None)]
+            } else {
+                vec![(u, Some((v, w))), (v, Some((u, w)))]
+            }
+        })
+        .unary(
+            ExchangePact::new(|pair: &(Node, Option<(Node, u64)>)| pair.0 as u64),
+            "collect_auxiliary_edges",
+            move |_, _| {
+                move |input, output| {
+                    input.for_each(|t, data| {
+                        let data = data.replace(Vec::new());
+                        let mut adjacencies = auxiliary_adjacencies.borrow_mut();
+                        let adjacencies = adjacencies.get_or_insert_with(HashMap::new);
+                        for (u, edge) in data.into_iter() {
+                            let neighbours = adjacencies.entry(u).or_insert_with(Vec::new);
+                            if let Some(edge) = edge {
+                                neighbours.push(edge);
+                            }
+                        }
+                        output.session(&t).give(());
+                    });
+                }
+            },
+        );
+
+    let radii = clusters_radii(clustering, radii).unary(
+        Pipeline,
+        "collect_auxiliary_radii",
+        move |_, _| {
+            move |input, output| {
+                input.for_each(|t, data| {
+                    let data = data.replace(Vec::new());
+                    auxiliary_radii
+                        .borrow_mut()
+                        .get_or_insert_with(HashMap::new)
+                        .extend(data.into_iter());
+                    output.session(&t).give(());
+                });
+            }
+        },
+    );
+
+    let probe = edges.concat(&radii).probe();
+
+    (auxiliary_adjacencies_ref, auxiliary_radii_ref, probe)
+}
+
+#[test]
+fn test_predict_radius() {
+    let attempt = |radius, centers| (radius, Duration::from_secs(0), centers);
+    // A single attempt gives the geometric step
+    assert_eq!(predict_radius(&[attempt(4, 1000)], 10, 2), 8);
+    // Clusters decreasing as the square of the radius
+    assert_eq!(
+        predict_radius(&[attempt(4, 1600), attempt(8, 400)], 100, 2),
+        16
+    );
+    // Predictions are capped to the square of the step
+    assert_eq!(
+        predict_radius(&[attempt(4, 1000), attempt(8, 999)], 10, 2),
+        32
+    );
+    // No decrease in the clusters falls back to the geometric step
+    assert_eq!(
+        predict_radius(&[attempt(4, 1000), attempt(8, 1000)], 10, 2),
+        16
+    );
+}
+
+#[test]
+fn test_rand_cluster_recursive_bounds_diameter() {
+    // Two components: a weighted cycle with chords, and a path
+    let mut edges: Vec<((Node, Node), u32)> = (0..20)
+        .map(|i: u32| ((i as Node, (i as Node + 1) % 20), 1 + i % 3))
+        .collect();
+    edges.push(((0, 10), 4));
+    edges.push(((5, 15), 7));
+    edges.push(((20, 21), 3));
+    edges.push(((21, 22), 2));
+    let n = 23;
+    let exact = ifub_diameter(edges.clone(), n).0.lower as u64;
+
+    timely::execute_directly(move |worker| {
+        let checkpoints = Checkpoints::new(&std::env::temp_dir(), "none", 0, None, false);
+        for &(memory, init) in &[(4, 1), (8, 2), (30, 1)] {
+            let adjacencies = DistributedAdjacencies::from_edge_list(0, 1, n, &edges);
+            let mut iteration_information = Vec::new();
+            let (bounds, _) = rand_cluster_recursive::<u32, _>(
+                adjacencies,
+                worker,
+                memory,
+                init,
+                2,
+                n,
+                42,
+                &mut None,
+                &mut iteration_information,
+                &checkpoints,
+            );
+            let bounds = bounds.expect("missing bounds");
+            assert!(bounds.lower <= exact, "{:?} for {}", bounds, exact);
+            assert!(bounds.upper.expect("missing upper bound") >= exact);
+            // Each level but the last has more clusters than allowed
+            let (last, levels) = iteration_information.split_last().unwrap();
+            assert!(last.2 <= memory);
+            assert!(levels.iter().all(|level| level.2 > memory));
+        }
+    });
+}
+
+#[test]
+fn test_cluster_levels_stop_without_edges() {
+    // Isolated nodes never merge into fewer clusters than nodes: the levels
+    // stop once the radius cannot grow any further
+    let n = 10;
+    timely::execute_directly(move |worker| {
+        let checkpoints = Checkpoints::new(&std::env::temp_dir(), "none", 0, None, false);
+        let adjacencies = DistributedAdjacencies::from_edge_list(0, 1, n, &[]);
+        let mut iteration_information = Vec::new();
+        let (bounds, _) = rand_cluster_recursive::<u32, _>(
+            adjacencies,
+            worker,
+            2,
+            std::u32::MAX - 1,
+            2,
+            n,
+            42,
+            &mut None,
+            &mut iteration_information,
+            &checkpoints,
+        );
+        assert!(bounds.is_some());
+        assert_eq!(iteration_information.len(), 2);
+    });
+}
+
+#[test]
+fn test_mpx_clusters_and_bounds() {
+    // A weighted cycle with chords
+    let mut edges: Vec<((Node, Node), u32)> = (0..20)
+        .map(|i: u32| ((i as Node, (i as Node + 1) % 20), 1 + i % 3))
+        .collect();
+    edges.push(((0, 10), 4));
+    edges.push(((5, 15), 7));
+    let n = 20;
+    let neighbourhoods = init_neighbourhoods(edges.clone(), n);
+    let distances: Vec<Vec<Option<u32>>> = (0..n).map(|x| dijkstra(&neighbourhoods, x).0).collect();
+    let diameter = ifub_diameter(edges.clone(), n).0.lower as u64;
+
+    timely::execute_directly(move |worker| {
+        let no_eccentricities = Eccentricities::new(&std::env::temp_dir(), "none", 0, false);
+        for &(beta, seed) in &[(0.1, 1), (0.5, 2), (2.0, 3)] {
+            let adjacencies = DistributedAdjacencies::from_edge_list(0, 1, n, &edges);
+            let cap = shift_cap(beta, n);
+            let starts: Vec<u32> = (0..n).map(|id| shift_start(id, beta, cap, seed)).collect();
+            let arrival = |root: Node, id: Node| {
+                let distance = distances[root as usize][id as usize].expect("disconnected graph");
+                (starts[root as usize] + distance, root)
+            };
+
+            let (states, _) = shift_clustering::<u32, _>(&adjacencies, worker, beta, n, seed);
+            assert_eq!(states.len(), n as usize);
+            for (id, state) in states {
+                // The root of each node reaches it first, along a shortest path
+                assert_eq!(state.start, starts[state.root as usize]);
+                assert_eq!(
+                    Some(state.distance),
+                    distances[state.root as usize][id as usize]
+                );
+                assert_eq!(
+                    state.arrival(),
+                    (0..n).map(|root| arrival(root, id)).min().unwrap()
+                );
+            }
+
+            let (bounds, _) = mpx::<u32, _>(
+                adjacencies,
+                worker,
+                beta,
+                n,
+                seed,
+                &mut None,
+                &no_eccentricities,
+            );
+            let bounds = bounds.expect("missing bounds");
+            assert!(bounds.lower <= diameter);
+            assert!(bounds.upper.expect("missing upper bound") >= diameter);
+        }
+    });
+}
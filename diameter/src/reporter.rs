@@ -1,3 +1,8 @@
+use crate::bounds::DiameterBounds;
+use crate::components::Components;
+use crate::hyperball::HyperBallRepetitions;
+use crate::node::Node;
+use crate::radius::RadiusBounds;
 use crate::Config;
 use chrono::prelude::*;
 
@@ -14,7 +19,16 @@ pub struct Reporter {
     counters: Vec<(String, u32, u32, u64)>,
     // Table with iteration, radius, duration, and size of the graph
     rand_cluster_guesses: Vec<(u32, u32, Duration, u32)>,
-    diameter: Option<u32>,
+    bounds: Option<DiameterBounds<u64>>,
+    radius: Option<RadiusBounds>,
+    hyperball: Option<HyperBallRepetitions>,
+    // Table with the source, target, and distance of the queries, if any
+    distance_answers: Vec<((Node, Node), Option<u64>)>,
+    components: Option<Components>,
+    // The time to find the components, out of the total one
+    components_time: Option<Duration>,
+    // The delta of the delta-stepping runs, given or picked automatically
+    delta: Option<u32>,
     duration: Option<Duration>,
     final_approx_time: Option<Duration>,
     killed: bool,
@@ -27,18 +41,45 @@ impl Reporter {
             config: config,
             counters: Vec::new(),
             rand_cluster_guesses: Vec::new(),
-            diameter: None,
+            bounds: None,
+            radius: None,
+            hyperball: None,
+            distance_answers: Vec::new(),
+            components: None,
+            components_time: None,
+            delta: None,
             duration: None,
             final_approx_time: None,
             killed: false,
         }
     }
 
-    pub fn set_result(&mut self, diameter: u32, elapsed: Duration) {
-        self.diameter.replace(diameter);
+    pub fn set_result(&mut self, bounds: DiameterBounds<u64>, elapsed: Duration) {
+        self.bounds.replace(bounds);
         self.duration.replace(elapsed);
     }
 
+    pub fn set_radius(&mut self, radius: RadiusBounds) {
+        self.radius.replace(radius);
+    }
+
+    pub fn set_hyperball_repetitions(&mut self, repetitions: HyperBallRepetitions) {
+        self.hyperball.replace(repetitions);
+    }
+
+    pub fn set_distance_answers(&mut self, answers: Vec<((Node, Node), Option<u64>)>) {
+        self.distance_answers = answers;
+    }
+
+    pub fn set_components(&mut self, components: Components, elapsed: Duration) {
+        self.components.replace(components);
+        self.components_time.replace(elapsed);
+    }
+
+    pub fn set_delta(&mut self, delta: u32) {
+        self.delta.replace(delta);
+    }
+
     pub fn set_final_approx_time(&mut self, final_approx_time: Duration) {
         self.final_approx_time.replace(final_approx_time);
     }
@@ -59,7 +100,10 @@ impl Reporter {
             .push((iteration, radius, duration, num_centers));
     }
 
-    fn sha(&self) -> String {
+    pub fn sha(&self) -> String {
+        if let Some(sha) = &self.config.sha {
+            return sha.clone();
+        }
         let datestr = self.date.to_rfc2822();
         let mut sha = Sha256::new();
         sha.input(datestr);
@@ -78,7 +122,7 @@ impl Reporter {
     }
 
     pub fn already_run(&self) -> Option<String> {
-        if self.config.rerun {
+        if self.config.rerun || self.config.resume.is_some() {
             return None;
         }
         let dbpath = Self::get_db_path();
@@ -91,7 +135,8 @@ impl Reporter {
                 dataset == ?4 AND
                 algorithm == ?5 AND 
                 parameters == ?6 AND
-                offline == ?7",
+                offline == ?7 AND
+                (?8 IS NULL OR delta == ?8)",
             params![
                 format!("{}", self.config.seed()),
                 self.config.threads.unwrap_or(1) as u32,
@@ -99,7 +144,8 @@ impl Reporter {
                 self.config.dataset,
                 self.config.algorithm.name(),
                 self.config.algorithm.parameters_string(),
-                self.config.offline
+                self.config.offline,
+                self.config.delta
             ],
             |row| row.get(0),
         )
@@ -117,9 +163,16 @@ impl Reporter {
         let dbpath = Self::get_db_path();
         let mut conn = Connection::open(dbpath).expect("error connecting to the database");
         create_tables_if_needed(&conn);
+        let tx = conn.transaction().expect("problem starting transaction");
+
+        // A resumed run keeps the sha of the killed one, whose row it replaces
+        if self.config.resume.is_some() {
+            tx.execute("DELETE FROM main WHERE sha == ?1 AND killed", params![sha])
+                .expect("error removing the killed run");
+        }
 
         if self.killed {
-            conn.execute(
+            tx.execute(
             "INSERT INTO main ( sha, date, seed, threads, hosts, dataset, algorithm, parameters, diameter, total_time_ms, offline, final_diameter_time_ms, killed )
                 VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13 )",
                 params![
@@ -140,13 +193,18 @@ impl Reporter {
             )
             .expect("error inserting into main table");
         } else {
-            let tx = conn.transaction().expect("problem starting transaction");
+            let bounds = self.bounds.expect("missing diameter bounds");
+            let diameter = if self.config.algorithm.estimates_from_above() {
+                bounds.upper.expect("missing upper bound")
+            } else {
+                bounds.lower
+            };
 
             {
                 // Insert into main table
                 tx.execute(
-                "INSERT INTO main ( sha, date, seed, threads, hosts, dataset, algorithm, parameters, diameter, total_time_ms, offline, final_diameter_time_ms )
-                 VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12 )",
+                "INSERT INTO main ( sha, date, seed, threads, hosts, dataset, algorithm, parameters, diameter, total_time_ms, offline, final_diameter_time_ms, diameter_lower, diameter_upper, witness_u, witness_v, radius_lower, radius_upper, radius_center, num_center_candidates, num_components, unreachable_nodes, delta, components_time_ms )
+                 VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24 )",
                 params![
                     sha,
                     self.date.to_rfc3339(),
@@ -156,10 +214,22 @@ impl Reporter {
                     self.config.dataset,
                     self.config.algorithm.name(),
                     self.config.algorithm.parameters_string(),
-                    self.diameter.expect("missing diameter"),
+                    diameter as i64,
                     self.duration.expect("missing total time").as_millis() as u32,
                     self.config.offline,
-                    self.final_approx_time.map(|dur| dur.as_millis() as u32)
+                    self.final_approx_time.map(|dur| dur.as_millis() as u32),
+                    bounds.lower as i64,
+                    bounds.upper.map(|upper| upper as i64),
+                    bounds.witness_pair.map(|(u, _v)| u as i64),
+                    bounds.witness_pair.map(|(_u, v)| v as i64),
+                    self.radius.as_ref().map(|radius| radius.lower as i64),
+                    self.radius.as_ref().map(|radius| radius.upper as i64),
+                    self.radius.as_ref().map(|radius| radius.center as i64),
+                    self.radius.as_ref().map(|radius| radius.num_candidates as i64),
+                    self.components.as_ref().map(|components| components.count as i64),
+                    self.components.as_ref().map(|components| components.unreachable() as i64),
+                    self.delta,
+                    self.components_time.map(|dur| dur.as_millis() as u32)
                 ],
             )
             .expect("error inserting into main table");
@@ -195,10 +265,108 @@ impl Reporter {
                         .expect("failed to execute statement");
                     }
                 }
-            }
 
-            tx.commit().expect("error committing insertions");
+                if let Some(radius) = &self.radius {
+                    let mut stmt = tx
+                        .prepare(
+                            "INSERT INTO center_candidates (sha, node, eccentricity_lower)
+                            VALUES (?1, ?2, ?3)",
+                        )
+                        .expect("failed to prepare statement");
+
+                    for (lower, node) in radius.candidates.iter() {
+                        stmt.execute(params![sha, *node as i64, *lower as i64])
+                            .expect("failed to execute statement");
+                    }
+                }
+
+                if let Some(hyperball) = &self.hyperball {
+                    let mut stmt = tx.prepare(
+                    "INSERT INTO hyperball_repetitions (sha, repetition, diameter, effective_diameter, average_distance, harmonic_diameter)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                ).expect("failed to prepare statement");
+
+                    for (repetition, diameter) in hyperball.diameters.iter().enumerate() {
+                        let neighbourhood = hyperball.neighbourhoods.get(repetition);
+                        stmt.execute(params![
+                            sha,
+                            repetition as u32,
+                            diameter,
+                            neighbourhood.map(|n| n.effective_diameter(0.9)),
+                            neighbourhood.map(|n| n.average_distance()),
+                            neighbourhood.map(|n| n.harmonic_diameter())
+                        ])
+                        .expect("failed to execute statement");
+                    }
+
+                    let mut stmt = tx.prepare(
+                    "INSERT INTO neighbourhood_function (sha, repetition, distance, pairs, effective_diameter, average_distance, harmonic_diameter)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                ).expect("failed to prepare statement");
+
+                    // The statistics of each distance consider the pairs within it
+                    for (repetition, neighbourhood) in hyperball.neighbourhoods.iter().enumerate() {
+                        for (t, pairs) in neighbourhood.pairs.iter().enumerate() {
+                            let up_to = neighbourhood.up_to(t);
+                            stmt.execute(params![
+                                sha,
+                                repetition as u32,
+                                t as u32,
+                                pairs,
+                                up_to.effective_diameter(0.9),
+                                up_to.average_distance(),
+                                up_to.harmonic_diameter()
+                            ])
+                            .expect("failed to execute statement");
+                        }
+                    }
+                }
+
+                if let Some(components) = self.components.as_ref().filter(|c| !c.is_connected()) {
+                    let mut stmt = tx
+                        .prepare(
+                            "INSERT INTO components (sha, rank, root, size, diameter_lower, diameter_upper)
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        )
+                        .expect("failed to prepare statement");
+
+                    for (rank, component) in components.largest.iter().enumerate() {
+                        stmt.execute(params![
+                            sha,
+                            rank as u32,
+                            component.root as i64,
+                            component.size as i64,
+                            component.bounds.map(|bounds| bounds.lower as i64),
+                            component
+                                .bounds
+                                .and_then(|bounds| bounds.upper)
+                                .map(|upper| upper as i64)
+                        ])
+                        .expect("failed to execute statement");
+                    }
+                }
+
+                if !self.distance_answers.is_empty() {
+                    let mut stmt = tx
+                        .prepare(
+                            "INSERT INTO distance_queries (sha, source, target, distance)
+                            VALUES (?1, ?2, ?3, ?4)",
+                        )
+                        .expect("failed to prepare statement");
+
+                    for ((source, target), distance) in self.distance_answers.iter() {
+                        stmt.execute(params![
+                            sha,
+                            *source as i64,
+                            *target as i64,
+                            distance.map(|d| d as i64)
+                        ])
+                        .expect("failed to execute statement");
+                    }
+                }
+            }
         }
+        tx.commit().expect("error committing insertions");
         conn.close().expect("error inserting into the database");
     }
 }
@@ -335,5 +503,201 @@ fn create_tables_if_needed(conn: &Connection) {
         bump(conn, 6);
     }
 
+    if version < 7 {
+        info!("applying changes for version 7");
+
+        conn.execute(
+            "ALTER TABLE main ADD diameter_lower INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "ALTER TABLE main ADD diameter_upper INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "ALTER TABLE main ADD witness_u INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "ALTER TABLE main ADD witness_v INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+
+        bump(conn, 7);
+    }
+
+    if version < 8 {
+        info!("applying changes for version 8");
+
+        conn.execute(
+            "ALTER TABLE main ADD radius_lower INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "ALTER TABLE main ADD radius_upper INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "ALTER TABLE main ADD radius_center INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "ALTER TABLE main ADD num_center_candidates INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "CREATE TABLE center_candidates (
+                sha       TEXT NOT NULL,
+                node      INTEGER NOT NULL,
+                eccentricity_lower  INTEGER NOT NULL,
+                FOREIGN KEY (sha) REFERENCES main (sha)
+            )",
+            NO_PARAMS,
+        )
+        .expect("Error creating table center_candidates");
+
+        bump(conn, 8);
+    }
+
+    if version < 9 {
+        info!("applying changes for version 9");
+
+        conn.execute(
+            "CREATE TABLE neighbourhood_function (
+                sha       TEXT NOT NULL,
+                distance  INTEGER NOT NULL,
+                pairs     REAL NOT NULL,
+                effective_diameter  REAL NOT NULL,
+                average_distance    REAL NOT NULL,
+                harmonic_diameter   REAL NOT NULL,
+                FOREIGN KEY (sha) REFERENCES main (sha)
+            )",
+            NO_PARAMS,
+        )
+        .expect("Error creating table neighbourhood_function");
+
+        bump(conn, 9);
+    }
+
+    if version < 10 {
+        info!("applying changes for version 10");
+
+        conn.execute(
+            "CREATE TABLE hyperball_repetitions (
+                sha       TEXT NOT NULL,
+                repetition  INTEGER NOT NULL,
+                diameter    INTEGER NOT NULL,
+                effective_diameter  REAL DEFAULT NULL,
+                average_distance    REAL DEFAULT NULL,
+                harmonic_diameter   REAL DEFAULT NULL,
+                FOREIGN KEY (sha) REFERENCES main (sha)
+            )",
+            NO_PARAMS,
+        )
+        .expect("Error creating table hyperball_repetitions");
+
+        bump(conn, 10);
+    }
+
+    if version < 11 {
+        info!("applying changes for version 11");
+
+        conn.execute(
+            "CREATE TABLE distance_queries (
+                sha       TEXT NOT NULL,
+                source    INTEGER NOT NULL,
+                target    INTEGER NOT NULL,
+                distance  INTEGER DEFAULT NULL,
+                FOREIGN KEY (sha) REFERENCES main (sha)
+            )",
+            NO_PARAMS,
+        )
+        .expect("Error creating table distance_queries");
+
+        bump(conn, 11);
+    }
+
+    if version < 12 {
+        info!("applying changes for version 12");
+
+        conn.execute(
+            "ALTER TABLE main ADD num_components INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "ALTER TABLE main ADD unreachable_nodes INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+        conn.execute(
+            "CREATE TABLE components (
+                sha       TEXT NOT NULL,
+                rank      INTEGER NOT NULL,
+                root      INTEGER NOT NULL,
+                size      INTEGER NOT NULL,
+                diameter_lower  INTEGER DEFAULT NULL,
+                diameter_upper  INTEGER DEFAULT NULL,
+                FOREIGN KEY (sha) REFERENCES main (sha)
+            )",
+            NO_PARAMS,
+        )
+        .expect("Error creating table components");
+
+        bump(conn, 12);
+    }
+
+    if version < 13 {
+        info!("applying changes for version 13");
+
+        conn.execute("ALTER TABLE main ADD delta INT64 DEFAULT NULL", NO_PARAMS)
+            .expect("Error changing the table");
+
+        bump(conn, 13);
+    }
+
+    if version < 14 {
+        info!("applying changes for version 14");
+
+        conn.execute(
+            "ALTER TABLE main ADD components_time_ms INT64 DEFAULT NULL",
+            NO_PARAMS,
+        )
+        .expect("Error changing the table");
+
+        bump(conn, 14);
+    }
+
+    if version < 15 {
+        info!("applying changes for version 15");
+
+        // Databases that went through an earlier version 10 have it already
+        let has_repetition: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('neighbourhood_function')
+                    WHERE name == 'repetition'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .expect("Error inspecting the table");
+        if has_repetition == 0 {
+            conn.execute(
+                "ALTER TABLE neighbourhood_function ADD COLUMN repetition INTEGER NOT NULL DEFAULT 0",
+                NO_PARAMS,
+            )
+            .expect("Error changing the table");
+        }
+
+        bump(conn, 15);
+    }
+
     info!("database schema up tp date");
 }
@@ -1,58 +1,293 @@
-/// Compute an approximations to the diameter by means of runs of Dijkstra algorithm
-/// Returns a vector of approximations and pairs of nodes realizing them.
+use crate::bounds::DiameterBounds;
+use crate::distance::Distance;
+use crate::node::Node;
+
+/// Compute an approximations to the diameter by means of runs of Dijkstra algorithm:
+/// a double sweep from each unreached node, so that the upper bound is twice the
+/// largest eccentricity of the first sweeps of the components.
+/// Also returns a shortest path between the witness endpoints.
 /// Assumes that all vertices are in the range [0,n)
-pub fn approx_diameter<I: IntoIterator<Item = ((u32, u32), u32)>>(
+pub fn approx_diameter<D: Distance, I: IntoIterator<Item = ((Node, Node), D)>>(
     edges: I,
-    n: u32,
-) -> (Vec<(u32, (u32, u32))>, std::time::Duration) {
+    n: Node,
+) -> (DiameterBounds<D>, Vec<(Node, D)>, std::time::Duration) {
     use std::time::Instant;
 
     let neighbourhoods = init_neighbourhoods(edges, n);
     let mut reachable = vec![false; n as usize];
 
-    let mut distant_pairs = Vec::new();
+    let mut bounds = DiameterBounds::from_eccentricity(D::zero());
+    let mut path = Vec::new();
 
     let timer = Instant::now();
     for i in 0..n {
         if !reachable[i as usize] {
             // info!("starting sssp from {}", i);
-            let pair = sssp(&neighbourhoods, i, &mut reachable);
-            let v1 = (pair.1).1;
-            distant_pairs.push(pair);
-            distant_pairs.push(sssp(&neighbourhoods, v1, &mut reachable));
+            let (eccentricity, (_, v1), _) = sssp(&neighbourhoods, i, &mut reachable);
+            bounds.upper = std::cmp::max(bounds.upper, Some(eccentricity.saturating_times(2)));
+            let (lower, pair, sweep_path) = sssp(&neighbourhoods, v1, &mut reachable);
+            if lower >= bounds.lower {
+                bounds.lower = lower;
+                bounds.witness_pair = Some(pair);
+                path = sweep_path;
+            }
         }
     }
     let elapsed = timer.elapsed();
     info!("diameter computation: elapsed {:?}", timer.elapsed());
 
-    // distant_pairs.into_iter().max_by_key(|pair| pair.0).unwrap()
-    (distant_pairs, elapsed)
+    (bounds, path, elapsed)
+}
+
+pub fn single_pass<D: Distance, I: IntoIterator<Item = ((Node, Node), D)>>(
+    edges: I,
+    n: Node,
+) -> (DiameterBounds<D>, Vec<(Node, D)>, std::time::Duration) {
+    use std::time::Instant;
+
+    let neighbourhoods = init_neighbourhoods(edges, n);
+    let mut reachable = vec![false; n as usize];
+
+    let timer = Instant::now();
+    let (eccentricity, (source, farthest), path) = sssp(&neighbourhoods, 0, &mut reachable);
+    let bounds = DiameterBounds::from_sweep(eccentricity, source, farthest);
+    let elapsed = timer.elapsed();
+
+    (bounds, path, elapsed)
 }
 
-pub fn single_pass<I: IntoIterator<Item = ((u32, u32), u32)>>(
+/// Compute the exact diameter with the iFUB (iterative fringe upper bound)
+/// algorithm, run on each connected component starting from a 4-sweep.
+/// Also returns a shortest path between the witness endpoints.
+/// Assumes that all vertices are in the range [0,n)
+pub fn ifub_diameter<D: Distance, I: IntoIterator<Item = ((Node, Node), D)>>(
     edges: I,
-    n: u32,
-) -> (Vec<(u32, (u32, u32))>, std::time::Duration) {
+    n: Node,
+) -> (DiameterBounds<D>, Vec<(Node, D)>, std::time::Duration) {
     use std::time::Instant;
 
     let neighbourhoods = init_neighbourhoods(edges, n);
     let mut reachable = vec![false; n as usize];
 
-    let mut distant_pairs = Vec::new();
+    let mut diameter = (D::zero(), (0, 0), vec![(0, D::zero())]);
 
     let timer = Instant::now();
-    let pair = sssp(&neighbourhoods, 0, &mut reachable);
-    distant_pairs.push(pair);
+    for i in 0..n {
+        if !reachable[i as usize] {
+            let component = ifub(&neighbourhoods, i, &mut reachable);
+            if (component.0, component.1) >= (diameter.0, diameter.1) {
+                diameter = component;
+            }
+        }
+    }
     let elapsed = timer.elapsed();
+    info!("exact diameter computation: elapsed {:?}", elapsed);
 
-    (distant_pairs, elapsed)
+    let (diameter, witness_pair, path) = diameter;
+    (DiameterBounds::exact(diameter, witness_pair), path, elapsed)
+}
+
+/// Runs iFUB on the connected component of `start`, marking its nodes as
+/// reachable. Returns the diameter of the component, with the witness pair
+/// and a shortest path between them.
+fn ifub<D: Distance>(
+    adjs: &[Vec<(D, Node)>],
+    start: Node,
+    reachable: &mut [bool],
+) -> (D, (Node, Node), Vec<(Node, D)>) {
+    use std::time::{Duration, Instant};
+
+    let (distances, _) = dijkstra(adjs, start);
+    let component: Vec<Node> = (0..adjs.len())
+        .filter(|&x| distances[x].is_some())
+        .map(|x| x as Node)
+        .collect();
+    for &x in component.iter() {
+        reachable[x as usize] = true;
+    }
+    if component.len() == 1 {
+        return (D::zero(), (start, start), vec![(start, D::zero())]);
+    }
+
+    // 4-sweep: two double sweeps, the first from the node of highest degree
+    // and the second from the middle of the path found by the first one.
+    // All the eccentricities computed on the way are lower bounds.
+    let mut lower = D::zero();
+    let mut witness = (start, start);
+    let mut path = vec![(start, D::zero())];
+    let mut sweep = |source: Node| {
+        let (distances, parents) = dijkstra(adjs, source);
+        let (ecc, far) = farthest(&distances);
+        if ecc > lower {
+            lower = ecc;
+            witness = (source, far);
+            path = path_to(&distances, &parents, far);
+        }
+        (distances, parents, far)
+    };
+    let r1 = *component
+        .iter()
+        .max_by_key(|&&x| adjs[x as usize].len())
+        .expect("empty component");
+    let (_, _, a1) = sweep(r1);
+    let (distances, parents, b1) = sweep(a1);
+    let r2 = middle(&distances, &parents, b1);
+    let (_, _, a2) = sweep(r2);
+    let (distances, parents, b2) = sweep(a2);
+    let u = middle(&distances, &parents, b2);
+    let (distances, _, _) = sweep(u);
+    info!(
+        "iFUB: 4-sweep lower bound {}, upper bound {}",
+        lower,
+        farthest(&distances).0.saturating_times(2)
+    );
+
+    // The fringes of `u`, from the farthest one inwards. After visiting the
+    // fringes down to a level, the distance between any two of the remaining
+    // nodes is at most twice the distance of the next level from `u`
+    let mut by_distance: Vec<(D, Node)> = component
+        .iter()
+        .map(|&x| (distances[x as usize].expect("node in component"), x))
+        .collect();
+    by_distance.sort_unstable_by(|a, b| b.cmp(a));
+    let mut fringes: Vec<(D, Vec<Node>)> = Vec::new();
+    for (d, x) in by_distance {
+        match fringes.last_mut() {
+            Some((level, nodes)) if *level == d => nodes.push(x),
+            _ => fringes.push((d, vec![x])),
+        }
+    }
+
+    let mut last_log = Instant::now();
+    let mut visits = 0;
+    for (i, (level, nodes)) in fringes.iter().enumerate() {
+        if lower >= level.saturating_times(2) {
+            break;
+        }
+        let previous_lower = lower;
+        for &x in nodes.iter() {
+            let (distances, parents) = dijkstra(adjs, x);
+            let (ecc, far) = farthest(&distances);
+            if ecc > lower {
+                lower = ecc;
+                witness = (x, far);
+                path = path_to(&distances, &parents, far);
+            }
+        }
+        visits += nodes.len();
+        let upper = fringes
+            .get(i + 1)
+            .map(|(next, _)| std::cmp::max(lower, next.saturating_times(2)))
+            .unwrap_or(lower);
+        // Weighted graphs have many fringes, so keep the log readable
+        if lower > previous_lower || last_log.elapsed() > Duration::from_secs(1) {
+            info!(
+                "iFUB: lower bound {}, upper bound {} after {} visits",
+                lower, upper, visits
+            );
+            last_log = Instant::now();
+        }
+    }
+    info!(
+        "iFUB: diameter {} of component with {} nodes, after {} visits",
+        lower,
+        component.len(),
+        visits
+    );
+
+    (lower, witness, path)
+}
+
+/// The node on the path to `target` whose distance from the source is
+/// closest to half of the distance of `target`, from above.
+fn middle<D: Distance>(distances: &[Option<D>], parents: &[Node], target: Node) -> Node {
+    let total = distances[target as usize].expect("unreachable target");
+    let mut x = target;
+    loop {
+        let parent = parents[x as usize];
+        if parent == x
+            || distances[parent as usize]
+                .expect("unreachable parent")
+                .saturating_times(2)
+                < total
+        {
+            return x;
+        }
+        x = parent;
+    }
+}
+
+/// The nodes on the shortest path from the source to `target`, with their
+/// distances, following the parents up to the source
+fn path_to<D: Distance>(distances: &[Option<D>], parents: &[Node], target: Node) -> Vec<(Node, D)> {
+    let mut path = Vec::new();
+    let mut x = target;
+    loop {
+        path.push((x, distances[x as usize].expect("unreachable node on path")));
+        let parent = parents[x as usize];
+        if parent == x {
+            break;
+        }
+        x = parent;
+    }
+    path.reverse();
+    path
+}
+
+/// The largest distance and the node realizing it
+fn farthest<D: Distance>(distances: &[Option<D>]) -> (D, Node) {
+    let mut max_i = 0;
+    let mut max_dist = D::zero();
+    for (i, dist) in distances.iter().enumerate() {
+        if let Some(dist) = *dist {
+            if dist > max_dist {
+                max_dist = dist;
+                max_i = i;
+            }
+        }
+    }
+    (max_dist, max_i as Node)
+}
+
+/// Distances from the source, `None` for the unreachable nodes, and the
+/// parent of each node in the shortest paths tree (the source is its own parent)
+pub(crate) fn dijkstra<D: Distance>(
+    adjs: &[Vec<(D, Node)>],
+    source: Node,
+) -> (Vec<Option<D>>, Vec<Node>) {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    let n = adjs.len();
+    let mut distances: Vec<Option<D>> = vec![None; n];
+    let mut parents: Vec<Node> = (0..n).map(|x| x as Node).collect();
+    let mut pqueue = BinaryHeap::new();
+
+    pqueue.push(Reverse((D::zero(), source)));
+    distances[source as usize] = Some(D::zero());
+
+    while let Some(Reverse((dist, node))) = pqueue.pop() {
+        if Some(dist) > distances[node as usize] {
+            continue;
+        }
+        for &(weight, neigh) in adjs[node as usize].iter() {
+            let d = dist.plus(weight);
+            if distances[neigh as usize].is_none() || d < distances[neigh as usize].unwrap() {
+                distances[neigh as usize] = Some(d);
+                parents[neigh as usize] = node;
+                pqueue.push(Reverse((d, neigh)));
+            }
+        }
+    }
+
+    (distances, parents)
 }
 
 /// Build neighbourhoods, as vectors of (weight, id) pairs
-fn init_neighbourhoods<I: IntoIterator<Item = ((u32, u32), u32)>>(
+pub(crate) fn init_neighbourhoods<D: Distance, I: IntoIterator<Item = ((Node, Node), D)>>(
     edges: I,
-    n: u32,
-) -> Vec<Vec<(u32, u32)>> {
+    n: Node,
+) -> Vec<Vec<(D, Node)>> {
     let mut pl = progress_logger::ProgressLogger::builder()
         .with_items_name("edges")
         .start();
@@ -66,40 +301,44 @@ fn init_neighbourhoods<I: IntoIterator<Item = ((u32, u32), u32)>>(
     neighbourhoods
 }
 
-fn sssp(adjs: &Vec<Vec<(u32, u32)>>, source: u32, reachable: &mut Vec<bool>) -> (u32, (u32, u32)) {
+/// The eccentricity of the source, the pair of the source and the farthest
+/// node from it, and a shortest path between the two, marking the nodes
+/// reached on the way
+fn sssp<D: Distance>(
+    adjs: &Vec<Vec<(D, Node)>>,
+    source: Node,
+    reachable: &mut Vec<bool>,
+) -> (D, (Node, Node), Vec<(Node, D)>) {
     use std::cmp::Reverse;
     use std::collections::BinaryHeap;
 
     let n = adjs.len();
-    let mut distances: Vec<Option<u32>> = vec![None; n];
+    let mut distances: Vec<Option<D>> = vec![None; n];
+    let mut parents: Vec<Node> = (0..n).map(|x| x as Node).collect();
     let mut pqueue = BinaryHeap::new();
 
-    pqueue.push(Reverse((0, source)));
-    distances[source as usize] = Some(0);
+    pqueue.push(Reverse((D::zero(), source)));
+    distances[source as usize] = Some(D::zero());
     reachable[source as usize] = true;
 
     while let Some(Reverse((dist, node))) = pqueue.pop() {
         reachable[node as usize] = true;
         for &(weight, neigh) in adjs[node as usize].iter() {
-            let d = dist + weight;
+            let d = dist.plus(weight);
             if distances[neigh as usize].is_none() || d < distances[neigh as usize].unwrap() {
                 distances[neigh as usize] = Some(d);
+                parents[neigh as usize] = node;
                 pqueue.push(Reverse((d, neigh)));
             }
         }
     }
 
-    let mut max_i = 0;
-    let mut max_dist = 0;
-    for (i, dist) in distances.into_iter().enumerate() {
-        if let Some(dist) = dist {
-            if dist > max_dist {
-                max_dist = dist;
-                max_i = i;
-            }
-        }
-    }
-    (max_dist, (source, max_i as u32))
+    // An isolated source is the farthest node from itself
+    let (max_dist, far) = match farthest(&distances) {
+        (max_dist, _) if max_dist == D::zero() => (max_dist, source),
+        farthest => farthest,
+    };
+    (max_dist, (source, far), path_to(&distances, &parents, far))
 }
 
 // Floyd-Warshall algorithm
@@ -129,3 +368,36 @@ fn sssp(adjs: &Vec<Vec<(u32, u32)>>, source: u32, reachable: &mut Vec<bool>) ->
 
 //     cur
 // }
+
+#[test]
+fn test_ifub_diameter() {
+    // Two components: a weighted cycle with chords, and a path
+    let mut edges: Vec<((Node, Node), u32)> = (0..20)
+        .map(|i: u32| ((i as Node, (i as Node + 1) % 20), 1 + i % 3))
+        .collect();
+    edges.push(((0, 10), 4));
+    edges.push(((5, 15), 7));
+    edges.push(((20, 21), 3));
+    edges.push(((21, 22), 2));
+    let n = 23;
+
+    let neighbourhoods = init_neighbourhoods(edges.clone(), n);
+    let expected = (0..n)
+        .map(|x| farthest(&dijkstra(&neighbourhoods, x).0).0)
+        .max()
+        .unwrap();
+
+    let (bounds, path, _) = ifub_diameter(edges, n);
+    assert_eq!(bounds.lower, expected);
+    assert_eq!(bounds.upper, Some(expected));
+    let (u, v) = bounds.witness_pair.unwrap();
+    assert_eq!(dijkstra(&neighbourhoods, u).0[v as usize], Some(expected));
+
+    // The path goes from `u` to `v` along edges, adding up their weights
+    assert_eq!(path.first(), Some(&(u, 0)));
+    assert_eq!(path.last(), Some(&(v, expected)));
+    for pair in path.windows(2) {
+        let ((x, dx), (y, dy)) = (pair[0], pair[1]);
+        assert!(neighbourhoods[x as usize].contains(&(dy - dx, y)));
+    }
+}
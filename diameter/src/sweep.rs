@@ -0,0 +1,163 @@
+use crate::bfs::bfs_from;
+use crate::bounds::DiameterBounds;
+use crate::checkpoint::Checkpoints;
+use crate::delta_stepping::delta_stepping_from;
+use crate::distance::Distance;
+use crate::distributed_adjacencies::DistributedAdjacencies;
+use crate::eccentricities::*;
+use crate::node::Node;
+use crate::operators::random_root;
+use std::time::Duration;
+
+/// Chains `k` single source shortest paths computations, each from the farthest
+/// node of the previous one, starting from a random root: with `k = 2` this is
+/// the double sweep. The largest eccentricity found is a lower bound to the
+/// diameter, and twice the smallest one is an upper bound.
+///
+/// If all the edges have the same weight the sweeps are BFSs, otherwise they
+/// run delta-stepping with the given delta, as picked by `auto_delta`.
+///
+/// The bounds to the eccentricities of the nodes given by the distances from
+/// each of the roots are combined as well.
+pub fn sweep<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    k: u32,
+    n: Node,
+    weights: (u32, u32),
+    delta: u32,
+    seed: u64,
+    checkpoints: &Checkpoints,
+    eccentricities: &Eccentricities,
+) -> (Option<DiameterBounds<D>>, Duration) {
+    let root = random_root(&adjacencies, worker, n, seed);
+    let (bounds, node_bounds, elapsed) = sweep_from(
+        &adjacencies,
+        worker,
+        k,
+        root,
+        weights,
+        delta,
+        checkpoints,
+        eccentricities.is_enabled(),
+    );
+    eccentricities.save(&node_bounds);
+
+    (bounds, elapsed)
+}
+
+/// Chains `k` sweeps as `sweep` does, starting from the given root, so that
+/// the bounds hold for its connected component. The bounds to the
+/// eccentricities of the nodes of this worker are returned if asked to.
+pub fn sweep_from<D: Distance, A: timely::communication::Allocate>(
+    adjacencies: &DistributedAdjacencies,
+    worker: &mut timely::worker::Worker<A>,
+    k: u32,
+    mut root: Node,
+    (min_weight, max_weight): (u32, u32),
+    delta: u32,
+    checkpoints: &Checkpoints,
+    keep_distances: bool,
+) -> (Option<DiameterBounds<D>>, EccentricityBounds, Duration) {
+    let mut bounds: Option<DiameterBounds<D>> = None;
+    let mut elapsed = Duration::from_secs(0);
+    let mut node_bounds = EccentricityBounds::new();
+
+    for i in 0..k {
+        let (farthest, distances, sweep_elapsed) = if min_weight == max_weight {
+            let (farthest, distances, elapsed) =
+                bfs_from(adjacencies, worker, root, keep_distances);
+            let farthest = farthest.map(|(hops, far)| (D::from_u32(hops).times(max_weight), far));
+            let distances = distances
+                .into_iter()
+                .map(|(id, (hops, _parent))| (id, D::from_u32(hops).times(max_weight)))
+                .collect::<Vec<_>>();
+            (farthest, distances, elapsed)
+        } else {
+            let checkpoints = checkpoints.phase(&format!("sweep-{}", i));
+            let (farthest, distances, elapsed) = delta_stepping_from(
+                adjacencies,
+                worker,
+                delta,
+                root,
+                &checkpoints,
+                keep_distances,
+            );
+            let distances = distances
+                .into_iter()
+                .map(|(id, (distance, _parent))| (id, distance))
+                .collect::<Vec<_>>();
+            (farthest, distances, elapsed)
+        };
+        elapsed += sweep_elapsed;
+        let (eccentricity, far) = farthest.expect("missing farthest node");
+        info!(
+            "Sweep {}: eccentricity {} from {}, reaching {} ({:?})",
+            i, eccentricity, root, far, sweep_elapsed
+        );
+
+        for (id, distance) in distances {
+            node_bounds.refine_from_source(id, distance.to_u64(), eccentricity.to_u64());
+        }
+
+        let sweep_bounds = DiameterBounds::from_sweep(eccentricity, root, far);
+        bounds = Some(match bounds {
+            Some(bounds) => bounds.intersect(sweep_bounds),
+            None => sweep_bounds,
+        });
+        root = far;
+    }
+
+    (bounds, node_bounds, elapsed)
+}
+
+#[test]
+fn test_sweep_from_bounds() {
+    use crate::sequential::{dijkstra, init_neighbourhoods};
+
+    // A weighted cycle with chords, and the same graph with unit weights
+    let mut edges: Vec<((Node, Node), u32)> = (0..20)
+        .map(|i: u32| ((i as Node, (i as Node + 1) % 20), 1 + i % 3))
+        .collect();
+    edges.push(((0, 10), 4));
+    edges.push(((5, 15), 7));
+    let unweighted: Vec<((Node, Node), u32)> = edges.iter().map(|&(e, _w)| (e, 2)).collect();
+    let n = 20;
+
+    timely::execute_directly(move |worker| {
+        let checkpoints = Checkpoints::new(&std::env::temp_dir(), "none", 0, None, false);
+        for (edges, weights) in vec![(edges, (1, 3)), (unweighted, (2, 2))] {
+            let neighbourhoods = init_neighbourhoods(edges.clone(), n);
+            let distances: Vec<Vec<Option<u32>>> =
+                (0..n).map(|x| dijkstra(&neighbourhoods, x).0).collect();
+            let eccentricity = |x: Node| distances[x as usize].iter().flatten().max().copied();
+            let diameter = (0..n).filter_map(eccentricity).max().unwrap();
+
+            let adjacencies = DistributedAdjacencies::from_edge_list(0, 1, n, &edges);
+            for &k in &[1, 2, 4] {
+                let (bounds, node_bounds, _) = sweep_from::<u32, _>(
+                    &adjacencies,
+                    worker,
+                    k,
+                    3,
+                    weights,
+                    2,
+                    &checkpoints,
+                    true,
+                );
+                let bounds = bounds.expect("missing bounds");
+                assert!(bounds.lower <= diameter);
+                assert!(bounds.upper.expect("missing upper bound") >= diameter);
+                let (u, v) = bounds.witness_pair.expect("missing witness pair");
+                assert_eq!(distances[u as usize][v as usize], Some(bounds.lower));
+
+                // The bounds of all the nodes hold
+                assert_eq!(node_bounds.iter().count(), n as usize);
+                for (id, (lower, upper)) in node_bounds.iter() {
+                    let eccentricity = eccentricity(id).unwrap() as u64;
+                    assert!(lower <= eccentricity && eccentricity <= upper);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,372 @@
+use crate::checkpoint::*;
+use crate::distance::Weight;
+use crate::distributed_adjacencies::*;
+use crate::node::Node;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::generic::operator::Operator;
+use timely::dataflow::operators::*;
+use timely::dataflow::Scope;
+use timely::dataflow::Stream;
+use timely::order::Product;
+use timely::progress::Timestamp;
+use timely::ExchangeData;
+
+/// A graph algorithm expressed from the point of view of a single vertex.
+///
+/// In every round each node that `should_send` emits a message along each
+/// of its edges, messages directed to the same node are combined, and then
+/// every node updates its state with the combined message it received (if any).
+/// The computation is driven by `run_until_quiescent`, which keeps on
+/// iterating as long as some node is active. Only active nodes take part
+/// in a round: nodes that are not active and get no message are left untouched.
+///
+/// The program is parameterized by the timestamp of the scope it runs in,
+/// so that implementations can look at the round (or at the rounds of the
+/// enclosing loops) they are executed in, and by the type of the weights
+/// of the edges it sends messages along.
+pub trait VertexProgram<T: Timestamp, W: Weight = u32>: Clone + 'static {
+    type State: ExchangeData + Default;
+    type Message: ExchangeData;
+
+    /// Whether nodes that are not part of the input, but that receive a message,
+    /// should be created with the default state.
+    fn with_default(&self) -> bool {
+        true
+    }
+
+    /// Whether the given node has something to say in this round
+    fn should_send(&self, time: &T, state: &Self::State) -> bool;
+
+    /// The message sent by node `id` along an edge of the given weight.
+    fn message(&self, time: &T, id: Node, state: &Self::State, weight: W) -> Option<Self::Message>;
+
+    /// Combines two messages directed to the same node.
+    fn combine(msg1: &Self::Message, msg2: &Self::Message) -> Self::Message;
+
+    /// Updates the state of a node with the message it received, if any.
+    fn apply(&self, time: &T, state: &Self::State, message: Option<&Self::Message>) -> Self::State;
+
+    /// The halting condition: the iteration goes on as long as there is at
+    /// least one active node.
+    fn is_active(&self, time: &T, state: &Self::State) -> bool;
+}
+
+/// Runs the given vertex program in a new iterative scope, starting from
+/// the given node states, until no node is active. Returns the final states.
+///
+/// The states are kept in dense arrays local to each worker, and only
+/// the active nodes (the frontier) and the messages they send go around
+/// the loop, so that each round costs time proportional to the frontier,
+/// rather than to the number of nodes. For this to be correct, applying
+/// the program to an inactive node with no message must leave it unchanged.
+pub fn run_until_quiescent<G, W, P>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, (Node, P::State)>,
+    program: P,
+) -> Stream<G, (Node, P::State)>
+where
+    G: Scope,
+    W: Weight,
+    P: VertexProgram<Product<G::Timestamp, u32>, W>,
+{
+    drive(adjacencies, nodes, program, 0, None, None)
+}
+
+/// Like `run_until_quiescent`, but also calls `observe` with the round, the
+/// identifier and the state of each node that is still active at the end
+/// of it, before the next round starts.
+pub fn run_until_quiescent_observing<G, W, P, F>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, (Node, P::State)>,
+    program: P,
+    observe: F,
+) -> Stream<G, (Node, P::State)>
+where
+    G: Scope,
+    W: Weight,
+    P: VertexProgram<Product<G::Timestamp, u32>, W>,
+    F: Fn(u32, Node, &P::State) + 'static,
+{
+    drive(
+        adjacencies,
+        nodes,
+        program,
+        0,
+        None,
+        Some(Box::new(observe)),
+    )
+}
+
+/// Like `run_until_quiescent`, but on states kept in the given array rather
+/// than flowing in the stream, which lets them persist across the times of
+/// the enclosing scope, as long as these come one after the other. Starts
+/// from the active ones among the given nodes, which must be on the worker
+/// owning them, and returns these nodes along with all the ones touched by
+/// the iteration, once each, at the end of it.
+pub fn run_until_quiescent_in<G, W, P>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, Node>,
+    states: Rc<RefCell<LocalStates<P::State>>>,
+    program: P,
+) -> Stream<G, Node>
+where
+    G: Scope,
+    W: Weight,
+    P: VertexProgram<Product<G::Timestamp, u32>, W>,
+{
+    let loader_states = Rc::clone(&states);
+    let loader = program.clone();
+    let mut touched_stash = adjacencies.round_buffers::<G::Timestamp, ()>();
+
+    let touched = nodes.scope().iterative::<u32, _, _>(|subscope| {
+        let (handle, cycle) = subscope.feedback(Product::new(Default::default(), 1));
+
+        let initial_frontier =
+            nodes
+                .enter(subscope)
+                .unary(Pipeline, "active nodes", move |_, _| {
+                    move |input, output| {
+                        input.for_each(|t, data| {
+                            let data = data.replace(Vec::new());
+                            let mut session = output.session(&t);
+                            let states = loader_states.borrow();
+                            for id in data.into_iter() {
+                                let state = states.get(id).expect("missing state");
+                                if loader.is_active(t.time(), state) {
+                                    session.give(id);
+                                }
+                            }
+                        })
+                    }
+                });
+
+        let (inactive, active) = adjacencies
+            .send_frontier(&initial_frontier.concat(&cycle), states, program)
+            .branch(|_t, (_id, active)| *active);
+        active.map(|(id, _active)| id).connect_loop(handle);
+
+        // Every touched node ends up inactive
+        inactive.map(|(id, _active)| id).leave()
+    });
+
+    nodes.binary_notify(
+        &touched,
+        Pipeline,
+        Pipeline,
+        "collect touched",
+        None,
+        move |node_input, touched_input, output, notificator| {
+            notificator.for_each(|t, _, _| {
+                let mut touched = touched_stash.remove(t.time());
+                output
+                    .session(&t)
+                    .give_iterator(touched.drain().map(|(id, ())| id));
+                touched_stash.recycle(touched);
+            });
+            node_input.for_each(|t, data| {
+                let stash = touched_stash.entry(t.time());
+                for id in data.replace(Vec::new()).into_iter() {
+                    stash.insert(id, ());
+                }
+                notificator.notify_at(t.retain());
+            });
+            touched_input.for_each(|t, data| {
+                let stash = touched_stash.entry(t.time());
+                for id in data.replace(Vec::new()).into_iter() {
+                    stash.insert(id, ());
+                }
+                notificator.notify_at(t.retain());
+            });
+        },
+    )
+}
+
+/// Like `run_until_quiescent`, but saves the states of all the nodes of
+/// each worker at the checkpoint rounds. When resuming from a checkpoint,
+/// the given nodes should be the ones saved at round `resume`, and the
+/// iteration starts over from the following round.
+pub fn run_until_quiescent_checkpointed<G, W, P>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, (Node, P::State)>,
+    program: P,
+    checkpoints: &Checkpoints,
+    resume: Option<u32>,
+) -> Stream<G, (Node, P::State)>
+where
+    G: Scope,
+    W: Weight,
+    P: VertexProgram<Product<G::Timestamp, u32>, W>,
+    P::State: Serialize,
+{
+    checkpointed(adjacencies, nodes, program, checkpoints, resume, None)
+}
+
+/// Like `run_until_quiescent_checkpointed`, but also calls `observe` with
+/// the round, the identifier and the state of each node that is still
+/// active at the end of it, before the next round starts.
+pub fn run_until_quiescent_observed<G, W, P, F>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, (Node, P::State)>,
+    program: P,
+    checkpoints: &Checkpoints,
+    resume: Option<u32>,
+    observe: F,
+) -> Stream<G, (Node, P::State)>
+where
+    G: Scope,
+    W: Weight,
+    P: VertexProgram<Product<G::Timestamp, u32>, W>,
+    P::State: Serialize,
+    F: Fn(u32, Node, &P::State) + 'static,
+{
+    checkpointed(
+        adjacencies,
+        nodes,
+        program,
+        checkpoints,
+        resume,
+        Some(Box::new(observe)),
+    )
+}
+
+fn checkpointed<G, W, P>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, (Node, P::State)>,
+    program: P,
+    checkpoints: &Checkpoints,
+    resume: Option<u32>,
+    observe: Option<ObserveState<P::State>>,
+) -> Stream<G, (Node, P::State)>
+where
+    G: Scope,
+    W: Weight,
+    P: VertexProgram<Product<G::Timestamp, u32>, W>,
+    P::State: Serialize,
+{
+    let start = resume.map(|round| round + 1).unwrap_or(0);
+    let saver = checkpoints.clone();
+    let save: SaveStates<P::State> = Box::new(move |round, states| {
+        saver.save(round, states.iter(), std::iter::empty());
+    });
+    if checkpoints.is_enabled() {
+        drive(
+            adjacencies,
+            nodes,
+            program,
+            start,
+            Some((checkpoints.clone(), save)),
+            observe,
+        )
+    } else {
+        drive(adjacencies, nodes, program, start, None, observe)
+    }
+}
+
+type SaveStates<S> = Box<dyn Fn(u32, &LocalStates<S>)>;
+type ObserveState<S> = Box<dyn Fn(u32, Node, &S)>;
+
+fn drive<G, W, P>(
+    adjacencies: &DistributedAdjacencies<W>,
+    nodes: &Stream<G, (Node, P::State)>,
+    program: P,
+    start: u32,
+    checkpoint: Option<(Checkpoints, SaveStates<P::State>)>,
+    observe: Option<ObserveState<P::State>>,
+) -> Stream<G, (Node, P::State)>
+where
+    G: Scope,
+    W: Weight,
+    P: VertexProgram<Product<G::Timestamp, u32>, W>,
+{
+    let states: SharedStates<G::Timestamp, P::State> = Rc::new(RefCell::new(HashMap::new()));
+    let loader_states = Rc::clone(&states);
+    let collector_states = Rc::clone(&states);
+    let allocator = DistributedAdjacencies::clone(adjacencies);
+    let loader = program.clone();
+    let saved_states = Rc::clone(&states);
+    let observed_states = Rc::clone(&states);
+
+    let touched = nodes.scope().iterative::<u32, _, _>(|subscope| {
+        let (handle, cycle) = subscope.feedback(Product::new(Default::default(), 1));
+
+        // Store the states locally, and start from the active nodes
+        let initial_frontier =
+            nodes
+                .enter_at(subscope, move |_| start)
+                .unary(Pipeline, "load states", move |_, _| {
+                    move |input, output| {
+                        input.for_each(|t, data| {
+                            let data = data.replace(Vec::new());
+                            let mut session = output.session(&t);
+                            let mut states = loader_states.borrow_mut();
+                            let local_states = states
+                                .entry(t.time().outer.clone())
+                                .or_insert_with(|| allocator.local_states());
+                            for (id, state) in data.into_iter() {
+                                if loader.is_active(t.time(), &state) {
+                                    session.give(id);
+                                }
+                                local_states.insert(id, state);
+                            }
+                        })
+                    }
+                });
+
+        let (inactive, active) = adjacencies
+            .send_frontier(&initial_frontier.concat(&cycle), states, program)
+            .branch(|_t, (_id, active)| *active);
+
+        let active = active.map(|(id, _active)| id);
+        let active = match observe {
+            // Inline, so that the states are looked at before the next round changes them
+            Some(observe) => active.inspect_batch(move |t, ids| {
+                let states = observed_states.borrow();
+                let local_states = states.get(&t.outer).expect("missing states");
+                for id in ids.iter() {
+                    observe(t.inner, *id, local_states.get(*id).expect("missing state"));
+                }
+            }),
+            None => active,
+        };
+        let active = match checkpoint {
+            // The states of a round are saved before the next round starts
+            Some((checkpoints, save)) => {
+                hold_at_checkpoints(&active, &inactive, &checkpoints, move |t, _ids| {
+                    let states = saved_states.borrow();
+                    let local_states = states.get(&t.outer).expect("missing states");
+                    save(t.inner, local_states);
+                })
+            }
+            None => active,
+        };
+        active.connect_loop(handle);
+
+        inactive.leave()
+    });
+
+    // Once the iteration is over, output the final states
+    nodes.binary_notify(
+        &touched,
+        Pipeline,
+        Pipeline,
+        "collect states",
+        None,
+        move |node_input, touched_input, output, notificator| {
+            node_input.for_each(|t, _data| {
+                notificator.notify_at(t.retain());
+            });
+            touched_input.for_each(|t, _data| {
+                notificator.notify_at(t.retain());
+            });
+            notificator.for_each(|t, _, _| {
+                if let Some(local_states) = collector_states.borrow_mut().remove(t.time()) {
+                    output.session(&t).give_iterator(local_states.into_states());
+                }
+            });
+        },
+    )
+}
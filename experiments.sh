@@ -157,6 +157,30 @@ function run_scalability_n() {
 
 }
 
+function run_async_roads() {
+  for SEED in 13381 2350982 5089735 135 12346
+  do
+    for DATASET in USA USA-x5 USA-x10
+    do
+        $BIN \
+            --ddir $GRAPHS_DIR\
+            --hosts ~/diameter-hosts \
+            --threads 8 \
+            --seed $SEED \
+            "delta-stepping(auto)" \
+            $DATASET
+
+        $BIN \
+            --ddir $GRAPHS_DIR\
+            --hosts ~/diameter-hosts \
+            --threads 8 \
+            --seed $SEED \
+            "async-sssp" \
+            $DATASET
+    done
+  done
+}
+
 case $1 in
     roads)
         run_roads
@@ -173,4 +197,7 @@ case $1 in
     scalability_n)
         run_scalability_n
     ;;
+    async_roads)
+        run_async_roads
+    ;;
 esac